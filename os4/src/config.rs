@@ -2,10 +2,43 @@ pub const USER_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_STACK_SIZE: usize = 4096 * 2;
 pub const KERNEL_HEAP_SIZE: usize = 0x30_0000;
 pub const MEMORY_END: usize = 0x88000000;
+// QEMU virt 平台上串口（16550）寄存器的物理地址
+pub const UART_BASE: usize = 0x1000_0000;
 pub const PAGE_SIZE: usize = 0x1000;
 pub const PAGE_SIZE_BITS: usize = 0xc;
 pub const MAX_SYSCALL_NUM: usize = 500;
+// 任务数的上限。内核栈按进程号依次排在跳板之下，不小于它的位置留给测试中临时创建的任务控制块，
+// 这样测试用的内核栈不会与任何任务冲突
+pub const MAX_TASK_NUM: usize = 32;
 
+// 地址空间布局随机化，打开后用户栈和未指定地址的mmap区域会随机偏移至多 ASLR_MAX_PAGES 页
+// 默认关闭以保证测试结果确定
+pub const ASLR_ENABLED: bool = false;
+pub const ASLR_MAX_PAGES: usize = 256;
+// 未指定地址的mmap从这里开始查找空闲区域
+pub const MMAP_BASE: usize = 0x1000_0000;
+// 位置无关（ET_DYN）的应用整体加载到这个偏移之上
+pub const PIE_LOAD_BIAS: usize = 0x2000_0000;
+// 调试用的W^X模式：Framed逻辑段中可执行的页面一律不可写，写入时报告“写可执行页面”
+pub const WX_ENFORCE: bool = false;
+// mmap是否延迟到第一次访问时才分配页帧
+pub const LAZY_MMAP: bool = false;
+// 应用BSS中不含文件数据的页面是否先映射到共享零页，第一次写时才分配页帧
+pub const LAZY_BSS: bool = false;
+// 内核栈是否延迟分配页帧，栈顶一页总是立即分配
+pub const LAZY_KERNEL_STACK: bool = false;
+// 任务退出时是否用 info! 记录一行资源使用汇总（pid、应用名、退出码、峰值映射页数、系统调用次数、用户/内核时间、缺页次数）
+// 默认关闭，避免改变测例检查的输出
+pub const EXIT_SUMMARY: bool = false;
+// 启动时传给应用的额外命令行参数，排在应用名字（argv[0]）之后；没有列出的应用只收到自己的名字
+pub const APP_ARGS: &[(&str, &[&str])] = &[("ch4_args", &["hello", "rCore"])];
+// 分块读取应用数据（loader::app_reader）时每块的大小
+pub const APP_CHUNK_SIZE: usize = 0x1000;
+// 地址空间大小限制的默认值，表示不限制
+pub const RLIM_INFINITY: usize = usize::MAX;
+
+// 跳板和trap上下文的虚拟地址，默认放在虚拟地址空间最顶部的两页。可以改成别的布局做实验，
+// 启动时 mm::init 会检查它们页对齐、不重叠、是SV39的合法地址且不与内核的恒等映射冲突
 pub const TRAMPOLINE: usize = usize::MAX - PAGE_SIZE + 1;
 pub const TRAP_CONTEXT: usize = TRAMPOLINE - PAGE_SIZE;
 /// Return (bottom, top) of a kernel stack in kernel space.
@@ -17,3 +50,27 @@ pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
 }
 
 pub const CLOCK_FREQ: usize = 12500000;
+
+// 控制台行缓冲区的大小
+pub const CONSOLE_BUFFER_SIZE: usize = 128;
+
+// 调度策略：时间片轮转，或最早截止时间优先（没有截止时间的任务排在最后，彼此之间轮转），
+// 或者stride调度（每次选行程最小的任务，被调度后行程增加 BIG_STRIDE / 有效优先级）
+#[derive(PartialEq)]
+pub enum SchedPolicy {
+    RoundRobin,
+    Edf,
+    Stride,
+}
+pub const SCHED_POLICY: SchedPolicy = SchedPolicy::RoundRobin;
+
+// stride调度的参数：每次调度行程增加 BIG_STRIDE / 优先级，优先级不能小于2
+pub const BIG_STRIDE: usize = 0x10000;
+pub const DEFAULT_PRIORITY: usize = 16;
+
+// 就绪任务等待超过这么久（us）还没被调度时，看门狗打印可能饿死的警告
+pub const STARVATION_THRESHOLD_US: usize = 1_000_000;
+
+// 采样分析：每隔多少个时钟中断记录一次被打断的pc，以及直方图每个桶覆盖的字节数（2的幂）
+pub const PROFILE_SAMPLE_INTERVAL: usize = 4;
+pub const PROFILE_BUCKET_SIZE: usize = 256;
@@ -1,14 +1,84 @@
 /*！
     本模块实现了 print 和 println 宏
+    可以打开行缓冲模式：字节先积累在缓冲区里，遇到换行或写满时一次性输出，减少SBI调用
 */
 
-use crate::sbi::console_putchar;
+use crate::config::CONSOLE_BUFFER_SIZE;
+use crate::sbi::{console_putchar, console_write, console_write_supported};
 use core::fmt::{self, Write};
+use spin::Mutex;
+
+// 行缓冲区，遇到换行或写满时把积累的字节整体交给 out 输出
+pub struct LineBuffer {
+    buf: [u8; CONSOLE_BUFFER_SIZE],
+    len: usize,
+}
+
+impl LineBuffer {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; CONSOLE_BUFFER_SIZE],
+            len: 0,
+        }
+    }
+    pub fn write(&mut self, bytes: &[u8], out: &mut impl FnMut(&[u8])) {
+        for byte in bytes {
+            self.buf[self.len] = *byte;
+            self.len += 1;
+            if *byte == b'\n' || self.len == CONSOLE_BUFFER_SIZE {
+                self.flush(out);
+            }
+        }
+    }
+    pub fn flush(&mut self, out: &mut impl FnMut(&[u8])) {
+        if self.len > 0 {
+            out(&self.buf[..self.len]);
+            self.len = 0;
+        }
+    }
+}
+
+struct Console {
+    buffered: bool, // 是否处于行缓冲模式
+    dbcn: Option<bool>, // SBI是否支持一次写出一段字节，第一次输出时探测
+    line: LineBuffer,
+}
+
+// 缓冲区放在静态变量里，处于恒等映射区域，可以直接把地址交给SBI
+static CONSOLE: Mutex<Console> = Mutex::new(Console {
+    buffered: false,
+    dbcn: None,
+    line: LineBuffer::new(),
+});
+
+// 输出一段字节：支持DBCN时用一次SBI调用写出，否则逐字节输出
+fn write_bytes(dbcn: &mut Option<bool>, bytes: &[u8]) {
+    let mut rest = bytes;
+    if *dbcn.get_or_insert_with(console_write_supported) {
+        while !rest.is_empty() {
+            match console_write(rest) {
+                Some(written) if written > 0 => rest = &rest[written..],
+                _ => break,
+            }
+        }
+    }
+    for byte in rest {
+        console_putchar(*byte as usize);
+    }
+}
 
 struct Stdout;
 
 impl Write for Stdout {
     fn write_str(&mut self, s: &str) -> fmt::Result {
+        // 拿不到锁说明正在输出时发生了panic，直接输出，避免死锁
+        if let Some(mut console) = CONSOLE.try_lock() {
+            if console.buffered {
+                let Console { dbcn, line, .. } = &mut *console;
+                line.write(s.as_bytes(), &mut |bytes| write_bytes(dbcn, bytes));
+                return Ok(());
+            }
+        }
         for c in s.chars() {
             console_putchar(c as usize);
         }
@@ -20,6 +90,24 @@ pub fn print(args: fmt::Arguments) {
     Stdout.write_fmt(args).unwrap();
 }
 
+// 打开或关闭行缓冲模式，关闭时先输出缓冲区中剩余的内容
+pub fn set_buffered(enable: bool) {
+    let mut console = CONSOLE.lock();
+    if !enable {
+        let Console { dbcn, line, .. } = &mut *console;
+        line.flush(&mut |bytes| write_bytes(dbcn, bytes));
+    }
+    console.buffered = enable;
+}
+
+// 输出缓冲区中剩余的内容，panic 时也会调用，所以拿不到锁时直接放弃
+pub fn flush() {
+    if let Some(mut console) = CONSOLE.try_lock() {
+        let Console { dbcn, line, .. } = &mut *console;
+        line.flush(&mut |bytes| write_bytes(dbcn, bytes));
+    }
+}
+
 #[macro_export]
 macro_rules! print {
     ($fmt: literal $(, $($arg: tt)+)?) => {
@@ -33,3 +121,30 @@ macro_rules! println {
         $crate::console::print(format_args!(concat!($fmt, "\n") $(, $($arg)+)?));
     }
 }
+
+#[allow(unused)]
+// 测试行缓冲：输出内容与写入的一致，且输出次数（对应SBI调用）远少于逐字节输出
+pub fn line_buffer_test() {
+    use alloc::vec::Vec;
+    let mut input = Vec::new();
+    for i in 0..20 {
+        input.extend_from_slice(b"the quick brown fox jumps over the lazy dog ");
+        if i % 5 == 4 {
+            input.push(b'\n');
+        }
+    }
+    let mut output = Vec::new();
+    let mut calls = 0;
+    let mut line = LineBuffer::new();
+    let mut out = |bytes: &[u8]| {
+        calls += 1;
+        output.extend_from_slice(bytes);
+    };
+    for chunk in input.chunks(7) {
+        line.write(chunk, &mut out);
+    }
+    line.flush(&mut out);
+    assert_eq!(output, input);
+    assert!(calls * 10 < input.len());
+    info!("line_buffer_test passed!");
+}
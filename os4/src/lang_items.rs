@@ -3,6 +3,8 @@ use core::panic::PanicInfo;
 
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
+    // 先把缓冲区里还没输出的内容输出，保证panic前的日志完整
+    crate::console::flush();
     if let Some(location) = info.location() {
         println!(
             "Panicked at {}:{} {}",
@@ -13,5 +15,6 @@ fn panic(info: &PanicInfo) -> ! {
     } else {
         println!("[kernel] Panicked: {}", info.message().unwrap());
     }
+    crate::console::flush();
     shutdown()
 }
@@ -1,3 +1,10 @@
+use crate::config::{APP_ARGS, APP_CHUNK_SIZE};
+use crate::sync::UPSafeCell;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use lazy_static::*;
+
 // 获取链接到内核内的应用的数目
 pub fn get_num_app() -> usize {
     // 从app的链接脚本link_app.S中获取符号，也就是通过build脚本构建的那个
@@ -7,6 +14,86 @@ pub fn get_num_app() -> usize {
     unsafe { (_num_app as usize as *const usize).read_volatile() }
 }
 
+lazy_static! {
+    // 链接进内核的应用的名字，由build脚本以0结尾的字符串依次放在_app_names处
+    static ref APP_NAMES: Vec<&'static str> = {
+        let num_app = get_num_app();
+        extern "C" {
+            fn _app_names();
+        }
+        let mut start = _app_names as usize as *const u8;
+        let mut v = Vec::new();
+        unsafe {
+            for _ in 0..num_app {
+                let mut end = start;
+                while end.read_volatile() != b'\0' {
+                    end = end.add(1);
+                }
+                let slice = core::slice::from_raw_parts(start, end as usize - start as usize);
+                let str = core::str::from_utf8(slice).unwrap();
+                v.push(str);
+                start = end.add(1);
+            }
+        }
+        v
+    };
+    // 运行时注册的应用表，ELF数据放在内核堆上，编号接在链接进内核的应用之后
+    static ref DYNAMIC_APPS: UPSafeCell<Vec<(&'static str, &'static [u8])>> =
+        unsafe { UPSafeCell::new(Vec::new()) };
+    // 下一个可分配的进程号，只增不减，进程号不会被重复使用
+    static ref NEXT_PID: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
+}
+
+// 为新建的任务分配进程号，按创建顺序从0开始，0号是最先创建的初始任务
+pub fn alloc_pid() -> usize {
+    let mut next_pid = NEXT_PID.exclusive_access();
+    let pid = *next_pid;
+    *next_pid += 1;
+    pid
+}
+
+// 在运行时注册一个应用，返回它的应用编号
+pub fn register_app(name: &str, elf_data: Vec<u8>) -> usize {
+    let mut apps = DYNAMIC_APPS.exclusive_access();
+    // 注册的应用不会被注销，直接泄漏成静态生命周期，与链接进内核的应用数据保持一致
+    let name: &'static str = Box::leak(String::from(name).into_boxed_str());
+    apps.push((name, elf_data.leak()));
+    get_num_app() + apps.len() - 1
+}
+
+// 按名字查找应用，返回应用编号
+pub fn get_app_by_name(name: &str) -> Option<usize> {
+    if let Some(app_id) = APP_NAMES.iter().position(|app_name| *app_name == name) {
+        return Some(app_id);
+    }
+    DYNAMIC_APPS
+        .exclusive_access()
+        .iter()
+        .position(|(app_name, _)| *app_name == name)
+        .map(|idx| get_num_app() + idx)
+}
+
+// 根据应用编号取出应用的名字
+pub fn get_app_name(app_id: usize) -> &'static str {
+    let num_app = get_num_app();
+    if app_id < num_app {
+        APP_NAMES[app_id]
+    } else {
+        DYNAMIC_APPS.exclusive_access()[app_id - num_app].0
+    }
+}
+
+// 应用启动时的命令行参数：第一个是应用的名字，后面是 config::APP_ARGS 中为它配置的参数
+pub fn get_app_args(app_id: usize) -> Vec<&'static str> {
+    let name = get_app_name(app_id);
+    let mut args = Vec::new();
+    args.push(name);
+    if let Some((_, extra)) = APP_ARGS.iter().find(|(app, _)| *app == name) {
+        args.extend_from_slice(extra);
+    }
+    args
+}
+
 // 根据传入的应用编号取出对应应用的 ELF 格式可执行文件数据。
 pub fn get_app_data(app_id: usize) -> &'static [u8] {
     extern "C" {
@@ -14,8 +101,13 @@ pub fn get_app_data(app_id: usize) -> &'static [u8] {
     }
     let num_app_ptr = _num_app as usize as *const usize;
     let num_app = get_num_app();
+    // 编号超出链接进内核的应用时，到运行时注册的应用表中查找
+    if app_id >= num_app {
+        let apps = DYNAMIC_APPS.exclusive_access();
+        assert!(app_id - num_app < apps.len());
+        return apps[app_id - num_app].1;
+    }
     let app_start = unsafe { core::slice::from_raw_parts(num_app_ptr.add(1), num_app + 1) };
-    assert!(app_id < num_app);
     unsafe {
         // 利用link_app.S中已经放置好的符号，用app_id取出应用数据装到数组里
         core::slice::from_raw_parts(
@@ -24,3 +116,69 @@ pub fn get_app_data(app_id: usize) -> &'static [u8] {
         )
     }
 }
+
+// 按顺序分块读取应用的 ELF 数据，MemorySet::from_elf_reader 不要求整个应用连续存放。
+// 目前应用都是连续嵌入的，按固定大小切块；以后分段或压缩嵌入时只需要改这里
+pub fn app_reader(app_id: usize) -> impl Iterator<Item = &'static [u8]> {
+    get_app_data(app_id).chunks(APP_CHUNK_SIZE)
+}
+
+#[allow(unused)]
+// 测试运行时注册应用
+pub fn register_app_test() {
+    use crate::config::MAX_TASK_NUM;
+    use crate::mm::{translated_ref, translated_str};
+    use crate::task::TaskControlBlock;
+    let elf_data = get_app_data(0);
+    let app_id = register_app("copy_of_app_0", elf_data.to_vec());
+    assert!(app_id >= get_num_app());
+    assert_eq!(get_app_by_name("copy_of_app_0"), Some(app_id));
+    assert_eq!(get_app_data(app_id), elf_data);
+    assert_eq!(get_app_by_name("no_such_app"), None);
+    // 用返回的编号真正建出任务：入口与原应用相同，argv[0]是注册的名字。
+    // 用 MAX_TASK_NUM 之后的内核栈位置，不会与任务以及 task.rs 中的测试冲突
+    let args = get_app_args(app_id);
+    assert_eq!(args, ["copy_of_app_0"]);
+    let mut tcb = TaskControlBlock::new(get_app_data(app_id), MAX_TASK_NUM + 5, Some(&args));
+    let mut original = TaskControlBlock::new(elf_data, MAX_TASK_NUM + 6, None);
+    assert_eq!(tcb.get_trap_cx().sepc, original.get_trap_cx().sepc);
+    let token = tcb.get_user_token();
+    let argv0 = *translated_ref(token, tcb.get_trap_cx().x[11] as *const usize);
+    assert_eq!(translated_str(token, argv0 as *const u8), "copy_of_app_0");
+    tcb.run_exit_hooks();
+    original.run_exit_hooks();
+    info!("register_app_test passed!");
+}
+
+#[allow(unused)]
+// 测试分块读取应用：各块依次拼起来与 get_app_data 相同，按分块载入的地址空间与整块载入的内容一致
+pub fn app_reader_test() {
+    use crate::mm::{MemorySet, VirtAddr};
+    for app_id in 0..get_num_app() {
+        let elf_data = get_app_data(app_id);
+        let mut bytes: Vec<u8> = Vec::new();
+        for chunk in app_reader(app_id) {
+            assert!(!chunk.is_empty() && chunk.len() <= APP_CHUNK_SIZE);
+            bytes.extend_from_slice(chunk);
+        }
+        assert_eq!(bytes.as_slice(), elf_data);
+    }
+    let (whole, whole_sp, whole_entry) = MemorySet::from_elf(get_app_data(0));
+    let (chunked, chunked_sp, chunked_entry) = MemorySet::from_elf_reader(app_reader(0));
+    assert_eq!((whole_sp, whole_entry), (chunked_sp, chunked_entry));
+    assert_eq!(whole.mapped_bytes(), chunked.mapped_bytes());
+    let entry_vpn = VirtAddr::from(whole_entry).floor();
+    assert_eq!(
+        whole.translate(entry_vpn).unwrap().ppn().get_bytes_array(),
+        chunked.translate(entry_vpn).unwrap().ppn().get_bytes_array()
+    );
+    // 块的边界不必与页对齐
+    let odd_chunks = get_app_data(0).chunks(1000);
+    let (odd, _, odd_entry) = MemorySet::from_elf_reader(odd_chunks);
+    assert_eq!(odd_entry, whole_entry);
+    assert_eq!(
+        whole.translate(entry_vpn).unwrap().ppn().get_bytes_array(),
+        odd.translate(entry_vpn).unwrap().ppn().get_bytes_array()
+    );
+    info!("app_reader_test passed!");
+}
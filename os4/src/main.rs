@@ -21,6 +21,7 @@ mod lang_items;
 mod loader;
 mod logging;
 mod mm;
+mod random;
 mod sbi;
 mod sync;
 mod syscall;
@@ -46,6 +47,55 @@ fn clear_bss() {
     }
 }
 
+// 依次运行内存管理之外各模块的单元测试，失败时直接panic
+// 任务相关的测试会临时改动任务管理器里的状态并在结束时恢复，所以必须在第一个任务运行之前调用；
+// 睡眠的测试要靠时钟中断把 wfi 唤醒，所以要在打开时钟中断之后调用
+#[cfg(feature = "boot_test")]
+fn boot_test() {
+    let unit_tests: &[fn()] = &[
+        console::line_buffer_test,
+        loader::register_app_test,
+        loader::app_reader_test,
+        sync::rwlock_test,
+        task::current_task_test,
+        task::schedule_test,
+        task::parent_link_test,
+        task::pid_test,
+        task::reparent_test,
+        task::affinity_test,
+        task::sleep_test,
+        task::sched_latency_test,
+        task::first_running_time_test,
+        task::blocked_time_test,
+        task::priority_boost_test,
+        task::edf_test,
+        task::starvation_watchdog_test,
+        task::trap_cx_mapping_test,
+        task::exit_hooks_test,
+        task::lazy_kernel_stack_test,
+        task::exit_summary_test,
+        task::argv_test,
+        task::profile_test,
+        task::fatal_signal_test,
+        task::vmstat_test,
+        trap::sepc_advance_test,
+        trap::single_step_test,
+        trap::step_targets_test,
+        syscall::dispatch_test,
+        syscall::syscall_times_test,
+        syscall::sys_write_test,
+        syscall::process::mmap_pid_test,
+        syscall::process::kill_test,
+        syscall::process::setuid_test,
+        syscall::process::sys_ps_test,
+        syscall::process::dontneed_buffer_test,
+    ];
+    for test in unit_tests {
+        test();
+    }
+    info!("boot test: {} unit tests passed", unit_tests.len());
+}
+
 #[no_mangle]
 pub fn rust_main() -> ! {
     clear_bss();
@@ -56,12 +106,30 @@ pub fn rust_main() -> ! {
     println!("[kernel] back to world!");
     // 新增, 检查内核地址空间的多级页表是否被正确设置
     mm::remap_test();
+    // 内存耗尽压力测试，只在打开 stress_oom feature 时运行，结束后直接关机
+    #[cfg(feature = "stress_oom")]
+    mm::stress_oom();
+    // 内存管理子系统整体自检，只在打开 mm_self_test feature 时运行
+    #[cfg(feature = "mm_self_test")]
+    assert!(mm::self_test().is_ok(), "mm self test failed");
     // 设置stvec寄存器指向panic。这样在内核中发生trap会panic
     trap::init();
+    // 系统调用路径的微基准，只在打开 ecall_bench_test feature 时运行
+    #[cfg(feature = "ecall_bench_test")]
+    trap::ecall_bench_test();
+    // SUM 访问测试，只在打开 sum_test feature 时运行
+    #[cfg(feature = "sum_test")]
+    trap::sum_test();
+    // 中断状态检查测试，只在打开 sie_check_test feature 时运行
+    #[cfg(feature = "sie_check_test")]
+    trap::sie_check_test();
     // 通过 sie 寄存器中的 seie 位，对中断信号是否接收进行控制。设置为接受
     trap::enable_timer_interrupt();
     // 设置mtimecmp寄存器为10ms后触发中断
     timer::set_next_trigger();
+    // 内存管理之外各模块的单元测试，只在打开 boot_test feature 时运行
+    #[cfg(feature = "boot_test")]
+    boot_test();
     // 启动第一个任务,构造好任务上下文和trap上下文并触发还原
     task::run_first_task();
     panic!("Unreachable in rust_main!");
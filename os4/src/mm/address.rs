@@ -2,8 +2,25 @@
 
 use super::PageTableEntry;
 use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use alloc::format;
+use alloc::string::String;
 use core::fmt::{self, Debug, Formatter};
 
+// SV39 的各个位宽：56位物理地址、39位虚拟地址，三级页表每级用9位虚拟页号做索引
+pub const PA_WIDTH_SV39: usize = 56;
+pub const VA_WIDTH_SV39: usize = 39;
+pub const PPN_WIDTH_SV39: usize = PA_WIDTH_SV39 - PAGE_SIZE_BITS;
+pub const VPN_WIDTH_SV39: usize = VA_WIDTH_SV39 - PAGE_SIZE_BITS;
+pub const PAGE_INDEX_BITS: usize = 9;
+
+// 编译期检查上面的位宽和config中的页大小彼此一致，改错任何一个都会编译失败
+const _: () = assert!(1 << PAGE_SIZE_BITS == PAGE_SIZE);
+const _: () = assert!(PAGE_SIZE_BITS == 12, "SV39 uses 4KiB pages");
+const _: () = assert!(PPN_WIDTH_SV39 == 44);
+const _: () = assert!(VPN_WIDTH_SV39 == 3 * PAGE_INDEX_BITS);
+// 一个页表节点恰好占一页
+const _: () = assert!((1 << PAGE_INDEX_BITS) * core::mem::size_of::<PageTableEntry>() == PAGE_SIZE);
+
 // 物理地址，包装了usize
 #[derive(Copy, Clone, Ord, PartialOrd, Eq, PartialEq)]
 pub struct PhysAddr(pub usize);
@@ -87,20 +104,56 @@ impl From<VirtPageNum> for usize {
 }
 
 
+// 向上对齐到align的整数倍，align必须是2的幂
+pub fn align_up(addr: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "align {:#x} is not a power of two", align);
+    (addr + align - 1) & !(align - 1)
+}
+
+// 向下对齐到align的整数倍，align必须是2的幂
+pub fn align_down(addr: usize, align: usize) -> usize {
+    assert!(align.is_power_of_two(), "align {:#x} is not a power of two", align);
+    addr & !(align - 1)
+}
+
+// 检查配置中要求页对齐的地址（如 TRAP_CONTEXT、TRAMPOLINE）并转为虚拟页号，没有对齐时返回带名字的说明
+pub fn check_page_aligned(addr: usize, name: &str) -> Result<VirtPageNum, String> {
+    let va = VirtAddr::from(addr);
+    if va.aligned() {
+        Ok(va.floor())
+    } else {
+        Err(format!(
+            "{} = {:#x} is not page aligned (offset {:#x}), check config.rs",
+            name,
+            addr,
+            va.page_offset()
+        ))
+    }
+}
+
+// 同 check_page_aligned，没有对齐时直接panic，报错信息里指明是哪个配置项，而不是地址转换里的通用断言
+pub fn expect_page_aligned(addr: usize, name: &str) -> VirtPageNum {
+    check_page_aligned(addr, name).unwrap_or_else(|msg| panic!("{}", msg))
+}
+
 // 虚拟地址相关实现
 impl VirtAddr {
     pub fn floor(&self) -> VirtPageNum {
-        VirtPageNum(self.0 / PAGE_SIZE)
+        VirtPageNum(align_down(self.0, PAGE_SIZE) >> PAGE_SIZE_BITS)
     }
     pub fn ceil(&self) -> VirtPageNum {
-        VirtPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
+        VirtPageNum(align_up(self.0, PAGE_SIZE) >> PAGE_SIZE_BITS)
     }
     pub fn page_offset(&self) -> usize {
-        self.0 & (PAGE_SIZE - 1)
+        self.0 - align_down(self.0, PAGE_SIZE)
     }
     pub fn aligned(&self) -> bool {
         self.page_offset() == 0
     }
+    // 加上一个长度，溢出时返回None，用于检查用户传入的起始地址和长度
+    pub fn checked_add(&self, rhs: usize) -> Option<VirtAddr> {
+        self.0.checked_add(rhs).map(VirtAddr)
+    }
 }
 // 地址与页号互转
 impl From<VirtAddr> for VirtPageNum {
@@ -123,19 +176,30 @@ impl From<VirtPageNum> for VirtAddr {
 impl PhysAddr {
     // 下取整
     pub fn floor(&self) -> PhysPageNum {
-        PhysPageNum(self.0 / PAGE_SIZE)
+        PhysPageNum(align_down(self.0, PAGE_SIZE) >> PAGE_SIZE_BITS)
     }
     // 上取整
     pub fn ceil(&self) -> PhysPageNum {
-        PhysPageNum((self.0 - 1 + PAGE_SIZE) / PAGE_SIZE)
+        PhysPageNum(align_up(self.0, PAGE_SIZE) >> PAGE_SIZE_BITS)
     }
     // 偏移
     pub fn page_offset(&self) -> usize {
-        self.0 & (PAGE_SIZE - 1)
+        self.0 - align_down(self.0, PAGE_SIZE)
     }
     pub fn aligned(&self) -> bool {
         self.page_offset() == 0
     }
+    // 加上一个长度，溢出时返回None
+    pub fn checked_add(&self, rhs: usize) -> Option<PhysAddr> {
+        self.0.checked_add(rhs).map(PhysAddr)
+    }
+    // 获取放在这个物理地址上的类型为 T 的数据的引用，内核中物理内存是恒等映射的
+    pub fn get_ref<T>(&self) -> &'static T {
+        unsafe { (self.0 as *const T).as_ref().unwrap() }
+    }
+    pub fn get_mut<T>(&self) -> &'static mut T {
+        unsafe { (self.0 as *mut T).as_mut().unwrap() }
+    }
 }
 // 只有对其的情况下可以自动转
 impl From<PhysAddr> for PhysPageNum {
@@ -157,8 +221,8 @@ impl VirtPageNum {
         let mut vpn = self.0;
         let mut idx = [0usize; 3];
         for i in (0..3).rev() {
-            idx[i] = vpn & 511;
-            vpn >>= 9;
+            idx[i] = vpn & ((1 << PAGE_INDEX_BITS) - 1);
+            vpn >>= PAGE_INDEX_BITS;
         }
         idx
     }
@@ -195,11 +259,24 @@ impl PhysPageNum {
 
 pub trait StepByOne {
     fn step(&mut self);
+    // 从self走到end需要的步数，要求self不大于end
+    fn distance(&self, end: &Self) -> usize;
 }
 impl StepByOne for VirtPageNum {
     fn step(&mut self) {
         self.0 += 1;
     }
+    fn distance(&self, end: &Self) -> usize {
+        end.0 - self.0
+    }
+}
+impl StepByOne for PhysPageNum {
+    fn step(&mut self) {
+        self.0 += 1;
+    }
+    fn distance(&self, end: &Self) -> usize {
+        end.0 - self.0
+    }
 }
 
 #[derive(Copy, Clone)]
@@ -225,6 +302,13 @@ where
     pub fn get_end(&self) -> T {
         self.r
     }
+    // 区间包含的元素个数，对于 VPNRange 就是页数
+    pub fn len(&self) -> usize {
+        self.l.distance(&self.r)
+    }
+    pub fn is_empty(&self) -> bool {
+        self.l == self.r
+    }
 }
 impl<T> IntoIterator for SimpleRange<T>
 where
@@ -266,7 +350,104 @@ where
             Some(t)
         }
     }
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.current.distance(&self.end);
+        (len, Some(len))
+    }
+}
+impl<T> ExactSizeIterator for SimpleRangeIterator<T> where
+    T: StepByOne + Copy + PartialEq + PartialOrd + Debug
+{
 }
 
 /// a simple range structure for virtual page number
 pub type VPNRange = SimpleRange<VirtPageNum>;
+/// a simple range structure for physical page number
+pub type PPNRange = SimpleRange<PhysPageNum>;
+
+#[allow(unused)]
+// 测试对齐函数：边界、零以及非页大小的对齐
+pub fn align_test() {
+    assert_eq!(align_up(0, PAGE_SIZE), 0);
+    assert_eq!(align_down(0, PAGE_SIZE), 0);
+    assert_eq!(align_up(PAGE_SIZE, PAGE_SIZE), PAGE_SIZE);
+    assert_eq!(align_down(PAGE_SIZE, PAGE_SIZE), PAGE_SIZE);
+    assert_eq!(align_up(PAGE_SIZE + 1, PAGE_SIZE), 2 * PAGE_SIZE);
+    assert_eq!(align_down(2 * PAGE_SIZE - 1, PAGE_SIZE), PAGE_SIZE);
+    assert_eq!(align_up(1, 16), 16);
+    assert_eq!(align_up(17, 16), 32);
+    assert_eq!(align_down(31, 16), 16);
+    assert_eq!(align_up(0x20_0001, 0x20_0000), 0x40_0000);
+    assert_eq!(align_down(0x3f_ffff, 0x20_0000), 0x20_0000);
+    assert_eq!(align_up(0x7, 1), 0x7);
+    // 取整在零地址处不再下溢
+    assert_eq!(VirtAddr(0).ceil().0, 0);
+    assert_eq!(VirtAddr(PAGE_SIZE + 1).ceil().0, 2);
+    assert_eq!(PhysAddr(2 * PAGE_SIZE - 1).floor().0, 1);
+    assert_eq!(VirtAddr(PAGE_SIZE + 0x123).page_offset(), 0x123);
+    info!("align_test passed!");
+}
+
+#[allow(unused)]
+// 测试页号区间的长度，以及迭代过程中剩余长度随之减少
+pub fn vpn_range_len_test() {
+    let range = VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x14));
+    assert_eq!(range.len(), 4);
+    assert!(!range.is_empty());
+    assert!(VPNRange::new(VirtPageNum(0x10), VirtPageNum(0x10)).is_empty());
+    let mut iter = range.into_iter();
+    assert_eq!(iter.len(), 4);
+    iter.next();
+    assert_eq!(iter.len(), 3);
+    assert_eq!(iter.count(), 3);
+    info!("vpn_range_len_test passed!");
+}
+
+#[allow(unused)]
+// 测试按物理页号区间遍历连续的页帧
+pub fn ppn_range_test() {
+    use alloc::vec::Vec;
+    let range = PPNRange::new(PhysPageNum(0x80400), PhysPageNum(0x80403));
+    assert_eq!(range.len(), 3);
+    let ppns: Vec<usize> = range.into_iter().map(|ppn| ppn.0).collect();
+    assert_eq!(ppns, [0x80400, 0x80401, 0x80402]);
+    info!("ppn_range_test passed!");
+}
+
+#[allow(unused)]
+// SV39 布局的检查都在编译期完成（见文件开头的 const 断言，改错配置会直接编译失败），
+// 这里确认按这些位宽拆出的页表索引和地址转换在边界上是对的
+pub fn sv39_layout_test() {
+    let max_vpn = VirtPageNum((1 << VPN_WIDTH_SV39) - 1);
+    assert_eq!(max_vpn.indexes(), [(1 << PAGE_INDEX_BITS) - 1; 3]);
+    assert_eq!(VirtPageNum(1 << PAGE_INDEX_BITS).indexes(), [0, 1, 0]);
+    let max_ppn = PhysPageNum((1 << PPN_WIDTH_SV39) - 1);
+    assert_eq!(PhysAddr::from(max_ppn).0, (1 << PA_WIDTH_SV39) - PAGE_SIZE);
+    info!("sv39_layout_test passed!");
+}
+
+#[allow(unused)]
+// 测试没有页对齐的配置地址给出指明配置项的报错
+pub fn page_aligned_config_test() {
+    use crate::config::TRAP_CONTEXT;
+    assert_eq!(
+        check_page_aligned(TRAP_CONTEXT, "TRAP_CONTEXT"),
+        Ok(VirtAddr::from(TRAP_CONTEXT).floor())
+    );
+    let msg = check_page_aligned(TRAP_CONTEXT + 8, "TRAP_CONTEXT").unwrap_err();
+    assert!(msg.starts_with("TRAP_CONTEXT = "));
+    assert!(msg.contains("not page aligned (offset 0x8)"));
+    info!("page_aligned_config_test passed!");
+}
+
+#[allow(unused)]
+// 测试地址的溢出检查加法：正常相加得到和，在usize::MAX附近溢出时返回None
+pub fn checked_add_test() {
+    assert_eq!(VirtAddr::from(0x1000).checked_add(0x234), Some(VirtAddr::from(0x1234)));
+    assert_eq!(VirtAddr::from(usize::MAX).checked_add(0), Some(VirtAddr::from(usize::MAX)));
+    assert_eq!(VirtAddr::from(usize::MAX).checked_add(1), None);
+    assert_eq!(VirtAddr::from(PAGE_SIZE).checked_add(usize::MAX), None);
+    assert_eq!(PhysAddr::from(0x8000_0000).checked_add(PAGE_SIZE), Some(PhysAddr::from(0x8000_1000)));
+    assert_eq!(PhysAddr::from(usize::MAX).checked_add(1), None);
+    info!("checked_add_test passed!");
+}
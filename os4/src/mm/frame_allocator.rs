@@ -1,8 +1,9 @@
 // 物理页帧模块，控制操作系统中所有的物理页帧
 
-use super::{PhysAddr, PhysPageNum};
-use crate::config::MEMORY_END;
+use super::{align_up, PhysAddr, PhysPageNum};
+use crate::config::{MEMORY_END, PAGE_SIZE};
 use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use core::fmt::{self, Debug, Formatter};
 use lazy_static::*;
@@ -15,13 +16,19 @@ pub struct FrameTracker {
 // 实现物理页帧的初始化
 impl FrameTracker {
     pub fn new(ppn: PhysPageNum) -> Self {
-        // 清零页帧，从页帧号获取地址作为一个数组
-        let bytes_array = ppn.get_bytes_array();
-        for i in bytes_array {
-            *i = 0;
+        // 清零页帧，按64位字整页写零，比逐字节清零快
+        let pa: PhysAddr = ppn.into();
+        unsafe {
+            core::ptr::write_bytes(pa.0 as *mut u64, 0, PAGE_SIZE / core::mem::size_of::<u64>());
         }
         Self { ppn }
     }
+    // 与其它地址空间共享同一个物理页帧（写时复制），不清零，引用计数加一
+    pub fn share(&self) -> Self {
+        let mut refcount = FRAME_REFCOUNT.exclusive_access();
+        *refcount.entry(self.ppn.0).or_insert(1) += 1;
+        Self { ppn: self.ppn }
+    }
 }
 
 // 打印
@@ -31,13 +38,59 @@ impl Debug for FrameTracker {
     }
 }
 
-// 自动释放
+// 自动释放，被共享的页帧只有最后一个持有者释放时才真正回收
 impl Drop for FrameTracker {
     fn drop(&mut self) {
+        let mut refcount = FRAME_REFCOUNT.exclusive_access();
+        if let Some(count) = refcount.get_mut(&self.ppn.0) {
+            *count -= 1;
+            if *count == 1 {
+                refcount.remove(&self.ppn.0);
+            }
+            return;
+        }
+        drop(refcount);
         frame_dealloc(self.ppn);
     }
 }
 
+// 大页（2MiB）包含的页帧数
+pub const HUGE_FRAME_PAGES: usize = 512;
+
+// 2MiB 对齐、连续512个页帧的大页，封装为RAII资源，释放时归还全部页帧
+pub struct HugeFrameTracker {
+    pub ppn: PhysPageNum, // 起始页帧号
+}
+
+impl HugeFrameTracker {
+    pub fn new(ppn: PhysPageNum) -> Self {
+        let pa: PhysAddr = ppn.into();
+        unsafe {
+            core::ptr::write_bytes(
+                pa.0 as *mut u64,
+                0,
+                HUGE_FRAME_PAGES * PAGE_SIZE / core::mem::size_of::<u64>(),
+            );
+        }
+        Self { ppn }
+    }
+}
+
+impl Debug for HugeFrameTracker {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_fmt(format_args!("HugeFrameTracker:PPN={:#x}", self.ppn.0))
+    }
+}
+
+impl Drop for HugeFrameTracker {
+    fn drop(&mut self) {
+        let mut allocator = FRAME_ALLOCATOR.exclusive_access();
+        for ppn in self.ppn.0..self.ppn.0 + HUGE_FRAME_PAGES {
+            allocator.dealloc(ppn.into());
+        }
+    }
+}
+
 // 物理页帧分配器
 trait FrameAllocator {
     fn new() -> Self;
@@ -46,22 +99,115 @@ trait FrameAllocator {
 }
 
 
+// 页帧分配器的统计数据，用于排查页帧泄漏
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameStats {
+    pub total: usize, // 分配器管理的页帧总数
+    pub allocated: usize, // 已分配出去的页帧数
+    pub free: usize, // 空闲页帧数，与 frame_remain_num 相同
+    pub recycled: usize, // 空闲页帧中在回收列表里的个数
+}
+
+// 分配和回收页帧时的回调，参数为页帧号以及是否为分配（true分配，false回收）
+// 回调在分配器被借用时执行，不能在其中再分配或回收页帧
+pub type FrameHook = fn(PhysPageNum, bool);
+
 // 栈式物理页帧分配器
 pub struct StackFrameAllocator {
+    start: usize, // 管理的第一个页号
     current: usize, // 未分配的初始页号
     end: usize, // 未分配的结束页号
     recycled: Vec<usize>, // 回收到的页号
+    hook: Option<FrameHook>, // 分配与回收事件的回调，默认没有
 }
 
 // 初始化物理页帧分配器
 impl StackFrameAllocator {
     pub fn init(&mut self, l: PhysPageNum, r: PhysPageNum) {
+        self.start = l.0;
         self.current = l.0;
         self.end = r.0;
     }
+    // 设置分配与回收事件的回调，None表示取消
+    pub fn set_hook(&mut self, hook: Option<FrameHook>) {
+        self.hook = hook;
+    }
+    fn notify(&self, ppn: usize, alloc: bool) {
+        if let Some(hook) = self.hook {
+            hook(ppn.into(), alloc);
+        }
+    }
     pub fn remain_num(&self) -> usize {
         self.end - self.current + self.recycled.len()
     }
+    // 分配器的统计数据，预留出去的页帧不再计入
+    pub fn stats(&self) -> FrameStats {
+        let total = self.end - self.start;
+        let free = self.remain_num();
+        FrameStats {
+            total,
+            allocated: total - free,
+            free,
+            recycled: self.recycled.len(),
+        }
+    }
+    // 页帧是否已被分配出去
+    pub fn is_allocated(&self, ppn: PhysPageNum) -> bool {
+        ppn.0 < self.current && !self.recycled.contains(&ppn.0)
+    }
+    // 从尚未分配的 current..end 区间中找一段按512页对齐的连续页帧，返回起始页号
+    // 回收列表里的页号是零散的，不在其中查找；为对齐而跳过的页帧放入回收列表，仍可被普通分配使用
+    pub fn alloc_huge(&mut self) -> Option<PhysPageNum> {
+        let base = align_up(self.current, HUGE_FRAME_PAGES);
+        if base + HUGE_FRAME_PAGES > self.end {
+            return None;
+        }
+        self.recycled.extend(self.current..base);
+        self.current = base + HUGE_FRAME_PAGES;
+        for ppn in base..base + HUGE_FRAME_PAGES {
+            self.notify(ppn, true);
+        }
+        Some(base.into())
+    }
+    // 从尚未分配的尾部区间的末尾划出count个连续页帧，此后不再参与普通分配，返回起始页号
+    pub fn reserve(&mut self, count: usize) -> Option<PhysPageNum> {
+        if count == 0 || self.end - self.current < count {
+            return None;
+        }
+        self.end -= count;
+        Some(self.end.into())
+    }
+    // 撤销最近一次预留，只有紧挨着尾部区间的那段才能还回去
+    pub fn unreserve(&mut self, base: PhysPageNum, count: usize) {
+        assert_eq!(base.0, self.end, "only the latest reservation can be released");
+        self.end += count;
+    }
+    // 计算最长的连续空闲页帧数，考虑回收到的页号和尚未分配的 current..end 尾部区间
+    pub fn largest_free_run(&self) -> usize {
+        let mut free = self.recycled.clone();
+        free.sort_unstable();
+        let mut largest = 0;
+        let mut run = 0;
+        let mut last: Option<usize> = None;
+        for ppn in free {
+            run = match last {
+                Some(l) if l + 1 == ppn => run + 1,
+                _ => 1,
+            };
+            largest = largest.max(run);
+            last = Some(ppn);
+        }
+        // 回收的页号都小于current，若最后一段恰好紧挨着current，就能和尾部区间连起来
+        let tail = self.end - self.current;
+        if tail > 0 {
+            let joined = match last {
+                Some(l) if l + 1 == self.current => run + tail,
+                _ => tail,
+            };
+            largest = largest.max(joined);
+        }
+        largest
+    }
 }
 
 // 为其实现物理页帧分配器特性
@@ -69,21 +215,25 @@ impl FrameAllocator for StackFrameAllocator {
     // 新创建
     fn new() -> Self {
         Self {
+            start: 0,
             current: 0,
             end: 0,
             recycled: Vec::new(),
+            hook: None,
         }
     }
     // 分配页帧
     fn alloc(&mut self) -> Option<PhysPageNum> {
-        if let Some(ppn) = self.recycled.pop() {
-            Some(ppn.into())
+        let ppn = if let Some(ppn) = self.recycled.pop() {
+            ppn
         } else if self.current == self.end {
-            None
+            return None;
         } else {
             self.current += 1;
-            Some((self.current - 1).into())
-        }
+            self.current - 1
+        };
+        self.notify(ppn, true);
+        Some(ppn.into())
     }
     // 回收页帧
     fn dealloc(&mut self, ppn: PhysPageNum) {
@@ -94,6 +244,7 @@ impl FrameAllocator for StackFrameAllocator {
         }
         // recycle
         self.recycled.push(ppn);
+        self.notify(ppn, false);
     }
 }
 
@@ -103,6 +254,11 @@ lazy_static! {
     // 创建全局变量物理页帧分配器
     pub static ref FRAME_ALLOCATOR: UPSafeCell<FrameAllocatorImpl> =
         unsafe { UPSafeCell::new(FrameAllocatorImpl::new()) };
+    // 被多个地址空间共享的页帧的引用计数，只记录计数不小于2的页帧，其余页帧的计数隐含为1
+    static ref FRAME_REFCOUNT: UPSafeCell<BTreeMap<usize, usize>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+    // 全局共享的只读零页，延迟分配的匿名页面和BSS在第一次写之前都映射到它
+    static ref ZERO_FRAME: FrameTracker = frame_alloc().unwrap();
 }
 
 // 因为内核代码和堆已经占据一部分位置了
@@ -120,6 +276,21 @@ pub fn init_frame_allocator() {
     );
 }
 
+// 分配全局零页，在页帧分配器初始化之后调用
+pub fn init_zero_frame() {
+    lazy_static::initialize(&ZERO_FRAME);
+}
+
+// 全局零页的页帧号
+pub fn zero_frame() -> PhysPageNum {
+    ZERO_FRAME.ppn
+}
+
+// 再持有一份全局零页，引用计数加一，最后一个持有者释放时零页也不会被回收
+pub fn share_zero_frame() -> FrameTracker {
+    ZERO_FRAME.share()
+}
+
 // 申请物理页帧的接口
 pub fn frame_alloc() -> Option<FrameTracker> {
     FRAME_ALLOCATOR
@@ -133,10 +304,65 @@ fn frame_dealloc(ppn: PhysPageNum) {
     FRAME_ALLOCATOR.exclusive_access().dealloc(ppn);
 }
 
+// 预留一段连续的物理页帧（例如给设备DMA用），预留的页帧不会被普通分配使用，也不会被回收
+pub fn frame_reserve(count: usize) -> Option<PhysPageNum> {
+    FRAME_ALLOCATOR.exclusive_access().reserve(count)
+}
+
+// 撤销最近一次 frame_reserve 的预留，用于预留之后映射失败的情形
+pub fn frame_unreserve(base: PhysPageNum, count: usize) {
+    FRAME_ALLOCATOR.exclusive_access().unreserve(base, count);
+}
+
+// 申请一个2MiB大页
+pub fn frame_alloc_huge() -> Option<HugeFrameTracker> {
+    FRAME_ALLOCATOR
+        .exclusive_access()
+        .alloc_huge()
+        .map(HugeFrameTracker::new)
+}
+
+// 设置全局页帧分配器的事件回调，可以用来记录分配轨迹
+pub fn set_frame_hook(hook: Option<FrameHook>) {
+    FRAME_ALLOCATOR.exclusive_access().set_hook(hook);
+}
+
+// 空闲的页帧数，即 frame_allocator_stats().free
 pub fn frame_remain_num() -> usize {
     FRAME_ALLOCATOR.exclusive_access().remain_num()
 }
 
+// 全局页帧分配器的统计数据
+pub fn frame_allocator_stats() -> FrameStats {
+    FRAME_ALLOCATOR.exclusive_access().stats()
+}
+
+// 查询页帧的共享引用计数，未分配的页帧为0
+pub fn cow_refcount(ppn: PhysPageNum) -> usize {
+    if let Some(count) = FRAME_REFCOUNT.exclusive_access().get(&ppn.0) {
+        return *count;
+    }
+    if FRAME_ALLOCATOR.exclusive_access().is_allocated(ppn) {
+        1
+    } else {
+        0
+    }
+}
+
+// 打印所有被共享的页帧及其引用计数
+pub fn dump_cow_frames() {
+    let refcount = FRAME_REFCOUNT.exclusive_access();
+    info!("{} shared frames", refcount.len());
+    for (ppn, count) in refcount.iter() {
+        info!("PPN:{:#x} refcount = {}", ppn, count);
+    }
+}
+
+// 最长的连续空闲页帧数，用于判断连续分配能否成功
+pub fn largest_free_run() -> usize {
+    FRAME_ALLOCATOR.exclusive_access().largest_free_run()
+}
+
 
 #[allow(unused)]
 // 测试
@@ -156,3 +382,141 @@ pub fn frame_allocator_test() {
     drop(v);
     info!("frame_allocator_test passed!");
 }
+
+#[allow(unused)]
+// 测试页帧随 FrameTracker 的析构被回收：剩余页帧数先减少分配的个数再完全恢复，
+// 立即重新分配时按后进先出的顺序复用刚释放的页帧
+pub fn frame_reclaim_test() {
+    const COUNT: usize = 16;
+    let remain_before = frame_remain_num();
+    let v: Vec<FrameTracker> = (0..COUNT).map(|_| frame_alloc().unwrap()).collect();
+    assert_eq!(frame_remain_num(), remain_before - COUNT);
+    let ppns: Vec<PhysPageNum> = v.iter().map(|frame| frame.ppn).collect();
+    // Vec按下标顺序析构元素，最后一个被释放的页帧最先被复用
+    drop(v);
+    assert_eq!(frame_remain_num(), remain_before);
+    let v: Vec<FrameTracker> = (0..COUNT).map(|_| frame_alloc().unwrap()).collect();
+    for (frame, ppn) in v.iter().zip(ppns.iter().rev()) {
+        assert_eq!(frame.ppn, *ppn);
+    }
+    drop(v);
+    assert_eq!(frame_remain_num(), remain_before);
+    info!("frame_reclaim_test passed!");
+}
+
+#[allow(unused)]
+// 测试最长连续空闲页帧的统计，只操作页号，不会访问实际内存
+pub fn largest_free_run_test() {
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(100), PhysPageNum(110));
+    assert_eq!(allocator.largest_free_run(), 10);
+    while allocator.alloc().is_some() {}
+    assert_eq!(allocator.largest_free_run(), 0);
+    for ppn in [101, 103, 104, 105, 107] {
+        allocator.dealloc(PhysPageNum(ppn));
+    }
+    assert_eq!(allocator.largest_free_run(), 3);
+    // 回收的页号与尾部区间相连
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(100), PhysPageNum(110));
+    for _ in 0..8 {
+        allocator.alloc();
+    }
+    allocator.dealloc(PhysPageNum(107));
+    allocator.dealloc(PhysPageNum(106));
+    assert_eq!(allocator.largest_free_run(), 4);
+    info!("largest_free_run_test passed!");
+}
+
+#[allow(unused)]
+// 测试共享页帧的引用计数
+pub fn cow_refcount_test() {
+    let frame = frame_alloc().unwrap();
+    let ppn = frame.ppn;
+    assert_eq!(cow_refcount(ppn), 1);
+    let shared = frame.share();
+    assert_eq!(cow_refcount(ppn), 2);
+    dump_cow_frames();
+    drop(frame);
+    assert_eq!(cow_refcount(ppn), 1);
+    drop(shared);
+    assert_eq!(cow_refcount(ppn), 0);
+    info!("cow_refcount_test passed!");
+}
+
+#[allow(unused)]
+// 测试新分配的页帧全为零
+pub fn frame_zero_test() {
+    let frame = frame_alloc().unwrap();
+    let ppn = frame.ppn;
+    ppn.get_bytes_array().fill(0xff);
+    drop(frame);
+    // 栈式分配器会优先分配刚回收的页帧
+    let frame = frame_alloc().unwrap();
+    assert_eq!(frame.ppn, ppn);
+    assert!(frame.ppn.get_bytes_array().iter().all(|b| *b == 0));
+    info!("frame_zero_test passed!");
+}
+
+#[allow(unused)]
+// 测试大页按2MiB对齐，分配后剩余页帧减少512，释放后全部收回
+pub fn huge_frame_test() {
+    let remain_before = frame_remain_num();
+    let huge = frame_alloc_huge().unwrap();
+    let pa: PhysAddr = huge.ppn.into();
+    assert_eq!(pa.0 % (HUGE_FRAME_PAGES * PAGE_SIZE), 0);
+    assert_eq!(remain_before - frame_remain_num(), HUGE_FRAME_PAGES);
+    // 为对齐跳过的页帧仍能被普通分配使用
+    let frame = frame_alloc().unwrap();
+    assert!(frame.ppn.0 < huge.ppn.0 || frame.ppn.0 >= huge.ppn.0 + HUGE_FRAME_PAGES);
+    drop(frame);
+    drop(huge);
+    assert_eq!(frame_remain_num(), remain_before);
+    info!("huge_frame_test passed!");
+}
+
+#[allow(unused)]
+// 测试回调按顺序记录了分配与回收事件，只操作页号，不会访问实际内存
+pub fn frame_hook_test() {
+    lazy_static! {
+        static ref EVENTS: UPSafeCell<Vec<(usize, bool)>> = unsafe { UPSafeCell::new(Vec::new()) };
+    }
+    fn record(ppn: PhysPageNum, alloc: bool) {
+        EVENTS.exclusive_access().push((ppn.0, alloc));
+    }
+    let mut allocator = StackFrameAllocator::new();
+    allocator.init(PhysPageNum(100), PhysPageNum(110));
+    allocator.set_hook(Some(record));
+    let a = allocator.alloc().unwrap();
+    let b = allocator.alloc().unwrap();
+    allocator.dealloc(a);
+    let c = allocator.alloc().unwrap();
+    allocator.dealloc(b);
+    allocator.set_hook(None);
+    allocator.dealloc(c);
+    assert_eq!(
+        *EVENTS.exclusive_access(),
+        [(100, true), (101, true), (100, false), (100, true), (101, false)]
+    );
+    info!("frame_hook_test passed!");
+}
+
+#[allow(unused)]
+// 测试页帧统计：分配10个页帧后已分配数正好加10，空闲数与 frame_remain_num 一致，释放后回到原值
+pub fn frame_stats_test() {
+    let before = frame_allocator_stats();
+    assert_eq!(before.free, frame_remain_num());
+    assert_eq!(before.allocated + before.free, before.total);
+    assert!(before.recycled <= before.free);
+    let frames: Vec<FrameTracker> = (0..10).map(|_| frame_alloc().unwrap()).collect();
+    let stats = frame_allocator_stats();
+    assert_eq!(stats.total, before.total);
+    assert_eq!(stats.allocated, before.allocated + 10);
+    assert_eq!(stats.free, frame_remain_num());
+    drop(frames);
+    let after = frame_allocator_stats();
+    assert_eq!((after.allocated, after.free), (before.allocated, before.free));
+    // 释放的页帧进入回收列表
+    assert!(after.recycled >= 10);
+    info!("frame_stats_test passed!");
+}
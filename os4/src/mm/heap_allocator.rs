@@ -6,9 +6,116 @@ use crate::config::KERNEL_HEAP_SIZE;
 use buddy_system_allocator::LockedHeap;
 
 // 标注全局堆分配器，使能alloc库
+#[cfg(not(feature = "heap_canary"))]
 #[global_allocator]
 // 创建伙伴分配器全局实例,这也是内部可变,互斥锁 Mutex<T>(跨线程版的RefCell)
-static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+pub(super) static HEAP_ALLOCATOR: LockedHeap = LockedHeap::empty();
+
+// 打开 heap_canary feature 时，在伙伴分配器外面包一层，给每次分配的前后放上哨兵字
+#[cfg(feature = "heap_canary")]
+#[global_allocator]
+pub(super) static HEAP_ALLOCATOR: CanaryHeap = CanaryHeap {
+    inner: LockedHeap::empty(),
+};
+
+#[cfg(feature = "heap_canary")]
+pub use canary::{check_canaries, find_clobbered_canary};
+#[cfg(feature = "heap_canary")]
+use canary::CanaryHeap;
+
+#[cfg(feature = "heap_canary")]
+mod canary {
+    use buddy_system_allocator::LockedHeap;
+    use core::alloc::{GlobalAlloc, Layout};
+    use core::ops::Deref;
+    use spin::Mutex;
+
+    // 哨兵字，被改写说明发生了越界写
+    const CANARY: usize = 0xdead_beef_cafe_babe;
+    // 最多跟踪的分配数，超出的分配仍有哨兵，但只在释放时检查
+    const MAX_TRACKED: usize = 1024;
+
+    // 记录仍存活的分配（用户指针、大小），用定长数组避免跟踪本身再去分配堆
+    static TRACKED: Mutex<[(usize, usize); MAX_TRACKED]> = Mutex::new([(0, 0); MAX_TRACKED]);
+
+    pub struct CanaryHeap {
+        pub inner: LockedHeap,
+    }
+
+    // 让 HEAP_ALLOCATOR.lock() 等用法保持不变
+    impl Deref for CanaryHeap {
+        type Target = LockedHeap;
+        fn deref(&self) -> &LockedHeap {
+            &self.inner
+        }
+    }
+
+    // 头部哨兵占一个对齐单位，保证返回给用户的指针仍满足对齐要求
+    fn head_size(layout: &Layout) -> usize {
+        layout.align().max(core::mem::size_of::<usize>())
+    }
+
+    fn outer_layout(layout: &Layout) -> Layout {
+        let size = head_size(layout) + layout.size() + core::mem::size_of::<usize>();
+        Layout::from_size_align(size, head_size(layout)).unwrap()
+    }
+
+    // 检查一次分配前后的哨兵是否完好
+    unsafe fn intact(ptr: usize, size: usize) -> bool {
+        let head = ((ptr - core::mem::size_of::<usize>()) as *const usize).read();
+        let tail = ((ptr + size) as *const usize).read_unaligned();
+        head == CANARY && tail == CANARY
+    }
+
+    unsafe impl GlobalAlloc for CanaryHeap {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let outer = self.inner.alloc(outer_layout(&layout));
+            if outer.is_null() {
+                return outer;
+            }
+            let ptr = outer as usize + head_size(&layout);
+            ((ptr - core::mem::size_of::<usize>()) as *mut usize).write(CANARY);
+            ((ptr + layout.size()) as *mut usize).write_unaligned(CANARY);
+            let mut tracked = TRACKED.lock();
+            if let Some(slot) = tracked.iter_mut().find(|(p, _)| *p == 0) {
+                *slot = (ptr, layout.size());
+            }
+            ptr as *mut u8
+        }
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            let ptr = ptr as usize;
+            assert!(
+                intact(ptr, layout.size()),
+                "heap canary clobbered around {:#x}, size = {}",
+                ptr,
+                layout.size()
+            );
+            let mut tracked = TRACKED.lock();
+            if let Some(slot) = tracked.iter_mut().find(|(p, _)| *p == ptr) {
+                *slot = (0, 0);
+            }
+            drop(tracked);
+            self.inner
+                .dealloc((ptr - head_size(&layout)) as *mut u8, outer_layout(&layout));
+        }
+    }
+
+    // 扫描所有被跟踪的分配，返回第一个哨兵被改写的分配地址
+    pub fn find_clobbered_canary() -> Option<usize> {
+        let tracked = TRACKED.lock();
+        tracked
+            .iter()
+            .find(|(ptr, size)| *ptr != 0 && unsafe { !intact(*ptr, *size) })
+            .map(|(ptr, _)| *ptr)
+    }
+
+    // 发现哨兵被改写就panic，尽早暴露内核中的越界写
+    pub fn check_canaries() {
+        if let Some(ptr) = find_clobbered_canary() {
+            panic!("heap canary clobbered around {:#x}", ptr);
+        }
+    }
+}
 
 // 绑定分配出错处理
 #[alloc_error_handler]
@@ -54,3 +161,21 @@ pub fn heap_test() {
     drop(v);
     info!("heap_test passed!");
 }
+
+#[cfg(feature = "heap_canary")]
+#[allow(unused)]
+// 测试越界写会被哨兵检查发现
+pub fn heap_canary_test() {
+    use alloc::boxed::Box;
+    let buffer = Box::new([0u8; 16]);
+    assert_eq!(find_clobbered_canary(), None);
+    let ptr = buffer.as_ptr() as *mut u8;
+    // 故意越界写一个字节，之后再恢复，免得释放时panic
+    let saved = unsafe { ptr.add(16).read() };
+    unsafe { ptr.add(16).write(!saved) };
+    assert_eq!(find_clobbered_canary(), Some(ptr as usize));
+    unsafe { ptr.add(16).write(saved) };
+    check_canaries();
+    drop(buffer);
+    info!("heap_canary_test passed!");
+}
@@ -2,17 +2,26 @@
 // 操作系统通过对不同页表的管理，来完成对不同应用和操作系统自身所在的虚拟内存，以及虚拟内存与物理内存映射关系的全面管理。
 // 这种管理是建立在 地址空间 的抽象上，用来表明正在运行的应用或内核自身所在执行环境中的可访问的内存空间。
 
-use super::{frame_alloc, frame_remain_num, FrameTracker};
-use super::{PTEFlags, PageTable, PageTableEntry};
-use super::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use super::{StepByOne, VPNRange};
-use crate::config::{MEMORY_END, PAGE_SIZE, TRAMPOLINE, TRAP_CONTEXT, USER_STACK_SIZE};
+use super::{cow_refcount, frame_alloc, frame_remain_num, frame_reserve, frame_unreserve, share_zero_frame, zero_frame, FrameTracker};
+use super::{PTEFlags, PageTable, PageTableEntry, PageTableError};
+use super::{align_down, align_up, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum, HUGE_FRAME_PAGES};
+use super::{check_page_aligned, expect_page_aligned, StepByOne, VPNRange};
+use super::address::VA_WIDTH_SV39;
+use crate::config::{
+    ASLR_ENABLED, ASLR_MAX_PAGES, LAZY_BSS, LAZY_MMAP, PIE_LOAD_BIAS, WX_ENFORCE, MEMORY_END, MMAP_BASE, PAGE_SIZE, RLIM_INFINITY, TRAMPOLINE,
+    TRAP_CONTEXT, USER_STACK_SIZE,
+};
+use crate::syscall::errno::{EEXIST, EINVAL, ENOMEM};
 use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::fmt::{self, Display, Formatter, Write};
 use lazy_static::*;
+use crate::random::{next_seed, Prng};
+use crate::sync::{UPRwLock, UPSafeCell};
 use riscv::register::satp;
-use spin::Mutex;
 
 // 全是从ld里导入过来的
 extern "C" {
@@ -28,12 +37,31 @@ extern "C" {
     fn strampoline();
 }
 
+lazy_static! {
+    // 地址空间布局随机化是否打开，随机偏移本身由各地址空间自己的伪随机数发生器产生
+    static ref ASLR: UPSafeCell<bool> = unsafe { UPSafeCell::new(ASLR_ENABLED) };
+}
+
+// 运行时打开或关闭地址空间布局随机化
+pub fn set_aslr(enabled: bool) {
+    *ASLR.exclusive_access() = enabled;
+}
+
 lazy_static! {
     // 建内核地址空间的全局实例
-    pub static ref KERNEL_SPACE: Arc<Mutex<MemorySet>> =
-        Arc::new(Mutex::new(MemorySet::new_kernel()));
+    // 读多写少：查页表、取token只需读锁，修改映射时才需要写锁
+    pub static ref KERNEL_SPACE: Arc<UPRwLock<MemorySet>> =
+        Arc::new(unsafe { UPRwLock::new(MemorySet::new_kernel()) });
 }
 
+// 在内核地址空间中映射设备寄存器区间，供设备驱动使用，返回其地址，失败时返回-1
+pub fn map_mmio(pa_start: usize, len: usize) -> isize {
+    let ret = KERNEL_SPACE.write().map_mmio(pa_start, len);
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    ret
+}
 
 // 定义地址空间的结构，由一个页表和一些逻辑段组成，是一系列有关联的不一定连续的逻辑段，
 // 这种关联一般是指这些逻辑段组成的虚拟内存空间与一个运行的程序（目前把一个运行的程序称为任务，后续会称为进程）绑定，
@@ -44,16 +72,80 @@ lazy_static! {
 pub struct MemorySet {
     page_table: PageTable,
     areas: Vec<MapArea>,
+    rlimit_as: usize, // 地址空间字节数的软限制，mmap超出时失败
+    load_bias: usize, // 应用各段相对ELF中地址的加载偏移，ET_EXEC为0，供以后重定位使用
+    brk_base: usize, // 堆逻辑段的起始地址，没有堆逻辑段时为0
+    brk: usize, // 当前的program break，堆逻辑段覆盖[brk_base, brk)向上取整到页
+    stack_bottom: usize, // 用户栈的最低地址，其下一页是保护页，没有用户栈时为0
+    peak_mapped_bytes: usize, // 逻辑段映射字节数的历史最大值，供任务退出时的资源汇总使用
+    rng: Prng, // 本地址空间所属任务的伪随机数发生器，ASLR的偏移从这里取
 }
 
 impl MemorySet {
 
     // 新建一个空的地址空间
     pub fn new_bare() -> Self {
-        Self {
-            page_table: PageTable::new(),
+        Self::try_new_bare().expect("no frame for a new address space")
+    }
+
+    // 与 new_bare 相同，但没有页帧存放根页表时返回错误
+    pub fn try_new_bare() -> Result<Self, PageTableError> {
+        Ok(Self {
+            page_table: PageTable::try_new()?,
             areas: Vec::new(),
+            rlimit_as: RLIM_INFINITY,
+            load_bias: 0,
+            brk_base: 0,
+            brk: 0,
+            stack_bottom: 0,
+            peak_mapped_bytes: 0,
+            rng: Prng::new(next_seed()),
+        })
+    }
+
+    // 生成一个按页对齐的随机偏移，ASLR关闭时为0
+    fn aslr_offset(&mut self) -> usize {
+        if !*ASLR.exclusive_access() {
+            return 0;
         }
+        self.rng.below(ASLR_MAX_PAGES + 1) * PAGE_SIZE
+    }
+
+    // 应用的加载偏移
+    pub fn load_bias(&self) -> usize {
+        self.load_bias
+    }
+
+    // 地址空间中所有逻辑段占用的字节数
+    pub fn mapped_bytes(&self) -> usize {
+        self.areas
+            .iter()
+            .map(|area| area.vpn_range.len() * PAGE_SIZE)
+            .sum()
+    }
+
+    // 逻辑段映射字节数的历史最大值，clear之后重新计算
+    pub fn peak_mapped_bytes(&self) -> usize {
+        self.peak_mapped_bytes
+    }
+
+    // 新增映射之后调用，更新历史最大值
+    fn update_peak(&mut self) {
+        self.peak_mapped_bytes = self.peak_mapped_bytes.max(self.mapped_bytes());
+    }
+
+    // 页表自身的开销（各级页表节点占用的页帧），与逻辑段中的用户数据分开统计
+    pub fn overhead_bytes(&self) -> usize {
+        self.page_table.node_count() * PAGE_SIZE
+    }
+
+    pub fn rlimit_as(&self) -> usize {
+        self.rlimit_as
+    }
+
+    // 设置地址空间大小限制，可以低于当前用量，此后只是不能再增长
+    pub fn set_rlimit_as(&mut self, limit: usize) {
+        self.rlimit_as = limit;
     }
 
 
@@ -75,25 +167,100 @@ impl MemorySet {
         );
     }
 
+    // 与 insert_framed_area 相同，但只记录逻辑段，页帧由 commit_page 按需分配
+    pub fn insert_lazy_framed_area(
+        &mut self,
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        permission: MapPermission,
+    ) {
+        self.areas
+            .push(MapArea::new(start_va, end_va, MapType::Framed, permission));
+    }
+
     // push 方法可以在当前地址空间插入一个新的逻辑段 map_area 
     // 如果它是以 Framed 方式映射到物理内存，还可以可选地在那些被映射到的物理页帧上写入一些初始化数据 data
-    fn push(&mut self, mut map_area: MapArea, data: Option<&[u8]>) {
-        map_area.map(&mut self.page_table);
+    // 内核自己的映射不会重复，出错直接panic
+    fn push(&mut self, map_area: MapArea, data: Option<&[u8]>) {
+        self.try_push(map_area, data)
+            .expect("map area overlaps an existing mapping");
+    }
+
+    // 与 push 相同，但逻辑段与已有映射重叠或页帧不够时返回错误，不修改地址空间
+    fn try_push(
+        &mut self,
+        mut map_area: MapArea,
+        data: Option<&[u8]>,
+    ) -> Result<(), PageTableError> {
+        map_area.map(&mut self.page_table)?;
         if let Some(data) = data {
             map_area.copy_data(&mut self.page_table, data);
         }
         self.areas.push(map_area);
+        self.update_peak();
+        Ok(())
+    }
+
+    // 与 try_push 相同，但只为 data_end 之前（含有文件数据）的页面分配页帧，之后的页面（BSS）先映射到共享零页，数据由调用者写入
+    fn push_lazy_bss(&mut self, mut map_area: MapArea, data_end: VirtPageNum) -> Result<(), PageTableError> {
+        for vpn in map_area.vpn_range {
+            let result = if vpn < data_end {
+                map_area.map_one(&mut self.page_table, vpn)
+            } else {
+                map_area.map_zero_one(&mut self.page_table, vpn)
+            };
+            if let Err(err) = result {
+                map_area.unmap(&mut self.page_table);
+                return Err(err);
+            }
+        }
+        self.areas.push(map_area);
+        self.update_peak();
+        Ok(())
+    }
+
+    // 压入ELF的一个LOAD段，再把文件中从 offset 开始的 file_size 字节复制到 start_va 处
+    // start_va 不一定页对齐，含有文件数据的最后一页是 (start_va + file_size) 向上取整之前的那页；
+    // lazy_bss 为真时其后的页面映射到共享零页
+    fn push_elf_segment(
+        &mut self,
+        map_area: MapArea,
+        start_va: VirtAddr,
+        elf_data: &ElfChunks,
+        offset: usize,
+        file_size: usize,
+        lazy_bss: bool,
+    ) -> Result<(), PageTableError> {
+        if lazy_bss {
+            let data_end = start_va.checked_add(file_size).expect("segment address overflows").ceil();
+            self.push_lazy_bss(map_area, data_end)?;
+        } else {
+            self.try_push(map_area, None)?;
+        }
+        let area = self.areas.last_mut().unwrap();
+        area.copy_chunks(&mut self.page_table, elf_data, offset, start_va.page_offset(), file_size);
+        Ok(())
     }
 
     // 跳板代码地址加入页表里,跳板代码也就是之前的trap代码
     fn map_trampoline(&mut self) {
+        self.try_map_trampoline().expect("failed to map the trampoline");
+    }
+
+    // 与 map_trampoline 相同，但没有页帧存放页表节点时返回错误
+    fn try_map_trampoline(&mut self) -> Result<(), PageTableError> {
+        self.try_map_trampoline_at(TRAMPOLINE)
+    }
+
+    // 把跳板映射到虚拟地址 trampoline，平时就是配置中的 TRAMPOLINE，测试别的布局时可以换成其它地址
+    fn try_map_trampoline_at(&mut self, trampoline: usize) -> Result<(), PageTableError> {
         // 只调用加页表方法,不用分配页帧写数据什么的,因为本来就在内存里有了
         self.page_table.map(
-            VirtAddr::from(TRAMPOLINE).into(), // TRAMPOLINE是只把跳板放在虚拟地址空间最顶部,
+            expect_page_aligned(trampoline, "TRAMPOLINE"), // TRAMPOLINE默认把跳板放在虚拟地址空间最顶部,
             // 所有虚拟地址空间都这么放,那在转换的时候就不会造成指令无法桉顺序进行了
             PhysAddr::from(strampoline as usize).into(), // 物理地址对应ld的那片地址
-            PTEFlags::R | PTEFlags::X, // 可读可执行
-        );
+            PTEFlags::R | PTEFlags::X | PTEFlags::G, // 可读可执行，所有地址空间中都一样，设为全局页
+        )
     }
 
     // 生成内核的地址空间,在mm初始化的时候被调用,主要是为现有的内核部分内存构建一个虚拟的地址空间概念
@@ -116,13 +283,14 @@ impl MemorySet {
             ".bss [{:#x}, {:#x})",
             sbss_with_stack as usize, ebss as usize
         );
+        // 内核的恒等映射段在所有地址空间中都相同，标记为全局页，切换satp时TLB可以保留这些项
         info!("mapping .text section");
         memory_set.push(
             MapArea::new(
                 (stext as usize).into(),
                 (etext as usize).into(),
                 MapType::Identical,
-                MapPermission::R | MapPermission::X,
+                MapPermission::R | MapPermission::X | MapPermission::G,
             ),
             None,
         );
@@ -132,7 +300,7 @@ impl MemorySet {
                 (srodata as usize).into(),
                 (erodata as usize).into(),
                 MapType::Identical,
-                MapPermission::R,
+                MapPermission::R | MapPermission::G,
             ),
             None,
         );
@@ -142,7 +310,7 @@ impl MemorySet {
                 (sdata as usize).into(),
                 (edata as usize).into(),
                 MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
@@ -152,17 +320,18 @@ impl MemorySet {
                 (sbss_with_stack as usize).into(),
                 (ebss as usize).into(),
                 MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
+        // 物理内存窗口很大，对齐的部分用 2MiB 大页映射，省下大量末级页表节点
         info!("mapping physical memory");
         memory_set.push(
             MapArea::new(
                 (ekernel as usize).into(),
                 MEMORY_END.into(),
-                MapType::Identical,
-                MapPermission::R | MapPermission::W,
+                MapType::IdenticalHuge,
+                MapPermission::R | MapPermission::W | MapPermission::G,
             ),
             None,
         );
@@ -170,61 +339,535 @@ impl MemorySet {
         memory_set
     }
 
-    // 为分配内存的系统调用提供支持
+    // 为分配内存的系统调用提供支持，打开 LAZY_MMAP 时延迟到第一次访问再分配页帧
     pub fn mmap(&mut self, start: usize, len: usize, port: usize) -> isize {
-        if (port & !0b0000_0111 != 0) || (port & 0b0000_0111 == 0) { return -1; }
-        let va_start = VirtAddr::from(start);
-        let va_end = VirtAddr::from(start + len);
-        if va_start.page_offset() != 0 { return -1; }
-        let mut map_perm = MapPermission::U;
-        if port & 0b0000_0001 == 0b0000_0001 {
-            map_perm |= MapPermission::R;
+        if LAZY_MMAP {
+            self.mmap_lazy(start, len, port)
+        } else {
+            self.mmap_with_data(start, len, port, &[])
         }
-        if port & 0b0000_0010 == 0b0000_0010 {
-            map_perm |= MapPermission::W;
-        }
-        if port & 0b0000_0100 == 0b0000_0100 {
-            map_perm |= MapPermission::X;
+    }
+
+    // 分配内存并用data初始化，data比区域短时剩余部分保持为零
+    // start为0时由内核挑选空闲区域，成功时返回所选的起始地址
+    pub fn mmap_with_data(&mut self, start: usize, len: usize, port: usize, data: &[u8]) -> isize {
+        self.mmap_area(start, len, port, data, false)
+    }
+
+    // 只记录逻辑段而不分配页帧，页面在第一次访问触发缺页时由 commit_page 分配
+    pub fn mmap_lazy(&mut self, start: usize, len: usize, port: usize) -> isize {
+        self.mmap_area(start, len, port, &[], true)
+    }
+
+    fn mmap_area(&mut self, start: usize, len: usize, port: usize, data: &[u8], lazy: bool) -> isize {
+        // 先检查参数本身（-EINVAL），再检查与已有映射的冲突（-EEXIST），最后才检查资源（-ENOMEM）
+        if (port & !(0b0000_0111 | MAP_LOCKED) != 0) || (port & 0b0000_0111 == 0) { return -EINVAL; }
+        if data.len() > len { return -EINVAL; }
+        if start == 0 {
+            return match self.find_free_range(len) {
+                Some(start) => match self.mmap_area(start, len, port, data, lazy) {
+                    0 => start as isize,
+                    err => err,
+                },
+                None => -ENOMEM,
+            };
         }
-        let map_area = MapArea::new(va_start, va_end, MapType::Framed, map_perm);
-        if VirtAddr::from(len).ceil() > VirtPageNum(frame_remain_num()) { return -1; }
+        let va_start = VirtAddr::from(start);
+        let va_end = match user_range_end(va_start, len) {
+            Some(va_end) => va_end,
+            None => return -EINVAL,
+        };
+        if va_start.page_offset() != 0 { return -EINVAL; }
+        let map_perm = perm_from_port(port);
+        let mut map_area = MapArea::new(va_start, va_end, MapType::Framed, map_perm);
+        map_area.locked = port & MAP_LOCKED != 0;
+        let page_count = map_area.vpn_range.len();
         for vpn in map_area.vpn_range {
             if let Some(pte) = self.page_table.find_pte(vpn) { 
                 if pte.is_valid() {
-                    return -1; 
+                    return -EEXIST; 
                 }
             }
         }
-        self.push(map_area, None);
-        0
+        // 延迟分配的逻辑段还没有页表项，还要和已有逻辑段比较
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < map_area.vpn_range.get_end()
+                && map_area.vpn_range.get_start() < area.vpn_range.get_end()
+        }) {
+            return -EEXIST;
+        }
+        // 超出地址空间大小限制
+        if self.mapped_bytes() + page_count * PAGE_SIZE > self.rlimit_as { return -ENOMEM; }
+        // 除了数据页帧，最坏情况下还要为页表的中间节点分配页帧，不够就直接返回，避免映射到一半时分配失败
+        let table_frames = page_count / 512 + 2;
+        if !lazy && page_count + table_frames > frame_remain_num() { return -ENOMEM; }
+        if lazy {
+            self.areas.push(map_area);
+            self.update_peak();
+            return 0;
+        }
+        let data = if data.is_empty() { None } else { Some(data) };
+        match self.try_push(map_area, data) {
+            Ok(()) => 0,
+            Err(PageTableError::AlreadyMapped(_)) => -EEXIST,
+            Err(PageTableError::OutOfFrames) => -ENOMEM,
+        }
+    }
+
+    // 为延迟分配的页面分配私有页帧并建立映射，页面还映射在零页上时换成私有页帧
+    // vpn不在任何Framed逻辑段中或已经有私有页帧时返回false
+    pub fn commit_page(&mut self, vpn: VirtPageNum) -> bool {
+        let page_table = &mut self.page_table;
+        match self.areas.iter_mut().find(|area| {
+            area.map_type == MapType::Framed
+                && area.vpn_range.get_start() <= vpn
+                && vpn < area.vpn_range.get_end()
+        }) {
+            Some(area) => area.commit_one(page_table, vpn),
+            None => false,
+        }
+    }
+
+    // 处理延迟分配页面的缺页：读缺页只把页面映射到共享零页，写缺页才分配私有页帧（零页的写时复制）
+    // 不是延迟分配的页面，或者写一个不可写的逻辑段时返回false
+    pub fn commit_page_on_fault(&mut self, vpn: VirtPageNum, write: bool) -> bool {
+        let page_table = &mut self.page_table;
+        match self.areas.iter_mut().find(|area| {
+            area.map_type == MapType::Framed
+                && area.vpn_range.get_start() <= vpn
+                && vpn < area.vpn_range.get_end()
+        }) {
+            Some(area) if write => {
+                area.cow_one(page_table, vpn)
+                    || (area.map_perm.contains(MapPermission::W) && area.commit_one(page_table, vpn))
+            }
+            Some(area) => area.map_zero_one(page_table, vpn).is_ok(),
+            None => false,
+        }
+    }
+
+    // 立即为范围内所有延迟分配的页面分配页帧，返回新分配的页数；范围中有页面不属于任何逻辑段时返回-1
+    pub fn populate(&mut self, start: usize, len: usize) -> isize {
+        let va_start = VirtAddr::from(start);
+        if va_start.page_offset() != 0 { return -1; }
+        let va_end = match user_range_end(va_start, len) {
+            Some(va_end) => va_end,
+            None => return -1,
+        };
+        let vpn_range = VPNRange::new(va_start.floor(), va_end.ceil());
+        for vpn in vpn_range {
+            if !self.areas.iter().any(|area| {
+                area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end()
+            }) {
+                return -1;
+            }
+        }
+        let mut count = 0;
+        for vpn in vpn_range {
+            if self.commit_page(vpn) {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    // 把program break移动delta字节，堆逻辑段按整页扩大或缩小，成功时返回原来的break
+    // 没有堆逻辑段、break低于起点、与其它逻辑段重叠、超出地址空间限制或页帧不够时返回None
+    pub fn grow_brk(&mut self, delta: isize) -> Option<usize> {
+        let old_brk = self.brk;
+        let new_brk = (old_brk as isize).checked_add(delta)?;
+        if self.brk_base == 0 || new_brk < self.brk_base as isize {
+            return None;
+        }
+        let new_brk = new_brk as usize;
+        let base_vpn = VirtAddr::from(self.brk_base).floor();
+        let old_end = VirtAddr::from(old_brk).ceil();
+        let new_end = VirtAddr::from(new_brk).ceil();
+        let idx = self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == base_vpn)?;
+        if new_end > old_end {
+            let pages = new_end.0 - old_end.0;
+            if self.areas.iter().any(|area| {
+                area.vpn_range.get_start() < new_end && old_end < area.vpn_range.get_end()
+            }) {
+                return None;
+            }
+            if self.mapped_bytes() + pages * PAGE_SIZE > self.rlimit_as
+                || pages + pages / 512 + 2 > frame_remain_num()
+            {
+                return None;
+            }
+            self.areas[idx].append_to(&mut self.page_table, new_end).ok()?;
+            self.update_peak();
+        } else if new_end < old_end {
+            self.areas[idx].shrink_to(&mut self.page_table, new_end);
+        }
+        self.brk = new_brk;
+        Some(old_brk)
+    }
+
+    // 把设备寄存器所在的物理区间 [pa_start, pa_start + len) 恒等映射为内核可读写、不可执行的逻辑段，返回其地址
+    // 设备寄存器不应被缓存，但SV39没有描述缓存属性的位（需要Svpbmt扩展），QEMU上恒等映射即可。
+    // 区间与已有逻辑段重叠时返回-1：内核镜像和物理内存窗口都是逻辑段，页帧分配器管理的页帧因此不会被映射成设备区间
+    pub fn map_mmio(&mut self, pa_start: usize, len: usize) -> isize {
+        if len == 0 || pa_start % PAGE_SIZE != 0 { return -1; }
+        let pa_end = match PhysAddr::from(pa_start).checked_add(len) {
+            Some(pa_end) => pa_end,
+            None => return -1,
+        };
+        let start_vpn = VirtAddr::from(pa_start).floor();
+        let end_vpn = VirtAddr::from(pa_end.0).ceil();
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+        }) {
+            return -1;
+        }
+        self.push(
+            MapArea::new(
+                pa_start.into(),
+                pa_end.0.into(),
+                MapType::Identical,
+                MapPermission::R | MapPermission::W,
+            ),
+            None,
+        );
+        pa_start as isize
+    }
+
+    // 丢弃范围内页面的内容：解除映射并归还页帧，但保留逻辑段，之后再访问时缺页重新得到全零的页面
+    // 返回归还的页帧数；范围中有页面不属于任何Framed逻辑段，或属于锁定的逻辑段时返回-1
+    pub fn discard(&mut self, start: usize, len: usize) -> isize {
+        let va_start = VirtAddr::from(start);
+        if va_start.page_offset() != 0 { return -1; }
+        let va_end = match user_range_end(va_start, len) {
+            Some(va_end) => va_end,
+            None => return -1,
+        };
+        let vpn_range = VPNRange::new(va_start.floor(), va_end.ceil());
+        for vpn in vpn_range {
+            if !self.areas.iter().any(|area| {
+                area.map_type == MapType::Framed
+                    && !area.locked
+                    && area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+            }) {
+                return -1;
+            }
+        }
+        let page_table = &mut self.page_table;
+        let mut count = 0;
+        for area in self.areas.iter_mut() {
+            for vpn in vpn_range {
+                if vpn < area.vpn_range.get_start() || area.vpn_range.get_end() <= vpn {
+                    continue;
+                }
+                // 还在共享零页上的页面本来就是零，没有私有页帧可以归还
+                let private = area.data_frames.contains_key(&vpn) && !area.is_zero_mapped(vpn);
+                area.unmap_one(page_table, vpn);
+                if private {
+                    count += 1;
+                }
+            }
+        }
+        count
+    }
+
+    // 预留一段连续物理页帧，并以恒等映射的方式映射为用户可访问的区域，供设备DMA使用
+    // 成功时返回区域地址（虚拟地址与物理地址相同）；预留的物理页帧在解除映射后也不会归还
+    // 映射失败时立即撤销这次预留，不会泄漏页帧
+    pub fn mmap_identical(&mut self, len: usize, port: usize) -> isize {
+        if (port & !0b0000_0111 != 0) || (port & 0b0000_0111 == 0) || len == 0 { return -EINVAL; }
+        let page_count = match len.checked_add(PAGE_SIZE - 1) {
+            Some(end) => end / PAGE_SIZE,
+            None => return -EINVAL,
+        };
+        if self.mapped_bytes().saturating_add(page_count * PAGE_SIZE) > self.rlimit_as { return -ENOMEM; }
+        let ppn = match frame_reserve(page_count) {
+            Some(ppn) => ppn,
+            None => return -ENOMEM,
+        };
+        let base = PhysAddr::from(ppn).0;
+        let map_area = MapArea::new(
+            base.into(),
+            (base + page_count * PAGE_SIZE).into(),
+            MapType::Identical,
+            perm_from_port(port),
+        );
+        if self.areas.iter().any(|area| {
+            area.vpn_range.get_start() < map_area.vpn_range.get_end()
+                && map_area.vpn_range.get_start() < area.vpn_range.get_end()
+        }) {
+            frame_unreserve(ppn, page_count);
+            return -EEXIST;
+        }
+        if self.try_push(map_area, None).is_err() {
+            frame_unreserve(ppn, page_count);
+            return -ENOMEM;
+        }
+        base as isize
+    }
+
+    // 从 MMAP_BASE（打开ASLR时加上随机偏移）开始，找一段与已有逻辑段都不重叠的空闲区域
+    pub fn find_free_range(&mut self, len: usize) -> Option<usize> {
+        if len > TRAP_CONTEXT.min(TRAMPOLINE) {
+            return None;
+        }
+        let page_count = VirtAddr::from(len).ceil().0;
+        let mut start_vpn = VirtAddr::from(MMAP_BASE + self.aslr_offset()).floor();
+        // trap上下文和跳板中较低的那个以上都不分给mmap
+        let top_vpn = VirtAddr::from(TRAP_CONTEXT.min(TRAMPOLINE)).floor();
+        loop {
+            let end_vpn = VirtPageNum(start_vpn.0 + page_count);
+            if end_vpn > top_vpn {
+                return None;
+            }
+            match self.areas.iter().find(|area| {
+                area.vpn_range.get_start() < end_vpn && start_vpn < area.vpn_range.get_end()
+            }) {
+                Some(area) => start_vpn = area.vpn_range.get_end(),
+                None => return Some(VirtAddr::from(start_vpn).into()),
+            }
+        }
     }
 
+    // 解除映射的范围必须页对齐（否则返回-1），返回实际解除映射的页数，即被删掉的页表项数，延迟分配还没访问过的页面不算。
+    // 范围内没有映射的页面不算错误，完全没有映射时返回0；逻辑段只有一部分在范围内时把它拆开，范围外的头部和尾部成为独立的逻辑段，保持原来的映射方式和权限
+    // 只解除用户逻辑段，没有U标志的逻辑段（trap上下文、内核自己的映射）和还没有页面的空逻辑段（如还没增长的堆）保持不变
     pub fn munmap(&mut self, start: usize, len: usize) -> isize {
-        for map_area in self.areas.iter_mut() {
-            if VirtAddr::from(map_area.vpn_range.get_start()) == VirtAddr::from(start) &&
-            VirtAddr::from(map_area.vpn_range.get_end()) == VirtAddr::from(start + len) {
-                map_area.unmap(&mut self.page_table);
-                return 0;
+        let va_start = VirtAddr::from(start);
+        let va_end = match user_range_end(va_start, len) {
+            Some(va_end) => va_end,
+            None => return -1,
+        };
+        if va_start.page_offset() != 0 || va_end.page_offset() != 0 || len == 0 {
+            return -1;
+        }
+        let (first, last) = (va_start.floor(), va_end.floor());
+        self.split_areas_at(first, last);
+        let mut pages = 0;
+        // 解除映射后把逻辑段也移除，否则之后同一区域还能再次mmap，留下重叠的逻辑段
+        while let Some(idx) = self.areas.iter().position(|area| {
+            area.map_perm.contains(MapPermission::U)
+                && area.vpn_range.get_start() < area.vpn_range.get_end()
+                && first <= area.vpn_range.get_start()
+                && area.vpn_range.get_end() <= last
+        }) {
+            pages += self.areas[idx].mapped_count();
+            self.areas[idx].unmap(&mut self.page_table);
+            self.areas.remove(idx);
+        }
+        pages as isize
+    }
+
+    // 在页号first和last处拆开跨过它们的用户逻辑段，之后每个用户逻辑段要么整个在[first, last)内，要么整个在外面
+    fn split_areas_at(&mut self, first: VirtPageNum, last: VirtPageNum) {
+        for at in [first, last] {
+            if let Some(idx) = self.areas.iter().position(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.get_start() < at
+                    && at < area.vpn_range.get_end()
+            }) {
+                let tail = self.areas[idx].split_off(at);
+                self.areas.push(tail);
+            }
+        }
+    }
+
+    // 解除起始页号为start_vpn的逻辑段的映射并把它移除，没有这样的逻辑段时返回false
+    pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) -> bool {
+        match self
+            .areas
+            .iter()
+            .position(|area| area.vpn_range.get_start() == start_vpn)
+        {
+            Some(idx) => {
+                self.areas[idx].unmap(&mut self.page_table);
+                self.areas.remove(idx);
+                true
+            }
+            None => false,
+        }
+    }
+
+    // 为修改内存权限的系统调用提供支持，port为0时把页面设为保护页（保留映射但不可访问）
+    // 范围内的每个页面都必须属于某个用户逻辑段，否则返回-1且不做改动；只覆盖逻辑段一部分时像munmap一样把逻辑段拆开，
+    // 被覆盖的逻辑段记录新的权限，之后按需分配的页面也使用新权限。已映射页面的页表项改写R/W/X，保留U/V
+    pub fn set_permissions(&mut self, start: usize, len: usize, port: usize) -> isize {
+        if port & !0b0000_0111 != 0 { return -1; }
+        let va_start = VirtAddr::from(start);
+        let va_end = match user_range_end(va_start, len) {
+            Some(va_end) => va_end,
+            None => return -1,
+        };
+        if va_start.page_offset() != 0 { return -1; }
+        let (first, last) = (va_start.floor(), va_end.ceil());
+        let vpn_range = VPNRange::new(first, last);
+        // 先检查范围内的页面都属于用户逻辑段
+        for vpn in vpn_range {
+            if !self.areas.iter().any(|area| {
+                area.map_perm.contains(MapPermission::U)
+                    && area.vpn_range.get_start() <= vpn
+                    && vpn < area.vpn_range.get_end()
+            }) {
+                return -1;
+            }
+        }
+        self.split_areas_at(first, last);
+        let map_perm = perm_from_port(port);
+        for area in self.areas.iter_mut() {
+            if first <= area.vpn_range.get_start() && area.vpn_range.get_end() <= last {
+                area.map_perm = map_perm;
             }
         }
-        -1
+        let flags = PTEFlags::from_bits(map_perm.bits as u16).unwrap();
+        for vpn in vpn_range {
+            // 按需分配的逻辑段中还没有访问过的页面没有页表项，分配时会用逻辑段的新权限
+            let ppn = match self.page_table.translate(vpn) {
+                Some(pte) if pte.is_valid() => pte.ppn(),
+                _ => continue,
+            };
+            // 共享零页始终只读，写的时候再走缺页换成私有页帧；
+            // 与别的地址空间共享的页帧同理，可写的页面标为写时复制
+            if ppn == zero_frame() {
+                self.page_table.set_flags(vpn, flags - PTEFlags::W);
+            } else if flags.contains(PTEFlags::W) && cow_refcount(ppn) > 1 {
+                self.page_table.set_flags(vpn, flags - PTEFlags::W | PTEFlags::COW);
+            } else {
+                self.page_table.set_flags(vpn, flags);
+            }
+        }
+        unsafe {
+            core::arch::asm!("sfence.vma");
+        }
+        0
+    }
+
+    // 页面回收的时钟扫描，目前只挑出候选页面，还没有真正换出。跳过锁定的逻辑段；
+    // 访问位为1的页面清除访问位，给它第二次机会；访问位为0的页面作为可以换出的候选返回
+    pub fn age_pages(&mut self) -> Vec<VirtPageNum> {
+        let mut candidates = Vec::new();
+        for area in self.areas.iter() {
+            if area.locked
+                || area.map_type != MapType::Framed
+                || !area.map_perm.contains(MapPermission::U)
+            {
+                continue;
+            }
+            for vpn in area.data_frames.keys() {
+                // 共享零页不占私有页帧，没有换出的意义
+                if area.is_zero_mapped(*vpn) {
+                    continue;
+                }
+                let flags = self.page_table.translate(*vpn).unwrap().flags();
+                if flags.contains(PTEFlags::A) {
+                    self.page_table.set_flags(*vpn, flags - PTEFlags::A);
+                } else {
+                    candidates.push(*vpn);
+                }
+            }
+        }
+        // 清除的访问位要让TLB重新装入后才会被硬件再次置位
+        unsafe {
+            core::arch::asm!("sfence.vma");
+        }
+        candidates
+    }
+
+    // 判断某个虚拟地址是否落在被mprotect设为保护页的页面上
+    pub fn is_guard_page(&self, va: VirtAddr) -> bool {
+        match self.page_table.translate(va.floor()) {
+            Some(pte) => pte.is_guard(),
+            None => false,
+        }
+    }
+
+    // 判断出错地址是否落在用户栈正下方的保护页上，也就是栈溢出。保护页被mmap占用后就不再算
+    pub fn is_guard_fault(&self, va: VirtAddr) -> bool {
+        if self.stack_bottom == 0 {
+            return false;
+        }
+        let guard_vpn = VirtPageNum(VirtAddr::from(self.stack_bottom).floor().0 - 1);
+        va.floor() == guard_vpn && !self.contains_addr(va)
+    }
+
+    // 判断某个虚拟地址是否落在某个逻辑段内，按需分配还没有页表项的页面也算
+    pub fn contains_addr(&self, va: VirtAddr) -> bool {
+        let vpn = va.floor();
+        self.areas
+            .iter()
+            .any(|area| area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end())
+    }
+
+    // 判断某个虚拟地址是否落在已映射的可执行页面上
+    pub fn is_executable_page(&self, va: VirtAddr) -> bool {
+        match self.page_table.translate(va.floor()) {
+            Some(pte) => pte.is_valid() && pte.executable(),
+            None => false,
+        }
     }
 
     // 分析应用的 ELF 文件格式的内容，解析出各数据段并生成对应的地址空间
     pub fn from_elf(elf_data: &[u8]) -> (Self, usize, usize) {
+        Self::from_elf_reader(core::iter::once(elf_data))
+    }
+
+    // 与 from_elf 相同，但ELF文件按顺序分成若干块给出（见 loader::app_reader），不要求整个文件连续存放
+    pub fn from_elf_reader<'a>(reader: impl Iterator<Item = &'a [u8]>) -> (Self, usize, usize) {
+        Self::try_from_elf_reader(reader).expect("failed to load the app")
+    }
+
+    // 与 from_elf_reader 相同，但页帧不够时返回错误，已经分配的页帧随半成品的地址空间一起回收
+    pub fn try_from_elf_reader<'a>(
+        reader: impl Iterator<Item = &'a [u8]>,
+    ) -> Result<(Self, usize, usize), PageTableError> {
         // 新建地址空间
-        let mut memory_set = Self::new_bare();
+        let mut memory_set = Self::try_new_bare()?;
         // 插入跳板
-        memory_set.map_trampoline();
-        // 使用外部 crate xmas_elf 来解析传入的应用 ELF 数据并可以轻松取出各个部分。
+        memory_set.try_map_trampoline()?;
+        let (user_stack_top, entry_point) = memory_set.load_elf(ElfChunks::new(reader))?;
+        // 返回地址空间、用户栈底位置、应用程序入口点
+        Ok((memory_set, user_stack_top, entry_point))
+    }
+
+    // 清空地址空间中的所有逻辑段并回收其页帧，保留根页表和跳板，以便放回对象池复用
+    pub fn clear(&mut self) {
+        for map_area in self.areas.iter_mut() {
+            map_area.unmap(&mut self.page_table);
+        }
+        self.areas.clear();
+        self.brk_base = 0;
+        self.brk = 0;
+        self.stack_bottom = 0;
+        self.peak_mapped_bytes = 0;
+    }
 
-        let elf = xmas_elf::ElfFile::new(elf_data).unwrap();
+    // 清空后重新载入一个应用，返回用户栈顶位置和应用程序入口点
+    pub fn reset_from_elf(&mut self, elf_data: &[u8]) -> (usize, usize) {
+        self.clear();
+        self.load_elf(ElfChunks::new(core::iter::once(elf_data)))
+            .expect("failed to load the app")
+    }
+
+    // 按ELF期望的布局把应用的各段、用户栈和trap上下文压入地址空间，返回用户栈顶位置和应用程序入口点
+    // 段之间重叠或页帧不够时返回错误，已经压入的逻辑段留在地址空间里
+    fn load_elf(&mut self, elf_data: ElfChunks) -> Result<(usize, usize), PageTableError> {
+        // 使用外部 crate xmas_elf 来解析传入的应用 ELF 数据并可以轻松取出各个部分。
+        // xmas_elf 需要连续的数据，这里只把ELF头和程序头表拼到一起，各段的数据直接从分块中复制到页帧
+        let headers = elf_data.headers();
+        let elf = xmas_elf::ElfFile::new(&headers).unwrap();
         // 得到elf头
         let elf_header = elf.header;
         // 得到魔数
         let magic = elf_header.pt1.magic;
         // 检查魔数
         assert_eq!(magic, [0x7f, 0x45, 0x4c, 0x46], "invalid elf!");
+        // 位置无关的应用（ET_DYN）整体加上一个加载偏移，ET_EXEC照原样加载
+        self.load_bias = match elf_header.pt2.type_().as_type() {
+            xmas_elf::header::Type::SharedObject => PIE_LOAD_BIAS,
+            _ => 0,
+        };
         // 得到程序头的数量，程序头部表（Program Header Table），如果存在的话，告诉系统如何创建进程映像。
         let ph_count = elf_header.pt2.ph_count();
         // 用来记录应用虚拟地址静态部分，也就各个段的结束位置
@@ -235,8 +878,13 @@ impl MemorySet {
             // 对于LOAD类型，表明它有被内核加载的必要，进行加载操作
             if ph.get_type().unwrap() == xmas_elf::program::Type::Load {
                 // 用ph.virtual_addr()和ph.mem_size()查看ELF期望这一区域在应用虚拟地址空间中的位置
-                let start_va: VirtAddr = (ph.virtual_addr() as usize).into();
-                let end_va: VirtAddr = ((ph.virtual_addr() + ph.mem_size()) as usize).into();
+                // ELF中的地址和长度不可信，相加溢出时拒绝载入
+                let start_va = VirtAddr::from(ph.virtual_addr() as usize)
+                    .checked_add(self.load_bias)
+                    .expect("segment address overflows");
+                let end_va = start_va
+                    .checked_add(ph.mem_size() as usize)
+                    .expect("segment address overflows");
                 // 用ph_flags查看ELF期望这一区域的权限
                 // 首先肯定是用户可访问的
                 let mut map_perm = MapPermission::U;
@@ -254,12 +902,15 @@ impl MemorySet {
                 // 可以为任务的这个段创建逻辑段了
                 let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
                 max_end_vpn = map_area.vpn_range.get_end();
-                // 压入任务的地址空间
-                memory_set.push(
+                // 压入任务的地址空间，再把数据复制进去
+                self.push_elf_segment(
                     map_area,
-                    // 压入的同时附带数据
-                    Some(&elf.input[ph.offset() as usize..(ph.offset() + ph.file_size()) as usize]),
-                );
+                    start_va,
+                    &elf_data,
+                    ph.offset() as usize,
+                    ph.file_size() as usize,
+                    LAZY_BSS,
+                )?;
             }
         }
         // 刚才记录了静态部分的结束位置，接下来在静态部分的上方再分配以一个逻辑段作为用户栈
@@ -268,11 +919,13 @@ impl MemorySet {
         // 设置栈的最下界
         let mut user_stack_bottom: usize = max_end_va.into();
         // 搞一个保护页，有虚页面无实际页帧，好在栈溢出的时候trap
-        user_stack_bottom += PAGE_SIZE;
+        // 打开ASLR时再随机偏移若干页
+        user_stack_bottom += PAGE_SIZE + self.aslr_offset();
         // 设置栈最上界
         let user_stack_top = user_stack_bottom + USER_STACK_SIZE;
+        self.stack_bottom = user_stack_bottom;
         // 用户栈压入地址空间
-        memory_set.push(
+        self.try_push(
             MapArea::new(
                 user_stack_bottom.into(),
                 user_stack_top.into(),
@@ -280,23 +933,31 @@ impl MemorySet {
                 MapPermission::R | MapPermission::W | MapPermission::U,
             ),
             None,
-        );
-        // 压入trap上下文段，这部分config文件中给出了地址
-        memory_set.push(
+        )?;
+        // 在用户栈上方放一个初始为空的堆逻辑段，sbrk 从这里向上增长
+        // （ELF各段的正上方已经是保护页和用户栈，堆没法从那里长出来）
+        self.brk_base = user_stack_top;
+        self.brk = user_stack_top;
+        self.try_push(
+            MapArea::new(
+                user_stack_top.into(),
+                user_stack_top.into(),
+                MapType::Framed,
+                MapPermission::R | MapPermission::W | MapPermission::U,
+            ),
+            None,
+        )?;
+        // 压入trap上下文段，这部分config文件中给出了地址，占一页
+        self.try_push(
             MapArea::new(
                 TRAP_CONTEXT.into(),
-                TRAMPOLINE.into(),
+                (TRAP_CONTEXT + PAGE_SIZE).into(),
                 MapType::Framed,
                 MapPermission::R | MapPermission::W,
             ),
             None,
-        );
-        // 返回地址空间、用户栈底位置、应用程序入口点
-        (
-            memory_set,
-            user_stack_top,
-            elf.header.pt2.entry_point() as usize,
-        )
+        )?;
+        Ok((user_stack_top, elf.header.pt2.entry_point() as usize + self.load_bias))
     }
     
     // token 会按照 satp CSR 格式要求 构造一个无符号 64 位无符号整数，使得其分页模式为 SV39 ，
@@ -308,95 +969,600 @@ impl MemorySet {
     pub fn activate(&self) {
         // 生成token,也就是生成根页表的token,取地址号拼上标志位
         let satp = self.page_table.token();
-        // 放进satp
+        // 放进satp并刷新TLB。两条指令放在同一个asm块里，中间不会插入别的访存；
+        // 没有 nomem 选项，编译器把这个块当作读写任意内存，前后的访存都不会被挪过它。
+        // token 中ASID为0，所有地址空间共用同一个ASID，所以sfence.vma不带参数刷新全部TLB项；
+        // 以后给每个地址空间分配ASID时，可以改为只刷新该ASID的项
         unsafe {
-            satp::write(satp);
-            core::arch::asm!("sfence.vma");
+            core::arch::asm!(
+                "csrw satp, {satp}",
+                "sfence.vma",
+                satp = in(reg) satp,
+                options(nostack),
+            );
         }
     }
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
         self.page_table.translate(vpn)
     }
-}
-
-// 我们以逻辑段 MapArea 为单位描述一段连续地址的虚拟内存。
-// 所谓逻辑段，就是指地址区间中的一段实际可用（即 MMU 通过查多级页表可以正确完成地址转换）的地址连续的虚拟地址区间，
-// 该区间内包含的所有虚拟页面都以一种相同的方式映射到物理页帧，具有可读/可写/可执行等属性。
-pub struct MapArea {
-    vpn_range: VPNRange, // 描述一段虚拟页号的连续区间，表示该逻辑段在地址区间中的位置和长度。
-    data_frames: BTreeMap<VirtPageNum, FrameTracker>, // 当逻辑段采用 MapType::Framed 方式映射到物理内存的时候， 
-    // data_frames 是一个保存了该逻辑段内的每个虚拟页面和它被映射到的物理页帧 FrameTracker 的一个键值对容器 BTreeMap 中，
-    // 这些物理页帧被用来存放实际内存数据而不是作为多级页表中的中间节点。
-    map_type: MapType, // 物理页帧与虚拟页之间的映射关系，有恒等映射（S级）和依靠页表映射（U级）两种
-    map_perm: MapPermission, // 控制该逻辑段的访问方式，它是页表项标志位 PTEFlags 的一个子集，仅保留 U/R/W/X 四个标志位
-}
-
-impl MapArea {
 
-    // new 方法可以新建一个逻辑段结构体，注意传入的起始/终止虚拟地址会分别被
-    // 下取整/上取整为虚拟页号并传入迭代器 vpn_range 中
-    pub fn new(
-        start_va: VirtAddr,
-        end_va: VirtAddr,
-        map_type: MapType,
-        map_perm: MapPermission,
-    ) -> Self {
-        let start_vpn: VirtPageNum = start_va.floor();
-        let end_vpn: VirtPageNum = end_va.ceil();
-        Self {
-            vpn_range: VPNRange::new(start_vpn, end_vpn),
-            data_frames: BTreeMap::new(),
-            map_type,
-            map_perm,
+    // 检查逻辑段与页表是否一致：Framed 逻辑段中已分配的页面要映射到记录的页帧，延迟分配的页面不能有页表项，
+    // Identical 逻辑段要恒等映射；页表中除跳板和trap上下文之外的叶子页表项都要属于某个逻辑段。返回第一个出错的页号
+    pub fn check_consistency(&self) -> Result<(), VirtPageNum> {
+        for area in self.areas.iter() {
+            for vpn in area.vpn_range {
+                let pte = self.page_table.translate(vpn).filter(|pte| pte.is_valid());
+                let expected = match area.map_type {
+                    MapType::Framed => area.data_frames.get(&vpn).map(|frame| frame.ppn),
+                    MapType::Identical | MapType::IdenticalHuge => Some(PhysPageNum(vpn.0)),
+                };
+                if pte.map(|pte| pte.ppn()) != expected {
+                    return Err(vpn);
+                }
+            }
         }
-    }
-
-    // 对逻辑段中的单个虚拟页面进行映射, 添加到多级页表中
-    pub fn map_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        let ppn: PhysPageNum;
-        match self.map_type {
-            MapType::Identical => {
-                ppn = PhysPageNum(vpn.0);
+        let trampoline_vpn = VirtAddr::from(TRAMPOLINE).floor();
+        let trap_cx_vpn = VirtAddr::from(TRAP_CONTEXT).floor();
+        for (vpn, _) in self.page_table.enumerate() {
+            if vpn == trampoline_vpn || vpn == trap_cx_vpn {
+                continue;
             }
-            MapType::Framed => {
-                let frame = frame_alloc().unwrap();
-                ppn = frame.ppn;
-                self.data_frames.insert(vpn, frame);
+            if !self.areas.iter().any(|area| {
+                area.vpn_range.get_start() <= vpn && vpn < area.vpn_range.get_end()
+            }) {
+                return Err(vpn);
             }
         }
-        let pte_flags = PTEFlags::from_bits(self.map_perm.bits).unwrap();
-        page_table.map(vpn, ppn, pte_flags);
+        Ok(())
     }
-    #[allow(unused)]
-    // 对逻辑段中的单个虚拟页面进行映射, 从多级页表中删除
-    pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
-        #[allow(clippy::single_match)]
-        match self.map_type {
-            MapType::Framed => {
-                self.data_frames.remove(&vpn);
+
+    // 深拷贝一个地址空间：新的页表，每个Framed逻辑段分配新的页帧并逐页复制数据，跳板页重新映射。
+    // 页面保留原来的权限（包括mprotect改过的），写时复制的页面复制之后就是私有的，恢复写权限
+    pub fn clone_from(src: &MemorySet) -> Self {
+        let mut memory_set = Self::new_bare();
+        memory_set.map_trampoline();
+        memory_set.rlimit_as = src.rlimit_as;
+        memory_set.load_bias = src.load_bias;
+        memory_set.brk_base = src.brk_base;
+        memory_set.brk = src.brk;
+        memory_set.stack_bottom = src.stack_bottom;
+        for area in src.areas.iter() {
+            let mut new_area = MapArea::from_another(area);
+            match area.map_type {
+                MapType::Identical | MapType::IdenticalHuge => {
+                    new_area.map(&mut memory_set.page_table).unwrap()
+                }
+                MapType::Framed => {
+                    // 延迟分配还没访问过的页面在新地址空间里同样不映射
+                    for vpn in area.data_frames.keys() {
+                        let src_pte = src.translate(*vpn).unwrap();
+                        if area.is_zero_mapped(*vpn) {
+                            new_area.map_zero_one(&mut memory_set.page_table, *vpn).unwrap();
+                        } else {
+                            new_area.map_one(&mut memory_set.page_table, *vpn).unwrap();
+                            let dst_ppn = memory_set.translate(*vpn).unwrap().ppn();
+                            dst_ppn
+                                .get_bytes_array()
+                                .copy_from_slice(src_pte.ppn().get_bytes_array());
+                        }
+                        let mut flags = src_pte.flags();
+                        if src_pte.is_cow() {
+                            flags = flags - PTEFlags::COW | PTEFlags::W;
+                        }
+                        memory_set.page_table.set_flags(*vpn, flags);
+                    }
+                }
             }
-            _ => {}
+            memory_set.areas.push(new_area);
         }
-        page_table.unmap(vpn);
+        memory_set.update_peak();
+        memory_set
     }
 
-    // 将当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的多级页表中加入
-    // 遍历逻辑段中的所有虚拟页面，并以每个虚拟页面为单位依次在多级页表中进行键值对的插入
-    pub fn map(&mut self, page_table: &mut PageTable) {
-        for vpn in self.vpn_range {
-            self.map_one(page_table, vpn);
-        }
+    // 复制出一个新的用户地址空间（为fork准备），Framed页面以写时复制的方式共享页帧：
+    // 双方的可写页面都改为只读并打上COW标记，谁先写谁在缺页时复制。trap上下文这类内核直接按物理地址
+    // 写的页面（没有U标志）绕过页表，不能共享，立即复制
+    pub fn duplicate(&mut self) -> MemorySet {
+        let mut child = MemorySet::new_bare();
+        child.map_trampoline();
+        child.rlimit_as = self.rlimit_as;
+        child.load_bias = self.load_bias;
+        child.brk_base = self.brk_base;
+        child.brk = self.brk;
+        child.stack_bottom = self.stack_bottom;
+        let page_table = &mut self.page_table;
+        for area in self.areas.iter() {
+            let mut new_area = MapArea::from_another(area);
+            if area.map_type != MapType::Framed {
+                new_area.map(&mut child.page_table).unwrap();
+            } else if !area.map_perm.contains(MapPermission::U) {
+                for (vpn, frame) in area.data_frames.iter() {
+                    new_area.map_one(&mut child.page_table, *vpn).unwrap();
+                    new_area.data_frames[vpn]
+                        .ppn
+                        .get_bytes_array()
+                        .copy_from_slice(frame.ppn.get_bytes_array());
+                }
+            } else {
+                for (vpn, frame) in area.data_frames.iter() {
+                    let mut flags = page_table.translate(*vpn).unwrap().flags();
+                    if flags.contains(PTEFlags::W) {
+                        flags = flags - PTEFlags::W | PTEFlags::COW;
+                        page_table.set_flags(*vpn, flags);
+                    }
+                    child.page_table.map(*vpn, frame.ppn, flags).unwrap();
+                    new_area.data_frames.insert(*vpn, frame.share());
+                }
+            }
+            child.areas.push(new_area);
+        }
+        child.update_peak();
+        // 自己的页表项被改为只读，可能还在TLB里
+        unsafe {
+            core::arch::asm!("sfence.vma");
+        }
+        child
+    }
+
+    // 只读地访问页表，给调试和检查工具使用，修改页表仍然只能通过地址空间自己的方法
+    pub fn page_table(&self) -> &PageTable {
+        &self.page_table
+    }
+
+    // 遍历地址空间中的所有逻辑段
+    pub fn iter_areas(&self) -> core::slice::Iter<'_, MapArea> {
+        self.areas.iter()
+    }
+
+    // 对地址空间做快照，深拷贝所有 Framed 逻辑段的页帧内容和逻辑段布局
+    pub fn snapshot(&self) -> MemSnapshot {
+        let mut areas = Vec::new();
+        for map_area in self.areas.iter() {
+            let mut frames = Vec::new();
+            for (vpn, frame) in map_area.data_frames.iter() {
+                frames.push((*vpn, frame.ppn.get_bytes_array().to_vec()));
+            }
+            areas.push(AreaSnapshot {
+                start_vpn: map_area.vpn_range.get_start(),
+                end_vpn: map_area.vpn_range.get_end(),
+                map_type: map_area.map_type,
+                map_perm: map_area.map_perm,
+                frames,
+            });
+        }
+        MemSnapshot { areas }
+    }
+
+    // 将页帧内容恢复到快照时的状态，要求逻辑段布局与快照时一致，否则拒绝恢复并返回-1
+    pub fn restore(&mut self, snap: &MemSnapshot) -> isize {
+        if self.areas.len() != snap.areas.len() {
+            return -1;
+        }
+        for (map_area, area_snap) in self.areas.iter().zip(snap.areas.iter()) {
+            if map_area.vpn_range.get_start() != area_snap.start_vpn
+                || map_area.vpn_range.get_end() != area_snap.end_vpn
+                || map_area.map_type != area_snap.map_type
+                || map_area.map_perm != area_snap.map_perm
+                || map_area.data_frames.len() != area_snap.frames.len()
+            {
+                return -1;
+            }
+            if area_snap
+                .frames
+                .iter()
+                .any(|(vpn, _)| !map_area.data_frames.contains_key(vpn))
+            {
+                return -1;
+            }
+        }
+        // 布局检查通过后再统一写回，避免恢复到一半失败
+        for (map_area, area_snap) in self.areas.iter().zip(snap.areas.iter()) {
+            for (vpn, bytes) in area_snap.frames.iter() {
+                let frame = map_area.data_frames.get(vpn).unwrap();
+                frame.ppn.get_bytes_array().copy_from_slice(bytes);
+            }
+        }
+        0
+    }
+}
+
+// 单个逻辑段的快照，记录布局和每个页面的数据副本
+struct AreaSnapshot {
+    start_vpn: VirtPageNum,
+    end_vpn: VirtPageNum,
+    map_type: MapType,
+    map_perm: MapPermission,
+    frames: Vec<(VirtPageNum, Vec<u8>)>,
+}
+
+// 地址空间快照，用于单任务的检查点与恢复
+pub struct MemSnapshot {
+    areas: Vec<AreaSnapshot>,
+}
+
+// ELF64文件头的长度
+const ELF64_HEADER_SIZE: usize = 64;
+
+// 分块存放的ELF文件，只记下各块的引用，按偏移读取时在块之间拼接，不把整个文件复制到一起
+struct ElfChunks<'a> {
+    chunks: Vec<&'a [u8]>,
+}
+
+impl<'a> ElfChunks<'a> {
+    fn new(reader: impl Iterator<Item = &'a [u8]>) -> Self {
+        Self {
+            chunks: reader.collect(),
+        }
+    }
+
+    // 从文件偏移 offset 开始读满 dst，文件不够长时panic
+    fn read_at(&self, mut offset: usize, dst: &mut [u8]) {
+        let mut copied: usize = 0;
+        for chunk in self.chunks.iter() {
+            if copied == dst.len() {
+                break;
+            }
+            if offset >= chunk.len() {
+                offset -= chunk.len();
+                continue;
+            }
+            let n = (chunk.len() - offset).min(dst.len() - copied);
+            dst[copied..copied + n].copy_from_slice(&chunk[offset..offset + n]);
+            copied += n;
+            offset = 0;
+        }
+        assert_eq!(copied, dst.len(), "elf data truncated");
+    }
+
+    // 把ELF头和程序头表复制到一块连续的缓冲区里，交给 xmas_elf 解析
+    fn headers(&self) -> Vec<u8> {
+        let mut headers: Vec<u8> = Vec::new();
+        headers.resize(ELF64_HEADER_SIZE, 0);
+        self.read_at(0, &mut headers);
+        let ph_end = {
+            let header = xmas_elf::header::parse_header(&headers).unwrap();
+            header.pt2.ph_offset() as usize
+                + header.pt2.ph_entry_size() as usize * header.pt2.ph_count() as usize
+        };
+        if ph_end > headers.len() {
+            headers.resize(ph_end, 0);
+            self.read_at(0, &mut headers);
+        }
+        headers
+    }
+}
+
+// 我们以逻辑段 MapArea 为单位描述一段连续地址的虚拟内存。
+// 所谓逻辑段，就是指地址区间中的一段实际可用（即 MMU 通过查多级页表可以正确完成地址转换）的地址连续的虚拟地址区间，
+// 该区间内包含的所有虚拟页面都以一种相同的方式映射到物理页帧，具有可读/可写/可执行等属性。
+pub struct MapArea {
+    vpn_range: VPNRange, // 描述一段虚拟页号的连续区间，表示该逻辑段在地址区间中的位置和长度。
+    data_frames: BTreeMap<VirtPageNum, FrameTracker>, // 当逻辑段采用 MapType::Framed 方式映射到物理内存的时候， 
+    // data_frames 是一个保存了该逻辑段内的每个虚拟页面和它被映射到的物理页帧 FrameTracker 的一个键值对容器 BTreeMap 中，
+    // 这些物理页帧被用来存放实际内存数据而不是作为多级页表中的中间节点。
+    map_type: MapType, // 物理页帧与虚拟页之间的映射关系，有恒等映射（S级）和依靠页表映射（U级）两种
+    map_perm: MapPermission, // 控制该逻辑段的访问方式，它是页表项标志位 PTEFlags 的一个子集，仅保留 U/R/W/X 四个标志位
+    locked: bool, // 是否锁定在内存中，页面回收扫描（age_pages）会跳过锁定的逻辑段
+}
+
+impl MapArea {
+
+    // new 方法可以新建一个逻辑段结构体，注意传入的起始/终止虚拟地址会分别被
+    // 下取整/上取整为虚拟页号并传入迭代器 vpn_range 中
+    pub fn new(
+        start_va: VirtAddr,
+        end_va: VirtAddr,
+        map_type: MapType,
+        map_perm: MapPermission,
+    ) -> Self {
+        let start_vpn: VirtPageNum = start_va.floor();
+        let end_vpn: VirtPageNum = end_va.ceil();
+        let mut map_perm = map_perm;
+        // W^X模式下可执行的页面去掉写权限，写代码段会触发缺页
+        if WX_ENFORCE && map_type == MapType::Framed && map_perm.contains(MapPermission::X) {
+            map_perm.remove(MapPermission::W);
+        }
+        Self {
+            vpn_range: VPNRange::new(start_vpn, end_vpn),
+            data_frames: BTreeMap::new(),
+            map_type,
+            map_perm,
+            locked: false,
+        }
+    }
+
+    // 按另一个逻辑段的区间、映射方式和权限新建一个逻辑段，还没有映射任何页面
+    pub fn from_another(another: &MapArea) -> Self {
+        Self {
+            vpn_range: another.vpn_range,
+            data_frames: BTreeMap::new(),
+            map_type: another.map_type,
+            map_perm: another.map_perm,
+            locked: another.locked,
+        }
+    }
+
+    // 逻辑段的起始虚拟页号
+    pub fn start_vpn(&self) -> VirtPageNum {
+        self.vpn_range.get_start()
+    }
+    // 逻辑段的结束虚拟页号（不含）
+    pub fn end_vpn(&self) -> VirtPageNum {
+        self.vpn_range.get_end()
+    }
+    pub fn map_type(&self) -> MapType {
+        self.map_type
+    }
+    pub fn map_perm(&self) -> MapPermission {
+        self.map_perm
+    }
+    // 逻辑段是否被锁定在内存中，不参与页面回收
+    pub fn locked(&self) -> bool {
+        self.locked
+    }
+    // 逻辑段实际占用的数据页帧数，映射到共享零页的页面不算
+    pub fn frame_count(&self) -> usize {
+        self.data_frames
+            .values()
+            .filter(|frame| frame.ppn != zero_frame())
+            .count()
+    }
+
+    // 逻辑段中有页表项的页面数：Framed 逻辑段只算已经分配过的（包括还在共享零页上的），恒等映射的页面都有页表项
+    pub fn mapped_count(&self) -> usize {
+        match self.map_type {
+            MapType::Framed => self.data_frames.len(),
+            MapType::Identical | MapType::IdenticalHuge => self.vpn_range.len(),
+        }
+    }
+
+    // 页面是否还映射在共享零页上
+    pub fn is_zero_mapped(&self, vpn: VirtPageNum) -> bool {
+        self.data_frames
+            .get(&vpn)
+            .map_or(false, |frame| frame.ppn == zero_frame())
+    }
+
+    // 把页面去掉写权限后映射到共享零页，第一次写时由 commit_one 换成私有页帧
+    pub fn map_zero_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<(), PageTableError> {
+        assert_eq!(self.map_type, MapType::Framed);
+        let mut map_perm = self.map_perm;
+        map_perm.remove(MapPermission::W);
+        page_table.map(vpn, zero_frame(), PTEFlags::from_bits(map_perm.bits as u16).unwrap())?;
+        self.data_frames.insert(vpn, share_zero_frame());
+        Ok(())
+    }
+
+    // 处理写时复制页面的写缺页：页帧仍被别的地址空间共享时复制出私有页帧，已经只剩自己时直接恢复可写
+    // 页面不是写时复制的返回false
+    fn cow_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        let pte = match page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.is_cow() => pte,
+            _ => return false,
+        };
+        let flags = pte.flags() - PTEFlags::COW | PTEFlags::W;
+        if cow_refcount(pte.ppn()) > 1 {
+            let frame = match frame_alloc() {
+                Some(frame) => frame,
+                None => return false,
+            };
+            frame
+                .ppn
+                .get_bytes_array()
+                .copy_from_slice(pte.ppn().get_bytes_array());
+            page_table.unmap(vpn);
+            page_table.map(vpn, frame.ppn, flags).unwrap();
+            // 换下来的共享页帧引用计数减一
+            self.data_frames.insert(vpn, frame);
+        } else {
+            page_table.set_flags(vpn, flags);
+        }
+        // 原来的只读页表项可能还在TLB里
+        unsafe {
+            core::arch::asm!("sfence.vma");
+        }
+        true
+    }
+
+    // 为页面分配私有页帧：还没映射的直接映射，映射在零页上的先解除再映射，已有私有页帧时返回false
+    fn commit_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) -> bool {
+        if self.is_zero_mapped(vpn) {
+            self.unmap_one(page_table, vpn);
+            if self.map_one(page_table, vpn).is_err() {
+                // 没有页帧了，放回零页，页表节点还在，不需要新的页帧
+                self.map_zero_one(page_table, vpn).unwrap();
+                return false;
+            }
+            // 原来的只读页表项可能还在TLB里
+            unsafe {
+                core::arch::asm!("sfence.vma");
+            }
+            return true;
+        }
+        !self.data_frames.contains_key(&vpn) && self.map_one(page_table, vpn).is_ok()
+    }
+
+    // 对逻辑段中的单个虚拟页面进行映射, 添加到多级页表中，页面已被映射或页帧不够时返回错误
+    pub fn map_one(
+        &mut self,
+        page_table: &mut PageTable,
+        vpn: VirtPageNum,
+    ) -> Result<(), PageTableError> {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        match self.map_type {
+            MapType::Identical | MapType::IdenticalHuge => {
+                page_table.map(vpn, PhysPageNum(vpn.0), pte_flags)?;
+            }
+            MapType::Framed => {
+                // 映射成功后才记录页帧，失败时页帧随 frame 一起被回收
+                let frame = frame_alloc().ok_or(PageTableError::OutOfFrames)?;
+                page_table.map(vpn, frame.ppn, pte_flags)?;
+                self.data_frames.insert(vpn, frame);
+            }
+        }
+        Ok(())
+    }
+    #[allow(unused)]
+    // 对逻辑段中的单个虚拟页面进行映射, 从多级页表中删除
+    pub fn unmap_one(&mut self, page_table: &mut PageTable, vpn: VirtPageNum) {
+        #[allow(clippy::single_match)]
+        match self.map_type {
+            MapType::Framed => {
+                // 延迟分配且还没被访问过的页面没有页表项
+                if self.data_frames.remove(&vpn).is_none() {
+                    return;
+                }
+            }
+            _ => {}
+        }
+        page_table.unmap(vpn);
+    }
+
+    // 把逻辑段的结尾向后扩展到new_end，为新增的页面建立映射
+    // 中途映射失败时撤销新增的页面，逻辑段保持原样
+    pub fn append_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) -> Result<(), PageTableError> {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        for vpn in VPNRange::new(end, new_end) {
+            if let Err(err) = self.map_one(page_table, vpn) {
+                for mapped_vpn in VPNRange::new(end, vpn) {
+                    self.unmap_one(page_table, mapped_vpn);
+                }
+                return Err(err);
+            }
+        }
+        self.vpn_range = VPNRange::new(start, new_end);
+        Ok(())
+    }
+
+    // 把逻辑段的结尾收缩到new_end，解除多出的页面的映射并归还页帧
+    pub fn shrink_to(&mut self, page_table: &mut PageTable, new_end: VirtPageNum) {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        for vpn in VPNRange::new(new_end, end) {
+            self.unmap_one(page_table, vpn);
+        }
+        self.vpn_range = VPNRange::new(start, new_end);
+    }
+
+    // 在页号at处把逻辑段一分为二：自身保留[start, at)，返回[at, end)，页帧随页面归属，映射不变
+    pub fn split_off(&mut self, at: VirtPageNum) -> MapArea {
+        let (start, end) = (self.vpn_range.get_start(), self.vpn_range.get_end());
+        assert!(start < at && at < end, "split point {:?} is outside the area", at);
+        self.vpn_range = VPNRange::new(start, at);
+        MapArea {
+            vpn_range: VPNRange::new(at, end),
+            data_frames: self.data_frames.split_off(&at),
+            map_type: self.map_type,
+            map_perm: self.map_perm,
+            locked: self.locked,
+        }
+    }
+
+    // 将当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的多级页表中加入
+    // 遍历逻辑段中的所有虚拟页面，并以每个虚拟页面为单位依次在多级页表中进行键值对的插入
+    // 中途遇到已被映射的页面时，撤销本次已映射的页面并返回错误
+    pub fn map(&mut self, page_table: &mut PageTable) -> Result<(), PageTableError> {
+        if self.map_type == MapType::IdenticalHuge {
+            return self.map_huge(page_table);
+        }
+        for vpn in self.vpn_range {
+            if let Err(err) = self.map_one(page_table, vpn) {
+                for mapped_vpn in VPNRange::new(self.vpn_range.get_start(), vpn) {
+                    self.unmap_one(page_table, mapped_vpn);
+                }
+                return Err(err);
+            }
+        }
+        Ok(())
     }
 
     #[allow(unused)]
     // 将当前逻辑段到物理内存的映射从传入的该逻辑段所属的地址空间的多级页表中删除
     // 遍历逻辑段中的所有虚拟页面，并以每个虚拟页面为单位依次在多级页表中进行键值对的删除
     pub fn unmap(&mut self, page_table: &mut PageTable) {
+        if self.map_type == MapType::IdenticalHuge {
+            let (huge_start, huge_end) = self.huge_range();
+            for vpn in self.vpn_range {
+                if vpn < huge_start || huge_end <= vpn {
+                    self.unmap_one(page_table, vpn);
+                } else if vpn.0 % HUGE_FRAME_PAGES == 0 {
+                    page_table.unmap_huge(vpn);
+                }
+            }
+            return;
+        }
         for vpn in self.vpn_range {
             self.unmap_one(page_table, vpn);
         }
     }
 
+    // IdenticalHuge 逻辑段中完整的、按 512 页对齐的大页所在的区间，区间外首尾不足一个大页的部分用普通页面映射
+    fn huge_range(&self) -> (VirtPageNum, VirtPageNum) {
+        let start = align_up(self.vpn_range.get_start().0, HUGE_FRAME_PAGES);
+        let end = align_down(self.vpn_range.get_end().0, HUGE_FRAME_PAGES).max(start);
+        (VirtPageNum(start), VirtPageNum(end))
+    }
+
+    // 以大页为主恒等映射 IdenticalHuge 逻辑段，中途失败时撤销已经建立的映射并返回错误
+    fn map_huge(&mut self, page_table: &mut PageTable) -> Result<(), PageTableError> {
+        let pte_flags = PTEFlags::from_bits(self.map_perm.bits as u16).unwrap();
+        let (huge_start, huge_end) = self.huge_range();
+        let mut vpn = self.vpn_range.get_start();
+        while vpn < self.vpn_range.get_end() {
+            let result = if huge_start <= vpn && vpn < huge_end {
+                page_table.map_huge(vpn, PhysPageNum(vpn.0), pte_flags)
+            } else {
+                self.map_one(page_table, vpn)
+            };
+            if let Err(err) = result {
+                // 已经映射的前半段按同样的方式拆成大页和普通页面，直接撤销
+                let mut mapped = MapArea {
+                    vpn_range: VPNRange::new(self.vpn_range.get_start(), vpn),
+                    data_frames: BTreeMap::new(),
+                    map_type: self.map_type,
+                    map_perm: self.map_perm,
+                    locked: self.locked,
+                };
+                mapped.unmap(page_table);
+                return Err(err);
+            }
+            vpn = if huge_start <= vpn && vpn < huge_end {
+                VirtPageNum(vpn.0 + HUGE_FRAME_PAGES)
+            } else {
+                VirtPageNum(vpn.0 + 1)
+            };
+        }
+        Ok(())
+    }
+
+    // 与 copy_data 相同，但数据是分块存放的ELF文件中从 offset 开始的 len 字节，逐页直接复制到页帧上
+    // 数据从第一页的 page_offset 处开始放；放数据的页面不能是共享零页，否则所有地址空间的零页都会被写坏
+    fn copy_chunks(
+        &mut self,
+        page_table: &mut PageTable,
+        elf_data: &ElfChunks,
+        offset: usize,
+        mut page_offset: usize,
+        len: usize,
+    ) {
+        assert_eq!(self.map_type, MapType::Framed);
+        let mut copied: usize = 0;
+        let mut current_vpn = self.vpn_range.get_start();
+        while copied < len {
+            let ppn = page_table.translate(current_vpn).unwrap().ppn();
+            assert_ne!(ppn, zero_frame(), "elf data copied into the shared zero frame");
+            let n = (len - copied).min(PAGE_SIZE - page_offset);
+            let dst = &mut ppn.get_bytes_array()[page_offset..page_offset + n];
+            elf_data.read_at(offset + copied, dst);
+            copied += n;
+            page_offset = 0;
+            current_vpn.step();
+        }
+    }
+
     // 将切片 data 中的数据拷贝到当前逻辑段实际被内核放置在的各物理页帧上，从而在地址空间中通过该逻辑段就能访问这些数据。
     pub fn copy_data(&mut self, page_table: &mut PageTable, data: &[u8]) {
         assert_eq!(self.map_type, MapType::Framed);
@@ -425,15 +1591,37 @@ impl MapArea {
 pub enum MapType {
     Identical,
     Framed,
+    // 恒等映射，对齐的部分用 2MiB 大页，用于内核的物理内存窗口
+    IdenticalHuge,
 }
 
+// mmap的port参数中表示锁定页面的位（类似mlock），锁定的逻辑段不会被页面回收换出
+// 取Linux中MAP_LOCKED的值，紧挨权限位的第3位必须保持非法（ch4_mmap3要求port为0b1011时失败）
+pub const MAP_LOCKED: usize = 1 << 13;
+
 bitflags! {
-    // 逻辑段的访问方式
+    // 逻辑段的访问方式，G 表示全局映射，需要时由 map_one 一并写入页表项
     pub struct MapPermission: u8 {
         const R = 1 << 1;
         const W = 1 << 2;
         const X = 1 << 3;
         const U = 1 << 4;
+        const G = 1 << 5;
+    }
+}
+
+// 以 rwxu 的形式打印访问方式，未设置的位用 - 表示
+impl Display for MapPermission {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (perm, c) in [
+            (MapPermission::R, 'r'),
+            (MapPermission::W, 'w'),
+            (MapPermission::X, 'x'),
+            (MapPermission::U, 'u'),
+        ] {
+            f.write_char(if self.contains(perm) { c } else { '-' })?;
+        }
+        Ok(())
     }
 }
 
@@ -443,7 +1631,7 @@ bitflags! {
 #[allow(unused)]
 // 测试
 pub fn remap_test() {
-    let mut kernel_space = KERNEL_SPACE.lock();
+    let kernel_space = KERNEL_SPACE.read();
     let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
     let mid_rodata: VirtAddr = ((srodata as usize + erodata as usize) / 2).into();
     let mid_data: VirtAddr = ((sdata as usize + edata as usize) / 2).into();
@@ -462,5 +1650,1281 @@ pub fn remap_test() {
         .translate(mid_data.floor())
         .unwrap()
         .executable());
+    assert!(check_phys_window(&kernel_space));
     info!("remap_test passed!");
 }
+
+// 不panic地检查内核地址空间：各段权限、物理内存窗口、跳板映射，以及逻辑段与页表一致，返回第一个失败的检查
+pub fn check_kernel_space() -> Result<(), &'static str> {
+    let kernel_space = KERNEL_SPACE.read();
+    let pte_of = |addr: usize| {
+        kernel_space
+            .translate(VirtAddr::from(addr).floor())
+            .filter(|pte| pte.is_valid())
+    };
+    let mid = |start: usize, end: usize| (start + end) / 2;
+    match pte_of(mid(stext as usize, etext as usize)) {
+        Some(pte) if pte.readable() && pte.executable() && !pte.writable() => {}
+        _ => return Err("text is not R-X"),
+    }
+    match pte_of(mid(srodata as usize, erodata as usize)) {
+        Some(pte) if pte.readable() && !pte.writable() && !pte.executable() => {}
+        _ => return Err("rodata is not R--"),
+    }
+    match pte_of(mid(sdata as usize, edata as usize)) {
+        Some(pte) if pte.readable() && pte.writable() && !pte.executable() => {}
+        _ => return Err("data is not RW-"),
+    }
+    if !check_phys_window(&kernel_space) {
+        return Err("physical memory window is not identically mapped RW-");
+    }
+    match pte_of(TRAMPOLINE) {
+        Some(pte)
+            if pte.ppn() == PhysAddr::from(strampoline as usize).floor()
+                && pte.readable()
+                && pte.executable()
+                && !pte.writable()
+                && !pte.user_accessible() => {}
+        _ => return Err("trampoline is not mapped R-X to strampoline"),
+    }
+    if kernel_space.check_consistency().is_err() {
+        return Err("areas and page table disagree");
+    }
+    Ok(())
+}
+
+// 用户给出的区间 [va_start, va_start + len) 的结尾，相加溢出或伸到trap上下文和跳板（两者中较低的那个）时返回None
+// 用户传入的区间都先经过这里，之后对结尾上取整不会溢出；munmap 跨过多个逻辑段时也不会连同trap上下文一起释放
+fn user_range_end(va_start: VirtAddr, len: usize) -> Option<VirtAddr> {
+    va_start
+        .checked_add(len)
+        .filter(|va_end| va_end.0 <= TRAP_CONTEXT.min(TRAMPOLINE))
+}
+
+// 把mmap、mprotect的port参数（第0、1、2位分别为R、W、X）转换为用户逻辑段的权限
+fn perm_from_port(port: usize) -> MapPermission {
+    let mut map_perm = MapPermission::U;
+    if port & 0b0000_0001 == 0b0000_0001 {
+        map_perm |= MapPermission::R;
+    }
+    if port & 0b0000_0010 == 0b0000_0010 {
+        map_perm |= MapPermission::W;
+    }
+    if port & 0b0000_0100 == 0b0000_0100 {
+        map_perm |= MapPermission::X;
+    }
+    map_perm
+}
+
+// 检查跳板和trap上下文的虚拟地址配置：各自页对齐，不在同一页，是SV39的合法地址（第38位以上全0或全1），
+// 而且不落在内核镜像和物理内存窗口 [stext, MEMORY_END) 的恒等映射里。mm::init 在建立内核地址空间之前调用
+pub fn check_layout(trampoline: usize, trap_context: usize) -> Result<(), String> {
+    let trampoline_vpn = check_page_aligned(trampoline, "TRAMPOLINE")?;
+    let trap_cx_vpn = check_page_aligned(trap_context, "TRAP_CONTEXT")?;
+    if trampoline_vpn == trap_cx_vpn {
+        return Err(format!(
+            "TRAMPOLINE and TRAP_CONTEXT overlap at {:#x}, check config.rs",
+            trampoline
+        ));
+    }
+    for (addr, name) in [(trampoline, "TRAMPOLINE"), (trap_context, "TRAP_CONTEXT")] {
+        let upper = addr >> (VA_WIDTH_SV39 - 1);
+        if upper != 0 && upper != usize::MAX >> (VA_WIDTH_SV39 - 1) {
+            return Err(format!(
+                "{} = {:#x} is not a valid SV39 address, check config.rs",
+                name, addr
+            ));
+        }
+    }
+    if (stext as usize..MEMORY_END).contains(&trampoline) {
+        return Err(format!(
+            "TRAMPOLINE = {:#x} overlaps the identically mapped kernel memory, check config.rs",
+            trampoline
+        ));
+    }
+    Ok(())
+}
+
+// 检查物理内存窗口 [ekernel, MEMORY_END) 是否被恒等映射为可读可写、不可执行
+// 抽查窗口的开头、中间和结尾三个页面
+fn check_phys_window(memory_set: &MemorySet) -> bool {
+    let start = PhysAddr::from(ekernel as usize).ceil().0;
+    let end = PhysAddr::from(MEMORY_END).floor().0;
+    [start, (start + end) / 2, end - 1].iter().all(|&page| {
+        match memory_set.translate(VirtPageNum(page)) {
+            Some(pte) => {
+                pte.is_valid()
+                    && pte.ppn() == PhysPageNum(page)
+                    && pte.readable()
+                    && pte.writable()
+                    && !pte.executable()
+            }
+            None => false,
+        }
+    })
+}
+
+#[allow(unused)]
+// 测试activate之后新页表立即生效：切换到一个多映射了一页的内核地址空间，直接按虚拟地址读到这一页的内容
+// 必须在启动栈上、第一个任务运行之前调用，新建的内核地址空间里没有各任务的内核栈
+pub fn activate_test() {
+    const MAGIC: usize = 0x5a5a_a5a5_1234_5678;
+    let test_va: usize = 0x4000_0000;
+    let frame = frame_alloc().unwrap();
+    *frame.ppn.get_mut::<usize>() = MAGIC;
+    let mut memory_set = MemorySet::new_kernel();
+    memory_set
+        .page_table
+        .map(VirtAddr::from(test_va).floor(), frame.ppn, PTEFlags::R | PTEFlags::W)
+        .unwrap();
+    memory_set.activate();
+    assert_eq!(satp::read().bits(), memory_set.token());
+    let value = unsafe { (test_va as *const usize).read_volatile() };
+    KERNEL_SPACE.read().activate();
+    assert_eq!(satp::read().bits(), KERNEL_SPACE.read().token());
+    assert_eq!(value, MAGIC);
+    info!("activate_test passed!");
+}
+
+#[allow(unused)]
+// 测试物理内存窗口检查：内核地址空间能通过，缺少窗口的地址空间不能通过
+pub fn phys_window_test() {
+    assert!(check_phys_window(&KERNEL_SPACE.read()));
+    assert!(!check_phys_window(&MemorySet::new_bare()));
+    info!("phys_window_test passed!");
+}
+
+#[allow(unused)]
+// 测试快照与恢复
+pub fn snapshot_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start_va: VirtAddr = 0x1000_0000.into();
+    let end_va: VirtAddr = (0x1000_0000 + 2 * PAGE_SIZE).into();
+    memory_set.insert_framed_area(start_va, end_va, MapPermission::R | MapPermission::W);
+    let bytes = memory_set
+        .translate(start_va.floor())
+        .unwrap()
+        .ppn()
+        .get_bytes_array();
+    bytes[0] = 0x5a;
+    bytes[PAGE_SIZE - 1] = 0xa5;
+    let snap = memory_set.snapshot();
+    bytes[0] = 0;
+    bytes[PAGE_SIZE - 1] = 0;
+    assert_eq!(memory_set.restore(&snap), 0);
+    assert_eq!(bytes[0], 0x5a);
+    assert_eq!(bytes[PAGE_SIZE - 1], 0xa5);
+    // 布局改变后应拒绝恢复
+    memory_set.insert_framed_area(
+        end_va,
+        (usize::from(end_va) + PAGE_SIZE).into(),
+        MapPermission::R,
+    );
+    assert_eq!(memory_set.restore(&snap), -1);
+    info!("snapshot_test passed!");
+}
+
+#[allow(unused)]
+// 测试将页面设为保护页
+pub fn guard_page_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), 0);
+    assert!(!memory_set.is_guard_page(start.into()));
+    assert_eq!(memory_set.set_permissions(start, PAGE_SIZE, 0), 0);
+    assert!(memory_set.is_guard_page(start.into()));
+    // 保护页仍然有效，能区别于未映射的页面
+    assert!(memory_set.translate(VirtAddr::from(start).floor()).unwrap().is_valid());
+    assert!(!memory_set.is_guard_page((start + PAGE_SIZE).into()));
+    assert_eq!(memory_set.set_permissions(start + PAGE_SIZE, PAGE_SIZE, 0), -1);
+    info!("guard_page_test passed!");
+}
+
+#[allow(unused)]
+// 测试内核映射带有全局位，而应用的映射没有
+pub fn global_bit_test() {
+    let kernel_space = KERNEL_SPACE.read();
+    let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
+    assert!(kernel_space.translate(mid_text.floor()).unwrap().is_global());
+    drop(kernel_space);
+    let (memory_set, _, entry_point) = MemorySet::from_elf(crate::loader::get_app_data(0));
+    let entry_va: VirtAddr = entry_point.into();
+    assert!(!memory_set.translate(entry_va.floor()).unwrap().is_global());
+    info!("global_bit_test passed!");
+}
+
+#[allow(unused)]
+// 测试清空地址空间后页帧被回收、跳板仍在，并且可以重新载入应用
+pub fn clear_test() {
+    let elf_data = crate::loader::get_app_data(0);
+    let (mut memory_set, _, _) = MemorySet::from_elf(elf_data);
+    let remain_before = frame_remain_num();
+    memory_set.clear();
+    assert!(frame_remain_num() > remain_before);
+    assert!(memory_set
+        .translate(VirtAddr::from(TRAMPOLINE).into())
+        .unwrap()
+        .is_valid());
+    assert!(memory_set
+        .translate(VirtAddr::from(TRAP_CONTEXT).into())
+        .map_or(true, |pte| !pte.is_valid()));
+    let (_, entry_point) = memory_set.reset_from_elf(elf_data);
+    let entry_va: VirtAddr = entry_point.into();
+    assert!(memory_set.translate(entry_va.floor()).unwrap().is_valid());
+    info!("clear_test passed!");
+}
+
+#[allow(unused)]
+// 测试按起始页号移除逻辑段：两个Framed逻辑段只移除指定的那个，页帧归还，另一个不受影响
+pub fn remove_area_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let first: VirtAddr = 0x1000_0000.into();
+    let second: VirtAddr = 0x2000_0000.into();
+    let perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    memory_set.insert_framed_area(first, (first.0 + 2 * PAGE_SIZE).into(), perm);
+    memory_set.insert_framed_area(second, (second.0 + PAGE_SIZE).into(), perm);
+    let remain_before = frame_remain_num();
+    // 只有起始页号匹配才移除，落在逻辑段中间的页号不算
+    assert!(!memory_set.remove_area_with_start_vpn(VirtPageNum(first.floor().0 + 1)));
+    assert!(memory_set.remove_area_with_start_vpn(first.floor()));
+    assert_eq!(frame_remain_num(), remain_before + 2);
+    assert!(!memory_set.remove_area_with_start_vpn(first.floor()));
+    assert_eq!(memory_set.areas.len(), 1);
+    assert!(memory_set
+        .translate(first.floor())
+        .map_or(true, |pte| !pte.is_valid()));
+    assert!(memory_set.translate(second.floor()).unwrap().is_valid());
+    info!("remove_area_test passed!");
+}
+
+#[allow(unused)]
+// 测试munmap拆分逻辑段：从开头、中间、结尾各解除4页，剩下的页面仍可访问且数据不变，解除的页面不再有效；
+// 内核自己的（没有U标志的）逻辑段不受影响
+pub fn munmap_split_test() {
+    const PAGES: usize = 16;
+    let start: usize = 0x1000_0000;
+    let first = VirtAddr::from(start).floor();
+    let page = |i: usize| VirtPageNum(first.0 + i);
+    // (解除的起始页, 剩下的逻辑段数)
+    for (at, areas) in [(0, 1), (6, 2), (PAGES - 4, 1)] {
+        let mut memory_set = MemorySet::new_bare();
+        assert_eq!(memory_set.mmap(start, PAGES * PAGE_SIZE, 0b011), 0);
+        for i in 0..PAGES {
+            memory_set.translate(page(i)).unwrap().ppn().get_bytes_array()[0] = i as u8;
+        }
+        let remain_before = frame_remain_num();
+        // 范围内没有映射或没有对齐时不做任何改动
+        assert_eq!(memory_set.munmap(start + PAGES * PAGE_SIZE, 4 * PAGE_SIZE), 0);
+        assert_eq!(memory_set.munmap(start + at * PAGE_SIZE, 4 * PAGE_SIZE - 1), -1);
+        assert_eq!(memory_set.munmap(start + at * PAGE_SIZE, 4 * PAGE_SIZE), 4);
+        assert_eq!(frame_remain_num(), remain_before + 4);
+        assert_eq!(memory_set.areas.len(), areas);
+        for i in 0..PAGES {
+            let pte = memory_set.translate(page(i)).filter(|pte| pte.is_valid());
+            if (at..at + 4).contains(&i) {
+                assert!(pte.is_none(), "page {} should be unmapped", i);
+            } else {
+                let pte = pte.unwrap();
+                assert_eq!(pte.ppn().get_bytes_array()[0], i as u8);
+                assert!(pte.readable() && pte.writable() && pte.user_accessible());
+            }
+        }
+        assert_eq!(memory_set.mapped_bytes(), (PAGES - 4) * PAGE_SIZE);
+        // 解除后的空洞可以重新映射，剩下的部分也能整段解除
+        assert_eq!(memory_set.mmap(start + at * PAGE_SIZE, 4 * PAGE_SIZE, 0b011), 0);
+        assert_eq!(memory_set.munmap(start + at * PAGE_SIZE, 4 * PAGE_SIZE), 4);
+        if at > 0 {
+            assert_eq!(memory_set.munmap(start, at * PAGE_SIZE), at as isize);
+        }
+        if at + 4 < PAGES {
+            assert_eq!(
+                memory_set.munmap(start + (at + 4) * PAGE_SIZE, (PAGES - at - 4) * PAGE_SIZE),
+                (PAGES - at - 4) as isize
+            );
+        }
+        assert!(memory_set.areas.is_empty());
+    }
+    // 没有U标志的逻辑段既不被拆开也不被移除
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        start.into(),
+        (start + 4 * PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W,
+    );
+    assert_eq!(memory_set.munmap(start, 4 * PAGE_SIZE), 0);
+    assert_eq!(memory_set.munmap(start + PAGE_SIZE, PAGE_SIZE), 0);
+    assert_eq!(memory_set.areas.len(), 1);
+    assert!(memory_set.translate(page(1)).map_or(false, |pte| pte.is_valid()));
+    info!("munmap_split_test passed!");
+}
+
+#[allow(unused)]
+// 测试通过只读访问器枚举内核空间的页表：逻辑段的每个页面都能翻译，物理内存窗口用了大页，
+// 叶子数比逻辑段加跳板的页面数少，且恒等映射
+pub fn page_table_accessor_test() {
+    let kernel_space = KERNEL_SPACE.read();
+    let entries = kernel_space.page_table().enumerate();
+    let area_pages: usize = kernel_space
+        .iter_areas()
+        .map(|area| area.end_vpn().0 - area.start_vpn().0)
+        .sum();
+    assert!(entries.len() < area_pages + 1);
+    assert!(kernel_space.iter_areas().all(|area| {
+        VPNRange::new(area.start_vpn(), area.end_vpn())
+            .into_iter()
+            .all(|vpn| kernel_space.translate(vpn).map_or(false, |pte| pte.is_valid()))
+    }));
+    assert!(entries.windows(2).all(|pair| pair[0].0 < pair[1].0));
+    let trampoline_vpn = VirtAddr::from(TRAMPOLINE).floor();
+    assert!(entries.iter().any(|(vpn, _)| *vpn == trampoline_vpn));
+    let mid_text: VirtAddr = ((stext as usize + etext as usize) / 2).into();
+    assert_eq!(
+        kernel_space.page_table().translate_va(mid_text),
+        Some(PhysAddr::from(mid_text.0))
+    );
+    info!("page_table_accessor_test passed!");
+}
+
+#[cfg(feature = "stress_oom")]
+// 检查地址空间的不变量：逻辑段互不重叠、Framed逻辑段的每个页面都按记录的页帧和权限映射、
+// 页帧数与初始剩余页帧数对得上。出错时返回描述
+fn check_invariants(memory_set: &MemorySet, baseline: usize) -> Result<(), &'static str> {
+    let mut ranges: Vec<(VirtPageNum, VirtPageNum)> = memory_set
+        .areas
+        .iter()
+        .map(|area| (area.vpn_range.get_start(), area.vpn_range.get_end()))
+        .collect();
+    ranges.sort();
+    if ranges.windows(2).any(|pair| pair[0].1 > pair[1].0) {
+        return Err("overlapping areas");
+    }
+    let mut data_frames = 0;
+    for area in memory_set.areas.iter() {
+        if area.map_type != MapType::Framed {
+            continue;
+        }
+        let flags = PTEFlags::from_bits(area.map_perm.bits as u16).unwrap() | PTEFlags::V;
+        for vpn in area.vpn_range {
+            // 映射到共享零页的页面没有写权限
+            let flags = if area.is_zero_mapped(vpn) { flags - PTEFlags::W } else { flags };
+            let frame = match area.data_frames.get(&vpn) {
+                Some(frame) => frame,
+                // 延迟分配还没访问过的页面，不能有有效的页表项
+                None => match memory_set.translate(vpn) {
+                    Some(pte) if pte.is_valid() => return Err("page mapped without recorded frame"),
+                    _ => continue,
+                },
+            };
+            match memory_set.translate(vpn) {
+                Some(pte) if pte.is_valid() && pte.ppn() == frame.ppn && pte.flags() == flags => {}
+                _ => return Err("page not translatable with recorded frame and permission"),
+            }
+        }
+        data_frames += area.frame_count();
+    }
+    if baseline - frame_remain_num() != data_frames + memory_set.page_table.node_count() {
+        return Err("frame accounting mismatch");
+    }
+    Ok(())
+}
+
+#[cfg(feature = "stress_oom")]
+// mmap/munmap往返模糊测试：用固定种子的伪随机序列反复操作一个独立的地址空间，
+// 每步之后检查不变量，出错时打印操作记录并返回false
+fn mmap_fuzz() -> bool {
+    const ROUNDS: usize = 2000;
+    const WINDOW_PAGES: usize = 64;
+    let baseline = frame_remain_num();
+    let mut memory_set = MemorySet::new_bare();
+    let mut log: Vec<(bool, usize, usize, usize, isize)> = Vec::new();
+    let mut rng = Prng::new(0x2545_f491_4f6c_dd1d);
+    let mut rand = move || rng.next_u64() as usize;
+    for _ in 0..ROUNDS {
+        let start = 0x1000_0000 + (rand() % WINDOW_PAGES) * PAGE_SIZE;
+        let len = (rand() % 8 + 1) * PAGE_SIZE;
+        let op = rand() % 3;
+        let entry = if op == 0 {
+            let port = rand() % 8;
+            (true, start, len, port, memory_set.mmap(start, len, port))
+        } else if op == 1 && !memory_set.areas.is_empty() {
+            // 一半的munmap精确命中已有逻辑段，保证走到成功的路径
+            let area = &memory_set.areas[rand() % memory_set.areas.len()];
+            let start: usize = VirtAddr::from(area.vpn_range.get_start()).into();
+            let end: usize = VirtAddr::from(area.vpn_range.get_end()).into();
+            (false, start, end - start, 0, memory_set.munmap(start, end - start))
+        } else {
+            (false, start, len, 0, memory_set.munmap(start, len))
+        };
+        log.push(entry);
+        if let Err(reason) = check_invariants(&memory_set, baseline) {
+            println!("[kernel] mmap_fuzz failed: {}", reason);
+            for (is_mmap, start, len, port, ret) in log.iter() {
+                if *is_mmap {
+                    println!("  mmap({:#x}, {:#x}, {:#b}) = {}", start, len, port, ret);
+                } else {
+                    println!("  munmap({:#x}, {:#x}) = {}", start, len, ret);
+                }
+            }
+            return false;
+        }
+    }
+    drop(memory_set);
+    frame_remain_num() == baseline
+}
+
+#[cfg(feature = "stress_oom")]
+// 内存耗尽压力测试：不断mmap直到页帧耗尽，确认之后的请求都返回-ENOMEM，
+// 再释放全部内存，确认剩余页帧数回到初始值，最后打印结果并关机
+pub fn stress_oom() -> ! {
+    if !mmap_fuzz() {
+        println!("[kernel] stress_oom failed in mmap_fuzz");
+        crate::sbi::shutdown()
+    }
+    let baseline = frame_remain_num();
+    let mut memory_set = MemorySet::new_bare();
+    let mut start: usize = 0x1000_0000;
+    let mut mapped = 0;
+    // 按块申请，失败后块大小减半，直到单页也申请不到，避免逻辑段过多把内核堆先耗尽
+    let mut chunk = 256;
+    while chunk > 0 {
+        let ret = memory_set.mmap(start, chunk * PAGE_SIZE, 0b011);
+        if ret == -ENOMEM {
+            chunk /= 2;
+            continue;
+        }
+        assert_eq!(ret, 0);
+        start += chunk * PAGE_SIZE;
+        mapped += chunk;
+    }
+    let mut passed = mapped > 0;
+    for _ in 0..3 {
+        passed &= memory_set.mmap(start, PAGE_SIZE, 0b011) == -ENOMEM;
+        start += PAGE_SIZE;
+    }
+    drop(memory_set);
+    passed &= frame_remain_num() == baseline;
+    if passed {
+        println!("[kernel] stress_oom passed, {} pages mapped before exhaustion", mapped);
+    } else {
+        println!("[kernel] stress_oom failed, {} pages mapped before exhaustion", mapped);
+    }
+    crate::sbi::shutdown()
+}
+
+#[allow(unused)]
+// 测试访问方式和页表项标志位的打印格式
+pub fn flags_display_test() {
+    use alloc::string::ToString;
+    assert_eq!(MapPermission::empty().to_string(), "----");
+    assert_eq!((MapPermission::R | MapPermission::X).to_string(), "r-x-");
+    assert_eq!(
+        (MapPermission::R | MapPermission::W | MapPermission::U).to_string(),
+        "rw-u"
+    );
+    assert_eq!((PTEFlags::V | PTEFlags::R | PTEFlags::X).to_string(), "R-X-");
+    assert_eq!(PTEFlags::all().to_string(), "RWXU");
+    info!("flags_display_test passed!");
+}
+
+#[allow(unused)]
+// 测试分配内存时用给定数据初始化
+pub fn mmap_with_data_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let data = [1u8, 2, 3, 4];
+    assert_eq!(memory_set.mmap_with_data(start, 2 * PAGE_SIZE, 0b011, &data), 0);
+    let bytes = memory_set
+        .translate(VirtAddr::from(start).floor())
+        .unwrap()
+        .ppn()
+        .get_bytes_array();
+    assert_eq!(&bytes[..4], &data);
+    assert!(bytes[4..].iter().all(|b| *b == 0));
+    // 数据比区域长时拒绝
+    assert_eq!(memory_set.mmap_with_data(start + 2 * PAGE_SIZE, 2, 0b011, &data), -EINVAL);
+    info!("mmap_with_data_test passed!");
+}
+
+// 构造一个只有一个LOAD段的最小ELF，段在0x1000处，内容为data，入口为0x1010
+#[allow(unused)]
+fn synthetic_elf(elf_type: u16, data: &[u8]) -> Vec<u8> {
+    let mut elf = Vec::new();
+    // ELF头：魔数、64位、小端、版本1
+    elf.extend_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1, 1, 0]);
+    elf.extend_from_slice(&[0; 8]);
+    elf.extend_from_slice(&elf_type.to_le_bytes());
+    elf.extend_from_slice(&0xf3u16.to_le_bytes()); // RISC-V
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&0x1010u64.to_le_bytes()); // 入口
+    elf.extend_from_slice(&64u64.to_le_bytes()); // 程序头表紧跟在ELF头之后
+    elf.extend_from_slice(&0u64.to_le_bytes()); // 没有节头表
+    elf.extend_from_slice(&0u32.to_le_bytes());
+    for half in [64u16, 56, 1, 64, 0, 0] {
+        elf.extend_from_slice(&half.to_le_bytes());
+    }
+    // 程序头：LOAD，可读可执行
+    elf.extend_from_slice(&1u32.to_le_bytes());
+    elf.extend_from_slice(&5u32.to_le_bytes());
+    elf.extend_from_slice(&120u64.to_le_bytes()); // 段内容在文件中的偏移
+    for word in [0x1000u64, 0x1000, data.len() as u64, data.len() as u64, 0x1000] {
+        elf.extend_from_slice(&word.to_le_bytes());
+    }
+    elf.extend_from_slice(data);
+    elf
+}
+
+#[allow(unused)]
+// 测试ET_DYN的应用各段和入口都加上了加载偏移，ET_EXEC保持不变
+pub fn pie_load_bias_test() {
+    let data = [0x13u8, 0, 0, 0, 0x73, 0, 0x10, 0];
+    for (elf_type, bias) in [(3u16, PIE_LOAD_BIAS), (2, 0)] {
+        let elf = synthetic_elf(elf_type, &data);
+        let (memory_set, _, entry_point) = MemorySet::from_elf(&elf);
+        assert_eq!(memory_set.load_bias(), bias);
+        assert_eq!(entry_point, 0x1010 + bias);
+        let segment_va = VirtAddr::from(0x1000 + bias);
+        let pte = memory_set.translate(segment_va.floor()).unwrap();
+        assert!(pte.is_valid() && pte.executable());
+        assert_eq!(&pte.ppn().get_bytes_array()[..data.len()], &data);
+        let entry_va = VirtAddr::from(entry_point);
+        assert_eq!(entry_va.floor(), segment_va.floor());
+    }
+    info!("pie_load_bias_test passed!");
+}
+
+#[allow(unused)]
+// 测试DMA缓冲区是恒等映射的用户页面，且其物理页帧已从普通分配中预留出去
+pub fn mmap_identical_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let remain_before = frame_remain_num();
+    let base = memory_set.mmap_identical(2 * PAGE_SIZE, 0b011);
+    assert!(base > 0);
+    let base = base as usize;
+    assert_eq!(base % PAGE_SIZE, 0);
+    // 两个预留页帧加上最多两个新建的页表节点
+    assert!(remain_before - frame_remain_num() >= 2);
+    let va = VirtAddr::from(base + PAGE_SIZE + 0x10);
+    assert_eq!(memory_set.page_table().translate_va(va), Some(PhysAddr::from(va.0)));
+    let pte = memory_set.translate(va.floor()).unwrap();
+    assert!(pte.user_accessible() && pte.writable());
+    assert!(memory_set.check_consistency().is_ok());
+    assert_eq!(memory_set.mmap_identical(PAGE_SIZE, 0), -EINVAL);
+    assert_eq!(memory_set.mmap_identical(PAGE_SIZE, 0b1000), -EINVAL);
+    assert_eq!(memory_set.mmap_identical(0, 0b011), -EINVAL);
+    assert_eq!(memory_set.mmap_identical(usize::MAX, 0b011), -EINVAL);
+    // 预留失败或参数错误都不应占用页帧
+    let remain = frame_remain_num();
+    assert_eq!(memory_set.mmap_identical(usize::MAX - PAGE_SIZE, 0b011), -ENOMEM);
+    assert_eq!(frame_remain_num(), remain);
+    // 与已有逻辑段重叠时撤销预留：先探出下一次预留的位置，在那里放一个惰性映射
+    let next = frame_reserve(1).unwrap();
+    frame_unreserve(next, 1);
+    let next = PhysAddr::from(next).0;
+    assert_eq!(memory_set.mmap_lazy(next, PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap_identical(PAGE_SIZE, 0b011), -EEXIST);
+    assert_eq!(frame_remain_num(), remain);
+    assert_eq!(frame_reserve(1).map(|ppn| PhysAddr::from(ppn).0), Some(next));
+    // 超出地址空间上限时在预留之前就失败
+    memory_set.set_rlimit_as(memory_set.mapped_bytes());
+    assert_eq!(memory_set.mmap_identical(PAGE_SIZE, 0b011), -ENOMEM);
+    assert_eq!(frame_remain_num(), remain);
+    info!("mmap_identical_test passed!");
+}
+
+#[allow(unused)]
+// 测试一致性检查能发现页表项指向错误页帧以及不属于任何逻辑段的页表项
+pub fn check_consistency_test() {
+    let (mut memory_set, _, entry_point) = MemorySet::from_elf(crate::loader::get_app_data(0));
+    assert!(memory_set.check_consistency().is_ok());
+    assert!(KERNEL_SPACE.read().check_consistency().is_ok());
+    // 把入口所在页面改为映射到另一个页帧
+    let entry_vpn = VirtAddr::from(entry_point).floor();
+    let flags = memory_set.translate(entry_vpn).unwrap().flags();
+    let other = frame_alloc().unwrap();
+    memory_set.page_table.unmap(entry_vpn);
+    memory_set.page_table.map(entry_vpn, other.ppn, flags).unwrap();
+    assert_eq!(memory_set.check_consistency(), Err(entry_vpn));
+    // 恢复后再在逻辑段之外装一个页表项
+    let frame_ppn = memory_set
+        .areas
+        .iter()
+        .find_map(|area| area.data_frames.get(&entry_vpn))
+        .unwrap()
+        .ppn;
+    memory_set.page_table.unmap(entry_vpn);
+    memory_set.page_table.map(entry_vpn, frame_ppn, flags).unwrap();
+    assert!(memory_set.check_consistency().is_ok());
+    let stray_vpn = VirtAddr::from(0x4000_0000usize).floor();
+    memory_set.page_table.map(stray_vpn, other.ppn, PTEFlags::R).unwrap();
+    assert_eq!(memory_set.check_consistency(), Err(stray_vpn));
+    memory_set.page_table.unmap(stray_vpn);
+    info!("check_consistency_test passed!");
+}
+
+#[allow(unused)]
+// 测试延迟映射的区域在populate后全部分配了页帧，之后不会再有缺页
+pub fn populate_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap_lazy(start, 4 * PAGE_SIZE, 0b011), 0);
+    let vpn_range = VPNRange::new(
+        VirtAddr::from(start).floor(),
+        VirtAddr::from(start + 4 * PAGE_SIZE).floor(),
+    );
+    assert!(vpn_range.into_iter().all(|vpn| memory_set
+        .translate(vpn)
+        .map_or(true, |pte| !pte.is_valid())));
+    // 与延迟映射的区域重叠时仍然拒绝
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 0b011), -EEXIST);
+    // 先访问一页，populate 只分配剩下的
+    assert!(memory_set.commit_page(VirtAddr::from(start).floor()));
+    assert_eq!(memory_set.populate(start, 4 * PAGE_SIZE), 3);
+    for vpn in vpn_range {
+        assert!(memory_set.translate(vpn).unwrap().is_valid());
+        assert!(!memory_set.commit_page(vpn));
+    }
+    assert_eq!(memory_set.populate(start, 4 * PAGE_SIZE), 0);
+    assert_eq!(memory_set.populate(start, 5 * PAGE_SIZE), -1);
+    assert_eq!(memory_set.munmap(start, 4 * PAGE_SIZE), 4);
+    // 没被访问过的延迟页面也可以直接解除映射
+    assert_eq!(memory_set.mmap_lazy(start, 2 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.munmap(start, 2 * PAGE_SIZE), 2);
+    info!("populate_test passed!");
+}
+
+#[allow(unused)]
+// 测试丢弃页面内容：写入数据后丢弃，页帧被归还，逻辑段还在，再访问时得到全零的新页面
+pub fn discard_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let first = VirtAddr::from(start).floor();
+    let data: Vec<u8> = (0..4 * PAGE_SIZE).map(|_| 0x5a).collect();
+    assert_eq!(memory_set.mmap_with_data(start, 4 * PAGE_SIZE, 0b011, &data), 0);
+    let remain_before = frame_remain_num();
+    // 只丢弃中间两页
+    assert_eq!(memory_set.discard(start + PAGE_SIZE, 2 * PAGE_SIZE), 2);
+    assert_eq!(frame_remain_num(), remain_before + 2);
+    assert_eq!(memory_set.mapped_bytes(), 4 * PAGE_SIZE);
+    for i in [1, 2] {
+        let vpn = VirtPageNum(first.0 + i);
+        assert!(memory_set.translate(vpn).map_or(true, |pte| !pte.is_valid()));
+        // 模拟缺页：读得到零页，写得到新的私有页帧
+        assert!(memory_set.commit_page_on_fault(vpn, i == 2));
+        let bytes = memory_set.translate(vpn).unwrap().ppn().get_bytes_array();
+        assert!(bytes.iter().all(|byte| *byte == 0));
+    }
+    for i in [0, 3] {
+        let bytes = memory_set.translate(VirtPageNum(first.0 + i)).unwrap().ppn().get_bytes_array();
+        assert!(bytes.iter().all(|byte| *byte == 0x5a));
+    }
+    // 零页上的页面不算归还的页帧；超出逻辑段或锁定的逻辑段不能丢弃
+    assert_eq!(memory_set.discard(start, 4 * PAGE_SIZE), 3);
+    assert_eq!(memory_set.discard(start, 5 * PAGE_SIZE), -1);
+    assert_eq!(memory_set.mmap(start + 8 * PAGE_SIZE, PAGE_SIZE, 0b011 | MAP_LOCKED), 0);
+    assert_eq!(memory_set.discard(start + 8 * PAGE_SIZE, PAGE_SIZE), -1);
+    assert_eq!(memory_set.munmap(start, 4 * PAGE_SIZE), 4);
+    info!("discard_test passed!");
+}
+
+#[allow(unused)]
+// 测试设备寄存器映射：映射QEMU串口的寄存器页，通过恒等地址读写16550的暂存寄存器（偏移7），
+// 写入的值能读回说明访问到了设备；与内存或已有映射重叠的区间被拒绝
+pub fn mmio_test() {
+    use crate::config::UART_BASE;
+    const SCR: usize = 7;
+    assert_eq!(map_mmio(UART_BASE, PAGE_SIZE), UART_BASE as isize);
+    let pte = KERNEL_SPACE.read().translate(VirtAddr::from(UART_BASE).floor()).unwrap();
+    assert_eq!(pte.ppn(), PhysAddr::from(UART_BASE).floor());
+    assert!(pte.readable() && pte.writable() && !pte.executable() && !pte.user_accessible());
+    let scr = (UART_BASE + SCR) as *mut u8;
+    unsafe {
+        let saved = scr.read_volatile();
+        for value in [0x5a, 0xa5] {
+            scr.write_volatile(value);
+            assert_eq!(scr.read_volatile(), value);
+        }
+        scr.write_volatile(saved);
+    }
+    assert_eq!(map_mmio(UART_BASE, PAGE_SIZE), -1);
+    assert_eq!(map_mmio(UART_BASE + 1, PAGE_SIZE), -1);
+    assert_eq!(map_mmio(MEMORY_END - PAGE_SIZE, PAGE_SIZE), -1);
+    // munmap 只解除用户逻辑段，内核的设备映射要按起始页号移除
+    assert_eq!(KERNEL_SPACE.write().munmap(UART_BASE, PAGE_SIZE), 0);
+    assert!(KERNEL_SPACE
+        .write()
+        .remove_area_with_start_vpn(VirtAddr::from(UART_BASE).floor()));
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    info!("mmio_test passed!");
+}
+
+#[allow(unused)]
+// 测试堆的增长和收缩：break按字节移动，逻辑段按整页变化，收缩时归还页帧，不能低于起点，也不能长进别的逻辑段
+pub fn grow_brk_test() {
+    let (mut memory_set, user_stack_top, _) = MemorySet::from_elf(crate::loader::get_app_data(0));
+    let base = memory_set.brk_base;
+    assert_eq!(base, user_stack_top);
+    assert_eq!(memory_set.grow_brk(0), Some(base));
+    let remain_before = frame_remain_num();
+    assert_eq!(memory_set.grow_brk(10), Some(base));
+    assert_eq!(memory_set.grow_brk(PAGE_SIZE as isize), Some(base + 10));
+    // 10 + PAGE_SIZE 字节需要两页
+    assert_eq!(frame_remain_num(), remain_before - 2);
+    let second = VirtPageNum(VirtAddr::from(base).floor().0 + 1);
+    let pte = memory_set.translate(second).unwrap();
+    assert!(pte.is_valid() && pte.writable() && pte.user_accessible());
+    // 缩回一页以内，第二页被解除映射
+    assert_eq!(memory_set.grow_brk(-(PAGE_SIZE as isize)), Some(base + 10 + PAGE_SIZE));
+    assert_eq!(frame_remain_num(), remain_before - 1);
+    assert!(memory_set.translate(second).map_or(true, |pte| !pte.is_valid()));
+    assert_eq!(memory_set.grow_brk(-11), None);
+    assert_eq!(memory_set.grow_brk(-10), Some(base + 10));
+    assert_eq!(frame_remain_num(), remain_before);
+    // 上方已有映射时不能长过去
+    assert_eq!(memory_set.mmap(base + 2 * PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.grow_brk(3 * PAGE_SIZE as isize), None);
+    assert_eq!(memory_set.grow_brk(PAGE_SIZE as isize), Some(base + 10));
+    // 没有堆逻辑段的地址空间不支持sbrk
+    assert_eq!(MemorySet::new_bare().grow_brk(PAGE_SIZE as isize), None);
+    info!("grow_brk_test passed!");
+}
+
+#[allow(unused)]
+// 测试延迟映射的大区域：读只映射到共享零页，不占用页帧；写一页只分配一个私有页帧
+pub fn zero_page_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let pages = 256;
+    assert_eq!(memory_set.mmap_lazy(start, pages * PAGE_SIZE, 0b011), 0);
+    let first = VirtAddr::from(start).floor();
+    // 第一次访问可能要为页表分配中间节点，从第二页开始计数
+    assert!(memory_set.commit_page_on_fault(first, false));
+    let remain = frame_remain_num();
+    for i in 1..pages {
+        assert!(memory_set.commit_page_on_fault(VirtPageNum(first.0 + i), false));
+    }
+    assert_eq!(frame_remain_num(), remain);
+    let pte = memory_set.translate(VirtPageNum(first.0 + 1)).unwrap();
+    assert_eq!(pte.ppn(), zero_frame());
+    assert!(pte.readable() && !pte.writable());
+    assert!(pte.ppn().get_bytes_array().iter().all(|byte| *byte == 0));
+    // 已经映射的页面再读缺页说明是真正的非法访问
+    assert!(!memory_set.commit_page_on_fault(first, false));
+    // 写一页，只分配一个私有页帧
+    assert!(memory_set.commit_page_on_fault(VirtPageNum(first.0 + 1), true));
+    assert_eq!(frame_remain_num(), remain - 1);
+    let pte = memory_set.translate(VirtPageNum(first.0 + 1)).unwrap();
+    assert!(pte.ppn() != zero_frame() && pte.writable());
+    assert!(!memory_set.commit_page_on_fault(VirtPageNum(first.0 + 1), true));
+    assert_eq!(memory_set.check_consistency(), Ok(()));
+    // 只读区域写缺页不会分配页帧
+    assert_eq!(memory_set.mmap_lazy(start + pages * PAGE_SIZE, PAGE_SIZE, 0b001), 0);
+    assert!(!memory_set.commit_page_on_fault(VirtPageNum(first.0 + pages), true));
+    // 解除映射后零页仍然在
+    assert_eq!(memory_set.munmap(start, pages * PAGE_SIZE), pages as isize);
+    assert_eq!(frame_remain_num(), remain);
+    assert!(zero_frame().get_bytes_array().iter().all(|byte| *byte == 0));
+    info!("zero_page_test passed!");
+}
+
+#[allow(unused)]
+// 测试延迟映射的mmap：映射64MiB不占用页帧，写两个相距很远的页面只消耗两个数据页帧（外加页表节点），
+// 逻辑段之外的缺页不会被处理
+pub fn lazy_mmap_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let pages = (64 << 20) / PAGE_SIZE;
+    let remain_before = frame_remain_num();
+    assert_eq!(memory_set.mmap_lazy(start, pages * PAGE_SIZE, 0b011), 0);
+    assert_eq!(frame_remain_num(), remain_before);
+    assert_eq!(memory_set.mapped_bytes(), pages * PAGE_SIZE);
+    let first = VirtAddr::from(start).floor();
+    let nodes_before = memory_set.page_table.node_count();
+    for vpn in [first, VirtPageNum(first.0 + pages - 1)] {
+        assert!(memory_set.commit_page_on_fault(vpn, true));
+        assert!(memory_set.translate(vpn).unwrap().writable());
+    }
+    let nodes = memory_set.page_table.node_count() - nodes_before;
+    assert_eq!(remain_before - frame_remain_num(), 2 + nodes);
+    assert_eq!(memory_set.iter_areas().map(|area| area.frame_count()).sum::<usize>(), 2);
+    // 逻辑段之外的访问仍然是非法访问，由trap处理杀死任务
+    assert!(!memory_set.commit_page_on_fault(VirtPageNum(first.0 + pages), true));
+    assert!(!memory_set.commit_page_on_fault(VirtPageNum(first.0 - 1), false));
+    info!("lazy_mmap_test passed!");
+}
+
+#[allow(unused)]
+// 测试mmap只在目标页面已经映射时失败：新区域成功，与它重叠的区域返回-EEXIST
+pub fn mmap_collision_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, 2 * PAGE_SIZE, 0b011), -EEXIST);
+    assert_eq!(memory_set.mmap(start - PAGE_SIZE, 2 * PAGE_SIZE, 0b011), -EEXIST);
+    // 紧挨着的区域不算重叠
+    assert_eq!(memory_set.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap(start - PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    info!("mmap_collision_test passed!");
+}
+
+#[allow(unused)]
+// 测试mmap的各种失败返回不同的错误码，同时出现多种错误时参数错误优先，其次是重叠
+pub fn mmap_errno_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), 0);
+    // port不合法
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 0), -EINVAL);
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 0b1011), -EINVAL);
+    // 起始地址没有对齐
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE + 1, PAGE_SIZE, 0b011), -EINVAL);
+    // 与已有映射重叠
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), -EEXIST);
+    // 页帧或地址空间配额不够（延迟分配时不检查页帧，这里直接走立即分配的路径）
+    let too_many = (frame_remain_num() + 1) * PAGE_SIZE;
+    assert_eq!(memory_set.mmap_with_data(start + PAGE_SIZE, too_many, 0b011, &[]), -ENOMEM);
+    memory_set.set_rlimit_as(2 * PAGE_SIZE);
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, 2 * PAGE_SIZE, 0b011), -ENOMEM);
+    // 既重叠又超出配额时报告重叠，既没对齐又重叠时报告参数错误
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0b011), -EEXIST);
+    assert_eq!(memory_set.mmap(start + 1, PAGE_SIZE, 0b011), -EINVAL);
+    assert_eq!(memory_set.mmap(start + PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    info!("mmap_errno_test passed!");
+}
+
+#[allow(unused)]
+// 测试结尾伸到trap上下文和跳板、或者相加溢出的用户区间在上取整之前就被拒绝，各个系统调用都返回错误而不是panic
+pub fn user_range_end_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let huge = usize::MAX - 0x1010;
+    assert_eq!(user_range_end(VirtAddr::from(PAGE_SIZE), PAGE_SIZE), Some(VirtAddr::from(2 * PAGE_SIZE)));
+    assert_eq!(user_range_end(VirtAddr::from(PAGE_SIZE), huge), None);
+    assert_eq!(user_range_end(VirtAddr::from(TRAP_CONTEXT), PAGE_SIZE), None);
+    assert_eq!(user_range_end(VirtAddr::from(2 * PAGE_SIZE), usize::MAX), None);
+    assert_eq!(memory_set.mmap(PAGE_SIZE, huge, 0b011), -EINVAL);
+    assert_eq!(memory_set.mmap(0, huge, 0b011), -ENOMEM);
+    assert_eq!(memory_set.munmap(PAGE_SIZE, huge), -1);
+    assert_eq!(memory_set.set_permissions(PAGE_SIZE, huge, 0b001), -1);
+    assert_eq!(memory_set.populate(PAGE_SIZE, huge), -1);
+    assert_eq!(memory_set.discard(PAGE_SIZE, huge), -1);
+    assert!(memory_set.areas.is_empty());
+    info!("user_range_end_test passed!");
+}
+
+#[allow(unused)]
+// 测试页帧不够时mmap在分配任何页帧之前就失败，不留下映射了一半的页面
+pub fn mmap_exhaustion_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    // 先把页帧占到只剩几个
+    let mut hoard = Vec::new();
+    while frame_remain_num() > 4 {
+        hoard.push(frame_alloc().unwrap());
+    }
+    let remain = frame_remain_num();
+    assert_eq!(memory_set.mmap(start, 8 * PAGE_SIZE, 0b011), -ENOMEM);
+    assert_eq!(frame_remain_num(), remain);
+    assert!(memory_set.areas.is_empty());
+    assert!((0..8).all(|i| memory_set
+        .translate(VirtPageNum(VirtAddr::from(start).floor().0 + i))
+        .map_or(true, |pte| !pte.is_valid())));
+    // 归还页帧后同样的请求成功
+    drop(hoard);
+    assert_eq!(memory_set.mmap(start, 8 * PAGE_SIZE, 0b011), 0);
+    info!("mmap_exhaustion_test passed!");
+}
+
+#[allow(unused)]
+// 测试带锁定位mmap的逻辑段被标记为锁定，页面回收扫描跳过它，只考虑未锁定的页面
+pub fn locked_mmap_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let locked_start: usize = 0x1000_0000;
+    let start: usize = 0x2000_0000;
+    assert_eq!(memory_set.mmap(locked_start, 2 * PAGE_SIZE, 0b011 | MAP_LOCKED), 0);
+    assert_eq!(memory_set.mmap(start, 2 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap(start + 2 * PAGE_SIZE, PAGE_SIZE, 0b011 | (1 << 3)), -EINVAL);
+    let locked: Vec<bool> = memory_set.iter_areas().map(|area| area.locked()).collect();
+    assert_eq!(locked, [true, false]);
+    let first = VirtAddr::from(start).floor();
+    let second = VirtPageNum(first.0 + 1);
+    assert_eq!(memory_set.age_pages(), [first, second]);
+    // 被访问过的页面第一轮清除访问位，第二轮才成为候选
+    let flags = memory_set.translate(first).unwrap().flags();
+    memory_set.page_table.set_flags(first, flags | PTEFlags::A);
+    assert_eq!(memory_set.age_pages(), [second]);
+    assert!(!memory_set.translate(first).unwrap().flags().contains(PTEFlags::A));
+    assert_eq!(memory_set.age_pages(), [first, second]);
+    info!("locked_mmap_test passed!");
+}
+
+#[allow(unused)]
+// 测试页表开销：映射越分散，需要新分配的中间节点越多，相邻页面不增加节点
+pub fn overhead_bytes_test() {
+    let mut memory_set = MemorySet::new_bare();
+    assert_eq!(memory_set.overhead_bytes(), PAGE_SIZE);
+    let start: usize = 0x1000_0000;
+    // (映射地址, 此后的节点数)：首个页面需要一级和二级节点，
+    // 相邻页面共用节点，跨2MiB需要新的末级节点，跨1GiB还需要新的中间节点
+    let steps = [
+        (start, 3),
+        (start + PAGE_SIZE, 3),
+        (start + (1 << 21), 4),
+        (start + (1 << 30), 6),
+    ];
+    for (va, nodes) in steps {
+        assert_eq!(memory_set.mmap(va, PAGE_SIZE, 0b011), 0);
+        assert_eq!(memory_set.overhead_bytes(), nodes * PAGE_SIZE);
+    }
+    assert_eq!(memory_set.mapped_bytes(), steps.len() * PAGE_SIZE);
+    info!("overhead_bytes_test passed!");
+}
+
+#[allow(unused)]
+// 测试地址空间大小限制：映射到上限为止，超出失败，调高后成功，调低到用量以下后不能再增长
+pub fn rlimit_as_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    memory_set.set_rlimit_as(4 * PAGE_SIZE);
+    assert_eq!(memory_set.mmap(start, 3 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap(start + 3 * PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mapped_bytes(), 4 * PAGE_SIZE);
+    assert_eq!(memory_set.mmap(start + 4 * PAGE_SIZE, PAGE_SIZE, 0b011), -ENOMEM);
+    memory_set.set_rlimit_as(8 * PAGE_SIZE);
+    assert_eq!(memory_set.mmap(start + 4 * PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    memory_set.set_rlimit_as(PAGE_SIZE);
+    assert_eq!(memory_set.mmap(start + 5 * PAGE_SIZE, PAGE_SIZE, 0b011), -ENOMEM);
+    assert_eq!(memory_set.munmap(start, 3 * PAGE_SIZE), 3);
+    assert_eq!(memory_set.mapped_bytes(), 2 * PAGE_SIZE);
+    info!("rlimit_as_test passed!");
+}
+
+#[allow(unused)]
+// 测试ASLR打开时各任务的用户栈和不指定地址的mmap位置随机，关闭时固定；
+// 偏移来自各地址空间自己的伪随机数发生器，相同种子的发生器产生相同的序列
+pub fn aslr_test() {
+    let elf_data = crate::loader::get_app_data(0);
+    set_aslr(false);
+    let (mut ms0, sp0, _) = MemorySet::from_elf(elf_data);
+    let (mut ms1, sp1, _) = MemorySet::from_elf(elf_data);
+    assert_eq!(sp0, sp1);
+    assert_eq!(ms0.mmap(0, PAGE_SIZE, 0b011), MMAP_BASE as isize);
+    assert_eq!(ms1.mmap(0, PAGE_SIZE, 0b011), MMAP_BASE as isize);
+    set_aslr(true);
+    let mut sps = Vec::new();
+    for _ in 0..4 {
+        let (mut memory_set, sp, _) = MemorySet::from_elf(elf_data);
+        assert_eq!(sp % PAGE_SIZE, 0);
+        assert!(sp >= sp0 && sp <= sp0 + ASLR_MAX_PAGES * PAGE_SIZE);
+        let start = memory_set.mmap(0, PAGE_SIZE, 0b011);
+        assert!(start >= MMAP_BASE as isize);
+        assert!(start as usize <= MMAP_BASE + ASLR_MAX_PAGES * PAGE_SIZE);
+        assert_eq!(start as usize % PAGE_SIZE, 0);
+        sps.push(sp);
+    }
+    assert!(sps.iter().any(|sp| *sp != sps[0]));
+    set_aslr(ASLR_ENABLED);
+    let (mut a, mut b) = (Prng::new(42), Prng::new(42));
+    assert!((0..16).all(|_| a.next_u64() == b.next_u64()));
+    assert!((0..16).all(|_| Prng::new(0).below(ASLR_MAX_PAGES + 1) <= ASLR_MAX_PAGES));
+    info!("aslr_test passed!");
+}
+
+#[allow(unused)]
+// 测试写时复制：复制出的地址空间与原地址空间共享页帧，双方的页面都变为只读；一方写缺页后得到私有副本，
+// 另一方的数据不变，引用计数归一后再写只恢复写权限；两个地址空间都回收后页帧全部归还
+pub fn cow_test() {
+    let remain_before = frame_remain_num();
+    let mut parent = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(parent.mmap(start, 2 * PAGE_SIZE, 0b011), 0);
+    let vpn = VirtAddr::from(start).floor();
+    let ppn = parent.translate(vpn).unwrap().ppn();
+    ppn.get_bytes_array()[0] = 0x5a;
+    let mut child = parent.duplicate();
+    assert_eq!(cow_refcount(ppn), 2);
+    for memory_set in [&parent, &child] {
+        let pte = memory_set.translate(vpn).unwrap();
+        assert_eq!(pte.ppn(), ppn);
+        assert!(pte.is_cow() && !pte.writable() && pte.user_accessible());
+    }
+    // 子空间写第一页，复制出私有页帧
+    assert!(child.commit_page_on_fault(vpn, true));
+    let pte = child.translate(vpn).unwrap();
+    assert!(pte.ppn() != ppn && pte.writable() && !pte.is_cow());
+    assert_eq!(pte.ppn().get_bytes_array()[0], 0x5a);
+    pte.ppn().get_bytes_array()[0] = 0xa5;
+    assert_eq!(ppn.get_bytes_array()[0], 0x5a);
+    assert_eq!(cow_refcount(ppn), 1);
+    // 父空间的页帧已经独占，写缺页不再复制
+    assert!(parent.commit_page_on_fault(vpn, true));
+    let pte = parent.translate(vpn).unwrap();
+    assert!(pte.ppn() == ppn && pte.writable() && !pte.is_cow());
+    // 不是写时复制的页面，写缺页不处理
+    assert!(!parent.commit_page_on_fault(vpn, true));
+    // 共享中的页面改为只读再改回可写，仍然是写时复制
+    let second = VirtPageNum(vpn.0 + 1);
+    assert_eq!(parent.set_permissions(start, 2 * PAGE_SIZE, 0b001), 0);
+    assert!(!parent.translate(second).unwrap().is_cow());
+    assert_eq!(parent.set_permissions(start, 2 * PAGE_SIZE, 0b011), 0);
+    assert!(parent.translate(second).unwrap().is_cow());
+    assert!(!parent.translate(vpn).unwrap().is_cow());
+    assert_eq!(parent.check_consistency(), Ok(()));
+    assert_eq!(child.check_consistency(), Ok(()));
+    drop(parent);
+    drop(child);
+    assert_eq!(frame_remain_num(), remain_before);
+    info!("cow_test passed!");
+}
+
+#[allow(unused)]
+// 测试深拷贝地址空间：拷贝与原地址空间内容相同但页帧各自独立，改动一方不影响另一方；
+// 延迟分配没访问过的页面仍然不映射，写时复制的页面拷贝后可写；两个都回收后页帧全部归还
+pub fn clone_from_test() {
+    let remain_before = frame_remain_num();
+    let (mut memory_set, _, entry_point) = MemorySet::from_elf(crate::loader::get_app_data(0));
+    let start: usize = 0x1000_0000;
+    let data: Vec<u8> = (0..PAGE_SIZE + 16).map(|i| i as u8).collect();
+    assert_eq!(memory_set.mmap_with_data(start, 2 * PAGE_SIZE, 0b011, &data), 0);
+    assert_eq!(memory_set.mmap_lazy(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE, 0b011), 0);
+    let lazy = VirtAddr::from(start + 2 * PAGE_SIZE).floor();
+    assert!(memory_set.commit_page_on_fault(lazy, false));
+    let copy = MemorySet::clone_from(&memory_set);
+    assert_eq!(copy.check_consistency(), Ok(()));
+    assert_eq!(copy.mapped_bytes(), memory_set.mapped_bytes());
+    assert_eq!(copy.load_bias(), memory_set.load_bias());
+    let trampoline = VirtAddr::from(TRAMPOLINE).floor();
+    assert_eq!(
+        copy.translate(trampoline).unwrap().ppn(),
+        memory_set.translate(trampoline).unwrap().ppn()
+    );
+    // 代码和数据逐页相同，页帧不同
+    for vpn in [VirtAddr::from(entry_point).floor(), VirtAddr::from(start).floor()] {
+        let (src, dst) = (memory_set.translate(vpn).unwrap(), copy.translate(vpn).unwrap());
+        assert!(src.ppn() != dst.ppn() && src.flags() == dst.flags());
+        assert_eq!(src.ppn().get_bytes_array(), dst.ppn().get_bytes_array());
+    }
+    // 零页仍然共享，没访问过的页面不映射
+    assert_eq!(copy.translate(lazy).unwrap().ppn(), zero_frame());
+    assert!(copy.translate(VirtPageNum(lazy.0 + 1)).map_or(true, |pte| !pte.is_valid()));
+    // 改动拷贝不影响原地址空间
+    let vpn = VirtAddr::from(start).floor();
+    copy.translate(vpn).unwrap().ppn().get_bytes_array()[0] = 0xff;
+    assert_eq!(memory_set.translate(vpn).unwrap().ppn().get_bytes_array()[0], 0);
+    // 写时复制共享中的页面拷贝后是私有可写的
+    let shared = memory_set.duplicate();
+    let copy_of_cow = MemorySet::clone_from(&memory_set);
+    let pte = copy_of_cow.translate(vpn).unwrap();
+    assert!(pte.writable() && !pte.is_cow());
+    drop(copy_of_cow);
+    drop(shared);
+    drop(copy);
+    drop(memory_set);
+    assert_eq!(frame_remain_num(), remain_before);
+    info!("clone_from_test passed!");
+}
+
+#[allow(unused)]
+// 测试页帧耗尽：mmap一直映射到页帧用完，返回-ENOMEM而不是让内核panic；
+// 之后延迟分配的缺页、新建页表节点和载入应用都返回错误，中途失败不泄漏页帧
+pub fn out_of_frames_test() {
+    let remain_before = frame_remain_num();
+    let mut memory_set = MemorySet::new_bare();
+    let chunk = 64 * PAGE_SIZE;
+    let mut start: usize = 0x1000_0000;
+    let err = loop {
+        match memory_set.mmap(start, chunk, 0b011) {
+            0 => start += chunk,
+            err => break err,
+        }
+    };
+    assert_eq!(err, -ENOMEM);
+    // 占住剩下的页帧
+    let mut hoard: Vec<FrameTracker> = core::iter::from_fn(frame_alloc).collect();
+    assert_eq!(frame_remain_num(), 0);
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), -ENOMEM);
+    // 延迟分配的区域不占页帧，可以建立，但第一次写时分配失败，由trap处理结束任务
+    let far: usize = 0x20_0000_0000;
+    assert_eq!(memory_set.mmap_lazy(far, PAGE_SIZE, 0b011), 0);
+    assert!(!memory_set.commit_page_on_fault(VirtAddr::from(far).floor(), true));
+    assert_eq!(
+        memory_set.page_table.map(VirtAddr::from(far).floor(), zero_frame(), PTEFlags::R),
+        Err(PageTableError::OutOfFrames)
+    );
+    assert!(MemorySet::try_new_bare().is_err());
+    // 只放出几个页帧，载入应用到一半失败，已经分配的页帧全部归还
+    hoard.truncate(hoard.len() - 8);
+    assert_eq!(
+        MemorySet::try_from_elf_reader(crate::loader::app_reader(0)).err(),
+        Some(PageTableError::OutOfFrames)
+    );
+    assert_eq!(frame_remain_num(), 8);
+    drop(hoard);
+    drop(memory_set);
+    assert_eq!(frame_remain_num(), remain_before);
+    info!("out_of_frames_test passed!");
+}
+
+#[allow(unused)]
+// 测试跳板的位置配置：换一个合法的高半区地址后跳板被映射到那里，跳板页内的每个位置都翻译到 strampoline 中的对应位置；
+// 与trap上下文重叠、没有对齐或不是SV39合法地址的配置通不过启动时的检查
+pub fn trampoline_layout_test() {
+    assert_eq!(check_layout(TRAMPOLINE, TRAP_CONTEXT), Ok(()));
+    let alt_trampoline: usize = 0xffff_ffff_8000_0000;
+    let alt_trap_context = alt_trampoline - PAGE_SIZE;
+    assert_eq!(check_layout(alt_trampoline, alt_trap_context), Ok(()));
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.try_map_trampoline_at(alt_trampoline).unwrap();
+    let pte = memory_set
+        .translate(VirtAddr::from(alt_trampoline).floor())
+        .unwrap();
+    assert!(pte.is_valid() && pte.readable() && pte.executable() && !pte.writable());
+    for offset in [0, 0x10, PAGE_SIZE - 8] {
+        assert_eq!(
+            memory_set
+                .page_table()
+                .translate_va(VirtAddr::from(alt_trampoline + offset)),
+            Some(PhysAddr::from(strampoline as usize + offset))
+        );
+    }
+    // 默认位置没有被映射
+    assert!(memory_set
+        .translate(VirtAddr::from(TRAMPOLINE).floor())
+        .map_or(true, |pte| !pte.is_valid()));
+    // 不合法的配置
+    let overlap = check_layout(alt_trampoline, alt_trampoline).unwrap_err();
+    assert!(overlap.contains("overlap"));
+    assert!(check_layout(alt_trampoline + 8, alt_trap_context).is_err());
+    assert!(check_layout(0x40_0000_0000, alt_trap_context).is_err());
+    assert!(check_layout(MEMORY_END - PAGE_SIZE, alt_trap_context).is_err());
+    info!("trampoline_layout_test passed!");
+}
+
+#[allow(unused)]
+// 测试修改权限：只读页面改为可读写后页表项和逻辑段的权限都变了（用户态写入见 ch4_mprotect）；
+// 只改逻辑段的一部分时逻辑段被拆开，按需分配的页面使用新权限；范围内有未映射的页面时不做改动
+pub fn set_permissions_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let vpn = VirtAddr::from(start).floor();
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b001), 0);
+    let pte = memory_set.translate(vpn).unwrap();
+    assert!(pte.readable() && !pte.writable());
+    assert_eq!(memory_set.set_permissions(start, PAGE_SIZE, 0b011), 0);
+    let pte = memory_set.translate(vpn).unwrap();
+    assert!(pte.is_valid() && pte.readable() && pte.writable() && pte.user_accessible());
+    assert_eq!(memory_set.areas[0].map_perm, MapPermission::R | MapPermission::W | MapPermission::U);
+    // 延迟映射4页，只把中间两页改为可读写
+    let lazy_start = start + 0x10_0000;
+    assert_eq!(memory_set.mmap_lazy(lazy_start, 4 * PAGE_SIZE, 0b001), 0);
+    assert_eq!(memory_set.set_permissions(lazy_start + PAGE_SIZE, 2 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.areas.len(), 4);
+    let lazy_vpn = VirtAddr::from(lazy_start + PAGE_SIZE).floor();
+    assert!(memory_set.commit_page_on_fault(lazy_vpn, true));
+    assert!(memory_set.translate(lazy_vpn).unwrap().writable());
+    assert!(!memory_set.commit_page_on_fault(VirtAddr::from(lazy_start).floor(), true));
+    assert_eq!(memory_set.check_consistency(), Ok(()));
+    // 跨到未映射的页面时返回-1，原有权限不变
+    assert_eq!(memory_set.set_permissions(start, 2 * PAGE_SIZE, 0b001), -1);
+    assert!(memory_set.translate(vpn).unwrap().writable());
+    info!("set_permissions_test passed!");
+}
+
+#[allow(unused)]
+// 测试munmap返回解除映射的页数：完全映射的范围返回全部页数，没有映射的范围返回0，
+// 部分映射（跨过两个逻辑段和中间的空洞）的范围只解除重叠的页面，延迟分配的逻辑段只算访问过的页面
+pub fn munmap_count_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    let page = |i: usize| VirtAddr::from(start + i * PAGE_SIZE).floor();
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.munmap(start, 4 * PAGE_SIZE), 4);
+    assert!(memory_set.areas.is_empty());
+    assert_eq!(memory_set.munmap(start, 4 * PAGE_SIZE), 0);
+    // 页0~3和页6~9已映射，解除页2~7
+    assert_eq!(memory_set.mmap(start, 4 * PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap(start + 6 * PAGE_SIZE, 4 * PAGE_SIZE, 0b011), 0);
+    let remain_before = frame_remain_num();
+    assert_eq!(memory_set.munmap(start + 2 * PAGE_SIZE, 6 * PAGE_SIZE), 4);
+    assert_eq!(frame_remain_num(), remain_before + 4);
+    for i in 0..10 {
+        let valid = memory_set.translate(page(i)).map_or(false, |pte| pte.is_valid());
+        assert_eq!(valid, i < 2 || i >= 8, "page {}", i);
+    }
+    assert_eq!(memory_set.mapped_bytes(), 4 * PAGE_SIZE);
+    assert_eq!(memory_set.check_consistency(), Ok(()));
+    // 延迟分配的页面只有访问过的才算：读过的映射在零页上，写过的有私有页帧，没访问过的没有页表项
+    assert_eq!(memory_set.mmap_lazy(start + 16 * PAGE_SIZE, 4 * PAGE_SIZE, 0b011), 0);
+    assert!(memory_set.commit_page_on_fault(page(16), false));
+    assert!(memory_set.commit_page_on_fault(page(17), true));
+    let remain_before = frame_remain_num();
+    assert_eq!(memory_set.munmap(start + 16 * PAGE_SIZE, 4 * PAGE_SIZE), 2);
+    assert_eq!(frame_remain_num(), remain_before + 1);
+    assert!(memory_set.translate(page(16)).map_or(true, |pte| !pte.is_valid()));
+    // 没有对齐仍然是错误
+    assert_eq!(memory_set.munmap(start + 1, PAGE_SIZE), -1);
+    info!("munmap_count_test passed!");
+}
+
+#[allow(unused)]
+// 测试缺页报告用的 contains_addr：逻辑段内（包括还没分配的延迟页面）的地址算在内，空指针、段之间的空洞和段尾不算
+pub fn contains_addr_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start: usize = 0x1000_0000;
+    assert_eq!(memory_set.mmap(start, PAGE_SIZE, 0b011), 0);
+    assert_eq!(memory_set.mmap_lazy(start + 2 * PAGE_SIZE, PAGE_SIZE, 0b011), 0);
+    assert!(memory_set.contains_addr(start.into()));
+    assert!(memory_set.contains_addr((start + PAGE_SIZE - 1).into()));
+    assert!(memory_set.contains_addr((start + 2 * PAGE_SIZE + 8).into()));
+    assert!(!memory_set.contains_addr(0.into()));
+    assert!(!memory_set.contains_addr((start + PAGE_SIZE).into()));
+    assert!(!memory_set.contains_addr((start + 3 * PAGE_SIZE).into()));
+    info!("contains_addr_test passed!");
+}
+
+#[allow(unused)]
+// 测试栈溢出的识别：只有用户栈正下方那一页算保护页，栈内、更低的页面和没有用户栈的地址空间都不算
+pub fn guard_fault_test() {
+    let (mut memory_set, user_sp, _) = MemorySet::from_elf(crate::loader::get_app_data(0));
+    let stack_bottom = memory_set.stack_bottom;
+    assert_eq!(stack_bottom + USER_STACK_SIZE, user_sp);
+    assert!(memory_set.is_guard_fault((stack_bottom - 1).into()));
+    assert!(memory_set.is_guard_fault((stack_bottom - PAGE_SIZE).into()));
+    assert!(!memory_set.is_guard_fault(stack_bottom.into()));
+    assert!(!memory_set.is_guard_fault((stack_bottom - PAGE_SIZE - 1).into()));
+    let copy = MemorySet::clone_from(&memory_set);
+    assert!(copy.is_guard_fault((stack_bottom - 1).into()));
+    memory_set.clear();
+    assert!(!memory_set.is_guard_fault((stack_bottom - 1).into()));
+    assert!(!MemorySet::new_bare().is_guard_fault((PAGE_SIZE - 1).into()));
+    info!("guard_fault_test passed!");
+}
+
+#[allow(unused)]
+// 测试起始地址不按页对齐的数据段在延迟BSS下的载入：文件数据跨进的最后一页分配了私有页帧，
+// 数据放在段的起始地址处，其后纯BSS的页面才映射到共享零页，共享零页保持全零
+pub fn lazy_bss_unaligned_test() {
+    let mut memory_set = MemorySet::new_bare();
+    let start_va = VirtAddr::from(0x1000_0800);
+    let end_va = VirtAddr::from(start_va.0 + 3 * PAGE_SIZE);
+    // 文件数据从页内0x800处开始，占满第一页的后半和第二页的前半
+    let data: Vec<u8> = (0..PAGE_SIZE).map(|i| (i % 251) as u8 + 1).collect();
+    let elf_data = ElfChunks::new(core::iter::once(&data[..]));
+    let map_perm = MapPermission::R | MapPermission::W | MapPermission::U;
+    let map_area = MapArea::new(start_va, end_va, MapType::Framed, map_perm);
+    assert!(memory_set.push_elf_segment(map_area, start_va, &elf_data, 0, data.len(), true).is_ok());
+    let first = start_va.floor();
+    let ppn_of = |i: usize| memory_set.translate(VirtPageNum(first.0 + i)).unwrap().ppn();
+    assert_ne!(ppn_of(0), zero_frame());
+    assert_ne!(ppn_of(1), zero_frame());
+    assert_eq!(ppn_of(2), zero_frame());
+    assert_eq!(ppn_of(3), zero_frame());
+    let page_table = memory_set.page_table();
+    for (i, byte) in data.iter().enumerate() {
+        let pa = page_table.translate_va(VirtAddr::from(start_va.0 + i)).unwrap();
+        assert_eq!(*pa.get_ref::<u8>(), *byte);
+    }
+    assert!(zero_frame().get_bytes_array().iter().all(|byte| *byte == 0));
+    assert!(memory_set.check_consistency().is_ok());
+    info!("lazy_bss_unaligned_test passed!");
+}
@@ -8,24 +8,173 @@ mod heap_allocator;
 mod memory_set;
 mod page_table;
 
-pub use address::{PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
-use address::{StepByOne, VPNRange};
-pub use frame_allocator::{frame_alloc, frame_remain_num, FrameTracker};
-pub use memory_set::remap_test;
-pub use memory_set::{MapPermission, MemorySet, KERNEL_SPACE};
-pub use page_table::{translated_byte_buffer, translated_assign_ptr, PageTableEntry};
-use page_table::{PTEFlags, PageTable};
+pub use address::{align_down, align_up, expect_page_aligned, PPNRange, PhysAddr, PhysPageNum, VirtAddr, VirtPageNum};
+use address::{check_page_aligned, StepByOne, VPNRange};
+pub use frame_allocator::{
+    cow_refcount, dump_cow_frames, frame_alloc, frame_alloc_huge, frame_allocator_stats,
+    frame_remain_num, frame_reserve, frame_unreserve, largest_free_run, set_frame_hook,
+    share_zero_frame, zero_frame, FrameHook, FrameStats, FrameTracker, HugeFrameTracker,
+    HUGE_FRAME_PAGES,
+};
+pub use memory_set::{map_mmio, remap_test, set_aslr};
+#[cfg(feature = "stress_oom")]
+pub use memory_set::stress_oom;
+pub use memory_set::{MapArea, MapPermission, MapType, MemSnapshot, MemorySet, KERNEL_SPACE, MAP_LOCKED};
+pub use page_table::{
+    translated_assign_ptr, translated_byte_buffer, translated_read_ptr, translated_ref,
+    translated_refmut, translated_str, PageTable, PageTableEntry,
+};
+use page_table::{PTEFlags, PageTableError};
+use alloc::vec::Vec;
+use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
 
 // 初始化内核堆分配器、物理页帧分配器和内核地址空间
 pub fn init() {
     // 首先初始化内核堆分配器,在bss上划一个大数组给导入的伙伴分配器,以此实例化一个静态伙伴分配器
     heap_allocator::init_heap();
+    // 检查跳板和trap上下文的位置配置，不合法时在建立任何页表之前就停下来
+    if let Err(msg) = memory_set::check_layout(TRAMPOLINE, TRAP_CONTEXT) {
+        panic!("{}", msg);
+    }
     // 初始化物理页帧分配器,利用ld中的符号ekernel找到内核结束位置,然后把后面的地址全都上下取整获得物理页帧号分给分配器
     // 看来按照SV39约定,cpu真就是取物理地址的中间44位作为页帧号,而qume给我们的是从0x80000000开始的地址,那估计是80000开头的号?
     frame_allocator::init_frame_allocator();
+    // 分配全局零页，供延迟分配的页面在第一次写之前共享
+    frame_allocator::init_zero_frame();
     // 创建内核地址空间并让 CPU 开启分页模式， MMU 在地址转换的时候使用内核的多级页表，这一切均在一行之内做到
     // 首先，我们引用 KERNEL_SPACE ，这是它第一次被使用，就在此时它会被初始化
-    // 接着使用 .lock()访问里面的数据
+    // 接着使用 .read()访问里面的数据，激活只需要读页表
     // 最后，我们调用 MemorySet::activate, 设置satp, 使能分页模式
-    KERNEL_SPACE.lock().activate();
+    KERNEL_SPACE.read().activate();
+}
+
+// 内存管理子系统自检的汇总
+pub struct SelfTestReport {
+    pub passed: usize, // 通过的测试和检查数
+    pub failed: Vec<&'static str>, // 失败的测试和检查
+}
+
+impl SelfTestReport {
+    pub fn is_ok(&self) -> bool {
+        self.failed.is_empty()
+    }
+
+    // 记录一项测试或检查的结果，失败时记下它的名字
+    fn record(&mut self, name: &'static str, outcome: Result<(), &'static str>) {
+        match outcome {
+            Ok(()) => self.passed += 1,
+            Err(reason) => {
+                warn!("mm self test: {} failed: {}", name, reason);
+                self.failed.push(name);
+            }
+        }
+    }
+}
+
+// 自检运行的单元测试，名字用于在汇总里报告失败的那一项
+const UNIT_TESTS: &[(&str, fn())] = &[
+    ("heap_test", heap_allocator::heap_test),
+    #[cfg(feature = "heap_canary")]
+    ("heap_canary_test", heap_allocator::heap_canary_test),
+    ("frame_allocator_test", frame_allocator::frame_allocator_test),
+    ("frame_reclaim_test", frame_allocator::frame_reclaim_test),
+    ("frame_hook_test", frame_allocator::frame_hook_test),
+    ("largest_free_run_test", frame_allocator::largest_free_run_test),
+    ("cow_refcount_test", frame_allocator::cow_refcount_test),
+    ("frame_zero_test", frame_allocator::frame_zero_test),
+    ("huge_frame_test", frame_allocator::huge_frame_test),
+    ("frame_stats_test", frame_allocator::frame_stats_test),
+    ("align_test", address::align_test),
+    ("checked_add_test", address::checked_add_test),
+    ("vpn_range_len_test", address::vpn_range_len_test),
+    ("ppn_range_test", address::ppn_range_test),
+    ("sv39_layout_test", address::sv39_layout_test),
+    ("page_aligned_config_test", address::page_aligned_config_test),
+    ("map_twice_test", page_table::map_twice_test),
+    ("user_global_bits_test", page_table::user_global_bits_test),
+    ("megapage_test", page_table::megapage_test),
+    ("translated_str_test", page_table::translated_str_test),
+    ("translated_ref_test", page_table::translated_ref_test),
+    ("assign_across_pages_test", page_table::assign_across_pages_test),
+    ("heap_pressure_map_test", page_table::heap_pressure_map_test),
+    ("walk_test", page_table::walk_test),
+    ("remap_test", memory_set::remap_test),
+    ("activate_test", memory_set::activate_test),
+    ("phys_window_test", memory_set::phys_window_test),
+    ("check_consistency_test", memory_set::check_consistency_test),
+    ("mmap_collision_test", memory_set::mmap_collision_test),
+    ("populate_test", memory_set::populate_test),
+    ("zero_page_test", memory_set::zero_page_test),
+    ("user_range_end_test", memory_set::user_range_end_test),
+    ("snapshot_test", memory_set::snapshot_test),
+    ("guard_page_test", memory_set::guard_page_test),
+    ("global_bit_test", memory_set::global_bit_test),
+    ("clear_test", memory_set::clear_test),
+    ("remove_area_test", memory_set::remove_area_test),
+    ("munmap_split_test", memory_set::munmap_split_test),
+    ("page_table_accessor_test", memory_set::page_table_accessor_test),
+    ("flags_display_test", memory_set::flags_display_test),
+    ("mmap_with_data_test", memory_set::mmap_with_data_test),
+    ("pie_load_bias_test", memory_set::pie_load_bias_test),
+    ("mmap_identical_test", memory_set::mmap_identical_test),
+    ("discard_test", memory_set::discard_test),
+    ("mmio_test", memory_set::mmio_test),
+    ("grow_brk_test", memory_set::grow_brk_test),
+    ("lazy_mmap_test", memory_set::lazy_mmap_test),
+    ("mmap_errno_test", memory_set::mmap_errno_test),
+    ("mmap_exhaustion_test", memory_set::mmap_exhaustion_test),
+    ("locked_mmap_test", memory_set::locked_mmap_test),
+    ("overhead_bytes_test", memory_set::overhead_bytes_test),
+    ("rlimit_as_test", memory_set::rlimit_as_test),
+    ("aslr_test", memory_set::aslr_test),
+    ("lazy_bss_unaligned_test", memory_set::lazy_bss_unaligned_test),
+    ("cow_test", memory_set::cow_test),
+    ("clone_from_test", memory_set::clone_from_test),
+    ("out_of_frames_test", memory_set::out_of_frames_test),
+    ("trampoline_layout_test", memory_set::trampoline_layout_test),
+    ("set_permissions_test", memory_set::set_permissions_test),
+    ("munmap_count_test", memory_set::munmap_count_test),
+    ("contains_addr_test", memory_set::contains_addr_test),
+    ("guard_fault_test", memory_set::guard_fault_test),
+];
+
+// 内存管理子系统自检，在 mm::init 之后、第一个任务运行之前调用
+// 单元测试用断言检查，失败时直接panic；每个测试返回后再检查一遍内核地址空间的不变量，
+// 测试通过了断言却破坏了内核地址空间时记为失败。汇总按每一项实际的结果统计
+pub fn self_test() -> SelfTestReport {
+    let mut report = SelfTestReport {
+        passed: 0,
+        failed: Vec::new(),
+    };
+    for &(name, test) in UNIT_TESTS {
+        test();
+        report.record(name, memory_set::check_kernel_space());
+    }
+    report.record("check_kernel_space", memory_set::check_kernel_space());
+    info!(
+        "mm self test: {} passed, {} failed {:?}",
+        report.passed,
+        report.failed.len(),
+        report.failed
+    );
+    report
+}
+
+#[allow(unused)]
+// 测试正确初始化的内核能通过自检，每个单元测试和最后的内核地址空间检查各算一项；
+// 失败的检查按名字记入汇总，不计入通过数
+pub fn self_test_test() {
+    let report = self_test();
+    assert!(report.is_ok());
+    assert_eq!(report.passed, UNIT_TESTS.len() + 1);
+    let mut report = SelfTestReport {
+        passed: 0,
+        failed: Vec::new(),
+    };
+    report.record("ok", Ok(()));
+    report.record("broken", Err("injected failure"));
+    assert_eq!(report.passed, 1);
+    assert_eq!(report.failed, ["broken"]);
+    assert!(!report.is_ok());
+    info!("self_test_test passed!");
 }
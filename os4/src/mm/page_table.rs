@@ -1,13 +1,16 @@
 // 实现页表项和页表的模块
 
-use super::{frame_alloc, FrameTracker, PhysPageNum, StepByOne, VirtAddr, PhysAddr, VirtPageNum};
-use alloc::vec;
+use super::{frame_alloc, zero_frame, FrameTracker, HUGE_FRAME_PAGES, PhysPageNum, StepByOne, VirtAddr, PhysAddr, VirtPageNum};
+use super::address::{PAGE_INDEX_BITS, PPN_WIDTH_SV39};
+use crate::config::{PAGE_SIZE, PAGE_SIZE_BITS};
+use alloc::string::String;
 use alloc::vec::Vec;
 use bitflags::*;
+use core::fmt::{self, Display, Formatter, Write};
 
 bitflags! {
-    // 页表项标志位
-    pub struct PTEFlags: u8 {
+    // 页表项标志位，低8位由硬件定义，第8、9位（RSW）留给软件使用
+    pub struct PTEFlags: u16 {
         const V = 1 << 0;
         const R = 1 << 1;
         const W = 1 << 2;
@@ -16,9 +19,35 @@ bitflags! {
         const G = 1 << 5;
         const A = 1 << 6;
         const D = 1 << 7;
+        // 写时复制：页帧与别的地址空间共享，页表项暂时只读，写缺页时再复制出私有页帧
+        const COW = 1 << 8;
     }
 }
 
+// 以 RWXU 的形式打印标志位，未设置的位用 - 表示
+impl Display for PTEFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (flag, c) in [
+            (PTEFlags::R, 'R'),
+            (PTEFlags::W, 'W'),
+            (PTEFlags::X, 'X'),
+            (PTEFlags::U, 'U'),
+        ] {
+            f.write_char(if self.contains(flag) { c } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+// 页表操作的错误
+pub enum PageTableError {
+    // 要映射的虚拟页号已经被映射过了
+    AlreadyMapped(VirtPageNum),
+    // 没有空闲页帧来存放数据或新的页表节点
+    OutOfFrames,
+}
+
 #[derive(Copy, Clone)]
 #[repr(C)]
 // 页表项结构
@@ -27,7 +56,7 @@ pub struct PageTableEntry {
 }
 
 impl PageTableEntry {
-    // 新初始化页表项，页表项由【物理地址（56位）--标志位（8位）】拼接而成
+    // 新初始化页表项，页表项由【物理地址（54位）--软件保留位（2位）--标志位（8位）】拼接而成
     pub fn new(ppn: PhysPageNum, flags: PTEFlags) -> Self {
         PageTableEntry {
             bits: ppn.0 << 10 | flags.bits as usize,
@@ -39,11 +68,11 @@ impl PageTableEntry {
     }
     // 取出页表项的物理页帧号
     pub fn ppn(&self) -> PhysPageNum {
-        (self.bits >> 10 & ((1usize << 44) - 1)).into()
+        (self.bits >> 10 & ((1usize << PPN_WIDTH_SV39) - 1)).into()
     }
     // 取出页表项的标志位段
     pub fn flags(&self) -> PTEFlags {
-        PTEFlags::from_bits(self.bits as u8).unwrap()
+        PTEFlags::from_bits_truncate((self.bits & 0x3ff) as u16)
     }
     // 判断是否有效，即V标志位是否为1
     pub fn is_valid(&self) -> bool {
@@ -61,85 +90,166 @@ impl PageTableEntry {
     pub fn executable(&self) -> bool {
         (self.flags() & PTEFlags::X) != PTEFlags::empty()
     }
+    // 判断是否u，即用户态可以访问
+    pub fn user_accessible(&self) -> bool {
+        (self.flags() & PTEFlags::U) != PTEFlags::empty()
+    }
+    // 是否为写时复制的页面
+    pub fn is_cow(&self) -> bool {
+        (self.flags() & PTEFlags::COW) != PTEFlags::empty()
+    }
+    // 判断是否g，即全局映射，所有地址空间中都一样
+    pub fn is_global(&self) -> bool {
+        (self.flags() & PTEFlags::G) != PTEFlags::empty()
+    }
+    // 判断是否为保护页，即有效但r、w、x都没有，访问时硬件一定会触发缺页
+    pub fn is_guard(&self) -> bool {
+        self.is_valid() && !self.readable() && !self.writable() && !self.executable()
+    }
+    // 判断是否为叶子，即有效且r、w、x至少有一个，出现在上两级时是大页
+    pub fn is_leaf(&self) -> bool {
+        self.is_valid() && (self.readable() || self.writable() || self.executable())
+    }
 }
 
 // 页表结构
 // 每个应用的地址空间都对应一个不同的多级页表，这也就意味这不同页表的起始地址（即页表根节点的地址）是不一样的。
 // 因此 PageTable 要保存它根节点的物理页号 root_ppn 作为页表唯一的区分标志。
-// 此外，向量 frames 以 FrameTracker 的形式保存了页表所有的节点（包括根节点）所在的物理页帧。
+// 此外，页表拥有所有节点（包括根节点）所在的物理页帧。这些页帧不另外记在内核堆上的向量里：
+// 除根节点外，每个节点都由上一级的非叶子页表项指向，页表树本身就是它们的链表，
+// 释放时沿页表树回收即可。这样新建节点不需要增长内核堆，堆紧张时只要还有页帧就能继续映射。
 pub struct PageTable {
     root_ppn: PhysPageNum, // 这个页表本身占的物理页帧号
-    frames: Vec<FrameTracker>, // 页表和页表的子结点占的物理页帧资源
+    owned: bool, // 是否拥有各节点的页帧，from_token 得到的临时页表不拥有
+    node_count: usize, // 页表和页表的子结点占的物理页帧数
 }
 
-/// Assume that it won't oom when creating/mapping.
+// 页帧不够时新建和映射都返回 PageTableError::OutOfFrames，只有内核启动时的映射直接断言成功
 impl PageTable {
     // 当我们通过 new 方法新建一个 PageTable 的时候，它只需有一个根节点。
     // 为此我们需要分配一个物理页帧 FrameTracker 并挂在向量 frames 下，然后更新根节点的物理页号 root_ppn 。
     pub fn new() -> Self {
-        let frame = frame_alloc().unwrap();
-        PageTable {
-            root_ppn: frame.ppn,
-            frames: vec![frame],
-        }
+        Self::try_new().expect("no frame for the root page table")
+    }
+
+    // 与 new 相同，但没有空闲页帧时返回错误
+    pub fn try_new() -> Result<Self, PageTableError> {
+        let frame = frame_alloc().ok_or(PageTableError::OutOfFrames)?;
+        let root_ppn = frame.ppn;
+        // 页帧的所有权交给页表树，在 drop 时沿树回收
+        core::mem::forget(frame);
+        Ok(PageTable {
+            root_ppn,
+            owned: true,
+            node_count: 1,
+        })
     }
 
     // 临时创建一个专用来手动查页表的 PageTable ，它仅有一个从传入的 satp token 中
-    // 得到的多级页表根节点的物理页号，它的 frames 字段为空，也即不实际控制任何资源
+    // 得到的多级页表根节点的物理页号，它不拥有任何节点，也即不实际控制任何资源
     pub fn from_token(satp: usize) -> Self {
         Self {
-            root_ppn: PhysPageNum::from(satp & ((1usize << 44) - 1)),
-            frames: Vec::new(),
+            root_ppn: PhysPageNum::from(satp & ((1usize << PPN_WIDTH_SV39) - 1)),
+            owned: false,
+            node_count: 0,
         }
     }
 
+    // 为空的页表项新建一个下一级节点并指向它，节点页帧的所有权交给页表树
+    fn create_node(&mut self, pte: &mut PageTableEntry) -> Result<(), PageTableError> {
+        let frame = frame_alloc().ok_or(PageTableError::OutOfFrames)?;
+        *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
+        core::mem::forget(frame);
+        self.node_count += 1;
+        Ok(())
+    }
+
     // 在多级页表找到一个虚拟页号对应的页表项的可变引用。如果在遍历的过程中发现有节点尚未创建则会新建一个节点。
-    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Option<&mut PageTableEntry> {
-        let mut idxs = vpn.indexes();
+    // 没有页帧新建节点时返回错误，已经建好的上层节点留在树里，随页表一起回收
+    // 路径上遇到大页时页面已被大页覆盖，返回 AlreadyMapped
+    fn find_pte_create(&mut self, vpn: VirtPageNum) -> Result<&mut PageTableEntry, PageTableError> {
+        let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&mut PageTableEntry> = None;
-        for (i, idx) in idxs.iter_mut().enumerate() {
+        // 前两级是中间节点，缺少时新建
+        for idx in idxs[..2].iter() {
             let pte = &mut ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+            if pte.is_leaf() {
+                return Err(PageTableError::AlreadyMapped(vpn));
             }
             if !pte.is_valid() {
-                let frame = frame_alloc().unwrap();
-                *pte = PageTableEntry::new(frame.ppn, PTEFlags::V);
-                self.frames.push(frame);
+                self.create_node(pte)?;
             }
             ppn = pte.ppn();
         }
-        result
+        Ok(&mut ppn.get_pte_array()[idxs[2]])
     }
 
-    // 在多级页表找到一个虚拟页号对应的页表项的不可变引用。
-    pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+    // 沿页表找到虚拟页号所在的页表项和它所在的级数（根节点为0），不新建节点
+    // 上两级遇到叶子（大页）时就停在那里，中间节点缺少时返回None
+    fn find_leaf(&self, vpn: VirtPageNum) -> Option<(&'static mut PageTableEntry, usize)> {
         let idxs = vpn.indexes();
         let mut ppn = self.root_ppn;
-        let mut result: Option<&PageTableEntry> = None;
-        for (i, idx) in idxs.iter().enumerate() {
-            let pte = &ppn.get_pte_array()[*idx];
-            if i == 2 {
-                result = Some(pte);
-                break;
+        for (level, idx) in idxs.iter().enumerate() {
+            let pte = &mut ppn.get_pte_array()[*idx];
+            if level == 2 || pte.is_leaf() {
+                return Some((pte, level));
             }
             if !pte.is_valid() {
                 return None;
             }
             ppn = pte.ppn();
         }
-        result
+        unreachable!()
+    }
+
+    // 在多级页表找到一个虚拟页号对应的页表项的不可变引用。页面在大页里时返回大页的页表项
+    pub fn find_pte(&self, vpn: VirtPageNum) -> Option<&PageTableEntry> {
+        self.find_leaf(vpn).map(|(pte, _)| &*pte)
     }
 
 
     #[allow(unused)]
-    // 通过 map 方法来在多级页表中插入一个键值对
-    pub fn map(&mut self, vpn: VirtPageNum, ppn: PhysPageNum, flags: PTEFlags) {
-        let pte = self.find_pte_create(vpn).unwrap();
-        assert!(!pte.is_valid(), "vpn {:?} is mapped before mapping", vpn);
+    // 通过 map 方法来在多级页表中插入一个键值对，虚拟页号已被映射时返回错误
+    pub fn map(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), PageTableError> {
+        let pte = self.find_pte_create(vpn)?;
+        if pte.is_valid() {
+            return Err(PageTableError::AlreadyMapped(vpn));
+        }
         *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
+    }
+
+    // 在中间一级节点里放一个叶子，一个页表项直接映射 2MiB 的大页，省去末级节点
+    // 虚拟页号和物理页号都必须按 512 页对齐，标志位要有R、W、X之一，否则会被当成指向下一级的节点
+    // 范围内已有映射（大页或者末级节点）时返回错误
+    pub fn map_huge(
+        &mut self,
+        vpn: VirtPageNum,
+        ppn: PhysPageNum,
+        flags: PTEFlags,
+    ) -> Result<(), PageTableError> {
+        assert_eq!(vpn.0 % HUGE_FRAME_PAGES, 0, "vpn {:?} is not 2MiB aligned", vpn);
+        assert_eq!(ppn.0 % HUGE_FRAME_PAGES, 0, "ppn {:?} is not 2MiB aligned", ppn);
+        assert!(flags.intersects(PTEFlags::R | PTEFlags::W | PTEFlags::X));
+        let idxs = vpn.indexes();
+        let root_pte = &mut self.root_ppn.get_pte_array()[idxs[0]];
+        if root_pte.is_leaf() {
+            return Err(PageTableError::AlreadyMapped(vpn));
+        }
+        if !root_pte.is_valid() {
+            self.create_node(root_pte)?;
+        }
+        let pte = &mut root_pte.ppn().get_pte_array()[idxs[1]];
+        if pte.is_valid() {
+            return Err(PageTableError::AlreadyMapped(vpn));
+        }
+        *pte = PageTableEntry::new(ppn, flags | PTEFlags::V);
+        Ok(())
     }
 
 
@@ -151,10 +261,77 @@ impl PageTable {
         *pte = PageTableEntry::empty();
     }
 
+    #[allow(unused)]
+    // 删除 map_huge 建立的大页映射，vpn 为大页的起始虚拟页号
+    pub fn unmap_huge(&mut self, vpn: VirtPageNum) {
+        match self.find_leaf(vpn) {
+            Some((pte, 1)) if pte.is_valid() && vpn.0 % HUGE_FRAME_PAGES == 0 => {
+                *pte = PageTableEntry::empty();
+            }
+            _ => panic!("vpn {:?} is not a huge page", vpn),
+        }
+    }
+
+    // 修改一个已映射页面的标志位，物理页帧不变，页面未映射时返回false
+    // 大页不能按单个页面修改，也返回false
+    pub fn set_flags(&mut self, vpn: VirtPageNum, flags: PTEFlags) -> bool {
+        match self.find_leaf(vpn) {
+            Some((pte, 2)) if pte.is_valid() => {
+                *pte = PageTableEntry::new(pte.ppn(), flags | PTEFlags::V);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // 页表自身（包括根节点）占用的页帧数
+    pub fn node_count(&self) -> usize {
+        self.node_count
+    }
+
     // translate 调用 find_pte 来实现，如果能够找到页表项，那么它会将页表项拷贝一份并返回，否则就返回一个 None 
     // 当遇到需要查一个特定页表（非当前正处在的地址空间的页表时），便可先通过 PageTable::from_token 新建一个页表，再调用它的 translate 方法查页表。
+    // 页面在大页里时，返回的页表项的物理页号是这个页面自己的页帧，即大页的起始页帧加上页面在大页内的偏移
     pub fn translate(&self, vpn: VirtPageNum) -> Option<PageTableEntry> {
-        self.find_pte(vpn).copied()
+        self.find_leaf(vpn).map(|(pte, level)| {
+            if level == 2 {
+                return *pte;
+            }
+            let offset = vpn.0 & ((1 << (PAGE_INDEX_BITS * (2 - level))) - 1);
+            PageTableEntry::new(PhysPageNum(pte.ppn().0 + offset), pte.flags())
+        })
+    }
+
+    // 查询虚拟地址对应的物理地址，页面未映射时返回None
+    pub fn translate_va(&self, va: VirtAddr) -> Option<PhysAddr> {
+        self.translate(va.floor())
+            .filter(|pte| pte.is_valid())
+            .map(|pte| PhysAddr::from(usize::from(PhysAddr::from(pte.ppn())) + va.page_offset()))
+    }
+
+    // 按虚拟页号从小到大列出所有有效的叶子页表项
+    pub fn enumerate(&self) -> Vec<(VirtPageNum, PageTableEntry)> {
+        self.walk()
+            .into_iter()
+            .map(|(vpn, ppn, flags)| (vpn, PageTableEntry::new(ppn, flags)))
+            .collect()
+    }
+
+    // 从根节点逐级向下，收集所有有效的叶子映射，按虚拟页号从小到大排列
+    // 只沿着有效的页表项走，不会像 find_pte_create 那样新建节点
+    pub fn walk(&self) -> Vec<(VirtPageNum, PhysPageNum, PTEFlags)> {
+        let mut result = Vec::new();
+        walk_node(self.root_ppn, 0, 0, &mut result);
+        result
+    }
+
+    // 打印所有叶子映射，调试用户程序的缺页时查看页表里到底有什么
+    pub fn dump(&self) {
+        let mappings = self.walk();
+        info!("page table root {:?}, {} mappings", self.root_ppn, mappings.len());
+        for (vpn, ppn, flags) in mappings {
+            info!("  {:?} -> {:?} {}", vpn, ppn, flags);
+        }
     }
 
     // 会按照 satp CSR 格式要求 构造一个无符号 64 位无符号整数，使得其分页模式为 SV39 ，
@@ -173,7 +350,7 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     while start < end {
         let start_va = VirtAddr::from(start);
         let mut vpn = start_va.floor();
-        let ppn = page_table.translate(vpn).unwrap().ppn();
+        let ppn = translated_user_ppn(&page_table, start_va, true);
         vpn.step();
         let mut end_va: VirtAddr = vpn.into();
         end_va = end_va.min(VirtAddr::from(end));
@@ -186,17 +363,363 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     }
     v
 }
+// 查出内核要访问的用户虚拟地址所在的物理页帧
+// 页表项无效的页面（延迟分配还没访问过的，或者被 MADV_DONTNEED 丢弃的）先像缺页一样替当前任务提交，
+// 绝不能使用无效页表项里的页帧号；要写时，还映射在共享零页上或写时复制的页面先换成私有页帧
+// 不可写的逻辑段提交不了私有页帧，无效的页面退而映射到共享零页，只供读取
+// 地址不属于任何逻辑段，或者页表不是当前任务的而页面无效时panic
+fn translated_user_ppn(page_table: &PageTable, va: VirtAddr, write: bool) -> PhysPageNum {
+    let vpn = va.floor();
+    let valid = page_table.translate(vpn).filter(|pte| pte.is_valid());
+    let needs_commit = match valid {
+        Some(pte) => write && (pte.ppn() == zero_frame() || pte.is_cow()),
+        None => true,
+    };
+    if needs_commit && page_table.token() == crate::task::current_user_token() {
+        let committed = crate::task::commit_page_in_current_memory_set(va.0, write);
+        if !committed && write && valid.is_none() {
+            crate::task::commit_page_in_current_memory_set(va.0, false);
+        }
+    }
+    match page_table.translate(vpn).filter(|pte| pte.is_valid()) {
+        Some(pte) => pte.ppn(),
+        None => panic!("user address {:#x} is not mapped", va.0),
+    }
+}
+
+// 内核要写入用户内存时用：同 translated_user_ppn，得到的是物理地址
+fn translated_pa_for_write(page_table: &PageTable, va: VirtAddr) -> PhysAddr {
+    PhysAddr::from(PhysAddr::from(translated_user_ppn(page_table, va, true)).0 + va.page_offset())
+}
+
 use core::fmt::Debug;
 // 在某个应用的虚拟地址空间中给裸指针赋值
+// 指针由用户给出，值可能跨页，所以把值当作字节序列，按页切分后逐段拷贝到各自的物理页帧上
 pub fn translated_assign_ptr<T: Debug>(token: usize, ptr: *mut T, value: T) {
     let page_table = PageTable::from_token(token);
+    let bytes = unsafe {
+        core::slice::from_raw_parts(&value as *const T as *const u8, core::mem::size_of::<T>())
+    };
+    let mut start = ptr as usize;
+    let end = start + bytes.len();
+    while start < end {
+        let start_va = VirtAddr::from(start);
+        let len = (PAGE_SIZE - start_va.page_offset()).min(end - start);
+        let pa = translated_pa_for_write(&page_table, start_va);
+        let copied = start - ptr as usize;
+        unsafe {
+            core::ptr::copy_nonoverlapping(bytes[copied..].as_ptr(), pa.0 as *mut u8, len);
+        }
+        start += len;
+    }
+    // 值已经按字节搬到用户空间，这里不能再析构它
+    core::mem::forget(value);
+}
+
+// 检查从va开始的一个T没有跨页，下面两个函数只能处理在一页之内的数据
+fn assert_within_page<T>(va: VirtAddr) {
+    assert!(
+        va.page_offset() + core::mem::size_of::<T>() <= PAGE_SIZE,
+        "{:?} with size {} crosses a page boundary",
+        va,
+        core::mem::size_of::<T>()
+    );
+}
+
+// 把应用地址空间中的指针转换为内核可以直接访问的引用，要求T不跨页
+pub fn translated_ref<T>(token: usize, ptr: *const T) -> &'static T {
     let va = VirtAddr::from(ptr as usize);
-    let vpn = va.floor();
-    let offset = va.page_offset();
-    let ppn = page_table.translate(vpn).unwrap().ppn();
-    let pa: PhysAddr = (usize::from(PhysAddr::from(ppn)) + offset).into();
-    unsafe {
-        let ptr_pa = (pa.0 as *mut T).as_mut().unwrap();
-        *ptr_pa = value;
+    assert_within_page::<T>(va);
+    let ppn = translated_user_ppn(&PageTable::from_token(token), va, false);
+    PhysAddr::from(PhysAddr::from(ppn).0 + va.page_offset()).get_ref()
+}
+
+// 同 translated_ref，但得到可变引用，要求T不跨页
+pub fn translated_refmut<T>(token: usize, ptr: *mut T) -> &'static mut T {
+    let va = VirtAddr::from(ptr as usize);
+    assert_within_page::<T>(va);
+    translated_pa_for_write(&PageTable::from_token(token), va).get_mut()
+}
+
+// 从某个应用的虚拟地址空间中读出以 \0 结尾的字符串，逐字节查页表，可以跨页
+// 不是合法UTF-8的字节会被替换为 U+FFFD
+pub fn translated_str(token: usize, ptr: *const u8) -> String {
+    let page_table = PageTable::from_token(token);
+    let mut bytes = Vec::new();
+    let mut va = ptr as usize;
+    loop {
+        let ppn = translated_user_ppn(&page_table, VirtAddr::from(va), false);
+        let pa = PhysAddr::from(PhysAddr::from(ppn).0 + VirtAddr::from(va).page_offset());
+        let byte = unsafe { *(pa.0 as *const u8) };
+        if byte == 0 {
+            break;
+        }
+        bytes.push(byte);
+        va += 1;
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// 从某个应用的虚拟地址空间中读出裸指针指向的值
+pub fn translated_read_ptr<T: Copy>(token: usize, ptr: *const T) -> T {
+    let page_table = PageTable::from_token(token);
+    let va = VirtAddr::from(ptr as usize);
+    let ppn = translated_user_ppn(&page_table, va, false);
+    let pa = PhysAddr::from(PhysAddr::from(ppn).0 + va.page_offset());
+    unsafe { *(pa.0 as *const T) }
+}
+
+// 沿页表树回收节点页帧：先回收子节点，再回收自身。非叶子页表项（V置位而RWX都为0）指向下一级节点，
+// 末级页表项指向的是逻辑段拥有的数据页帧，不在这里回收
+fn free_node(ppn: PhysPageNum, level: usize) {
+    if level < 2 {
+        for pte in ppn.get_pte_array().iter() {
+            if pte.is_valid() && !pte.readable() && !pte.writable() && !pte.executable() {
+                free_node(pte.ppn(), level + 1);
+            }
+        }
+    }
+    drop(FrameTracker { ppn });
+}
+
+// walk 的递归部分：level 为节点的级数（根节点为0），prefix 为走到这个节点时已经确定的虚拟页号高位
+// 有R、W、X之一的是叶子，出现在上两级时是大页，记录它的起始页号
+fn walk_node(
+    ppn: PhysPageNum,
+    level: usize,
+    prefix: usize,
+    result: &mut Vec<(VirtPageNum, PhysPageNum, PTEFlags)>,
+) {
+    for (idx, pte) in ppn.get_pte_array().iter().enumerate() {
+        if !pte.is_valid() {
+            continue;
+        }
+        let vpn = (prefix << PAGE_INDEX_BITS) | idx;
+        if level < 2 && !pte.readable() && !pte.writable() && !pte.executable() {
+            walk_node(pte.ppn(), level + 1, vpn, result);
+            continue;
+        }
+        let mut vpn = vpn << (PAGE_INDEX_BITS * (2 - level));
+        // SV39 的高半部分地址是符号扩展的，页号也要相应扩展
+        if vpn & (1 << 26) != 0 {
+            vpn |= (usize::MAX >> PAGE_SIZE_BITS) & !((1 << 27) - 1);
+        }
+        result.push((VirtPageNum(vpn), pte.ppn(), pte.flags()));
+    }
+}
+
+impl Drop for PageTable {
+    fn drop(&mut self) {
+        if self.owned {
+            free_node(self.root_ppn, 0);
+        }
+    }
+}
+
+#[allow(unused)]
+// 测试U、G标志位的判断，各自只看自己的位
+pub fn user_global_bits_test() {
+    let ppn = PhysPageNum(0x80400);
+    let pte = PageTableEntry::new(ppn, PTEFlags::V | PTEFlags::R);
+    assert!(!pte.user_accessible() && !pte.is_global());
+    let pte = PageTableEntry::new(ppn, PTEFlags::V | PTEFlags::R | PTEFlags::U);
+    assert!(pte.user_accessible() && !pte.is_global());
+    let pte = PageTableEntry::new(ppn, PTEFlags::V | PTEFlags::R | PTEFlags::X | PTEFlags::G);
+    assert!(!pte.user_accessible() && pte.is_global());
+    let pte = PageTableEntry::new(ppn, PTEFlags::V | PTEFlags::U | PTEFlags::G);
+    assert!(pte.user_accessible() && pte.is_global());
+    assert!(!PageTableEntry::empty().user_accessible() && !PageTableEntry::empty().is_global());
+    info!("user_global_bits_test passed!");
+}
+
+#[allow(unused)]
+// 测试读出跨越两页的字符串
+pub fn translated_str_test() {
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    let vpn = VirtPageNum(0x10000);
+    page_table.map(vpn, frames[0].ppn, PTEFlags::R | PTEFlags::U).unwrap();
+    page_table.map(VirtPageNum(vpn.0 + 1), frames[1].ppn, PTEFlags::R | PTEFlags::U).unwrap();
+    let text = b"hello, rCore\0";
+    let split = 5;
+    frames[0].ppn.get_bytes_array()[PAGE_SIZE - split..].copy_from_slice(&text[..split]);
+    frames[1].ppn.get_bytes_array()[..text.len() - split].copy_from_slice(&text[split..]);
+    let ptr = (VirtAddr::from(VirtPageNum(vpn.0 + 1)).0 - split) as *const u8;
+    assert_eq!(translated_str(page_table.token(), ptr), "hello, rCore");
+    // 从中间开始读，以及空字符串
+    assert_eq!(translated_str(page_table.token(), ptr.wrapping_add(7)), "rCore");
+    let end = ptr.wrapping_add(text.len() - 1);
+    assert_eq!(translated_str(page_table.token(), end), "");
+    info!("translated_str_test passed!");
+}
+
+#[allow(unused)]
+// 测试通过 translated_refmut 写入的值可以通过 translated_ref 读回，并且落在对应页帧的偏移处
+pub fn translated_ref_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x10000);
+    page_table.map(vpn, frame.ppn, PTEFlags::R | PTEFlags::W | PTEFlags::U).unwrap();
+    let ptr = (VirtAddr::from(vpn).0 + PAGE_SIZE - 8) as *mut u64;
+    *translated_refmut(page_table.token(), ptr) = 0x1234_5678_9abc_def0;
+    assert_eq!(*translated_ref(page_table.token(), ptr as *const u64), 0x1234_5678_9abc_def0);
+    assert_eq!(frame.ppn.get_bytes_array()[PAGE_SIZE - 8], 0xf0);
+    info!("translated_ref_test passed!");
+}
+
+#[allow(unused)]
+// 测试跨页的结构体被完整写入两个页帧
+pub fn assign_across_pages_test() {
+    use crate::syscall::process::TimeVal;
+    let mut page_table = PageTable::new();
+    let frames = [frame_alloc().unwrap(), frame_alloc().unwrap()];
+    let vpn = VirtPageNum(0x10000);
+    let flags = PTEFlags::R | PTEFlags::W | PTEFlags::U;
+    page_table.map(vpn, frames[0].ppn, flags).unwrap();
+    page_table.map(VirtPageNum(vpn.0 + 1), frames[1].ppn, flags).unwrap();
+    let ptr = (VirtAddr::from(VirtPageNum(vpn.0 + 1)).0 - 4) as *mut TimeVal;
+    let value = TimeVal {
+        sec: 0x0102_0304_0506_0708,
+        usec: 0x1112_1314_1516_1718,
+    };
+    translated_assign_ptr(page_table.token(), ptr, value);
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&frames[0].ppn.get_bytes_array()[PAGE_SIZE - 4..]);
+    bytes.extend_from_slice(&frames[1].ppn.get_bytes_array()[..12]);
+    let mut expected = Vec::new();
+    expected.extend_from_slice(&0x0102_0304_0506_0708usize.to_le_bytes());
+    expected.extend_from_slice(&0x1112_1314_1516_1718usize.to_le_bytes());
+    assert_eq!(bytes, expected);
+    // 第二个页帧中结构体之后的字节没有被碰到
+    assert!(frames[1].ppn.get_bytes_array()[12..].iter().all(|byte| *byte == 0));
+    info!("assign_across_pages_test passed!");
+}
+
+#[allow(unused)]
+// 测试重复映射返回错误而不是panic
+pub fn map_twice_test() {
+    let mut page_table = PageTable::new();
+    let frame = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x10000);
+    assert_eq!(page_table.map(vpn, frame.ppn, PTEFlags::R), Ok(()));
+    assert_eq!(
+        page_table.map(vpn, frame.ppn, PTEFlags::R),
+        Err(PageTableError::AlreadyMapped(vpn))
+    );
+    info!("map_twice_test passed!");
+}
+
+#[allow(unused)]
+// 测试内核堆耗尽时页表仍能新建节点：把堆占满（只留页帧），新建页表并映射需要新节点的页面，
+// 之后释放页表，节点页帧全部归还
+pub fn heap_pressure_map_test() {
+    use super::frame_remain_num;
+    use super::heap_allocator::HEAP_ALLOCATOR;
+    use core::alloc::Layout;
+    use core::ptr::NonNull;
+    // 占住的块串成链表，链接就存放在块自己里面，记录本身不再需要堆
+    let mut hoard: Option<NonNull<u8>> = None;
+    let mut size = crate::config::KERNEL_HEAP_SIZE;
+    while size >= 16 {
+        let layout = Layout::from_size_align(size, 16).unwrap();
+        match HEAP_ALLOCATOR.lock().alloc(layout) {
+            Ok(block) => unsafe {
+                let words = block.as_ptr() as *mut usize;
+                words.write(hoard.map_or(0, |next| next.as_ptr() as usize));
+                words.add(1).write(size);
+                hoard = Some(block);
+            },
+            Err(_) => size /= 2,
+        }
     }
+    // 堆里最多只剩零碎的小块，任何需要存放一个节点记录的分配都会失败
+    assert!(HEAP_ALLOCATOR.lock().alloc(Layout::from_size_align(16, 16).unwrap()).is_err());
+    let frames_before = frame_remain_num();
+    let mut page_table = PageTable::new();
+    let data = frame_alloc().unwrap();
+    let vpn = VirtPageNum(0x1_0000);
+    page_table.map(vpn, data.ppn, PTEFlags::R | PTEFlags::U).unwrap();
+    assert_eq!(page_table.node_count(), 3);
+    assert_eq!(page_table.translate(vpn).unwrap().ppn(), data.ppn);
+    // 先把堆还回去，回收页帧时分配器的回收栈可能要增长
+    while let Some(block) = hoard {
+        unsafe {
+            let words = block.as_ptr() as *const usize;
+            let (next, size) = (words.read(), words.add(1).read());
+            HEAP_ALLOCATOR
+                .lock()
+                .dealloc(block, Layout::from_size_align(size, 16).unwrap());
+            hoard = NonNull::new(next as *mut u8);
+        }
+    }
+    drop(page_table);
+    drop(data);
+    assert_eq!(frame_remain_num(), frames_before);
+    info!("heap_pressure_map_test passed!");
+}
+
+#[allow(unused)]
+// 测试遍历页表：映射三个分散在不同子树里的页面，walk 正好返回这三个映射和各自的标志位，
+// 按虚拟页号排列，遍历不新建节点；解除一个映射后只剩两个
+pub fn walk_test() {
+    let mut page_table = PageTable::new();
+    let frames: Vec<FrameTracker> = (0..3).map(|_| frame_alloc().unwrap()).collect();
+    let mappings = [
+        (VirtPageNum(0x10), PTEFlags::R | PTEFlags::U),
+        (VirtPageNum(0x10 + (1 << 9)), PTEFlags::R | PTEFlags::W | PTEFlags::U),
+        (VirtPageNum(0x3 << 18), PTEFlags::R | PTEFlags::X),
+    ];
+    // 倒序映射，检查结果按页号排列
+    for ((vpn, flags), frame) in mappings.iter().zip(frames.iter()).rev() {
+        page_table.map(*vpn, frame.ppn, *flags).unwrap();
+    }
+    let nodes = page_table.node_count();
+    let walked = page_table.walk();
+    assert_eq!(walked.len(), 3);
+    for (((vpn, ppn, flags), (expected_vpn, expected_flags)), frame) in
+        walked.iter().zip(mappings.iter()).zip(frames.iter())
+    {
+        assert_eq!(vpn, expected_vpn);
+        assert_eq!(*ppn, frame.ppn);
+        assert_eq!(*flags, *expected_flags | PTEFlags::V);
+    }
+    assert_eq!(page_table.node_count(), nodes);
+    page_table.dump();
+    page_table.unmap(mappings[1].0);
+    let vpns: Vec<VirtPageNum> = page_table.walk().iter().map(|(vpn, _, _)| *vpn).collect();
+    assert_eq!(vpns, [mappings[0].0, mappings[2].0]);
+    // 空页表没有映射
+    assert!(PageTable::new().walk().is_empty());
+    info!("walk_test passed!");
+}
+
+#[allow(unused)]
+// 测试大页映射：中间一级的一个叶子映射 2MiB，大页内的地址都能翻译到对应的物理地址，walk 只看到一个映射；
+// 被大页覆盖的页面不能再单独映射，已有末级节点的区域不能放大页，解除后翻译失败
+pub fn megapage_test() {
+    let mut page_table = PageTable::new();
+    let vpn = VirtPageNum(0x4000_0000 >> PAGE_SIZE_BITS);
+    // 只查页表不访问数据，借内核镜像开头这段对齐的物理内存即可
+    let ppn = PhysPageNum(0x8020_0000 >> PAGE_SIZE_BITS);
+    let flags = PTEFlags::R | PTEFlags::W;
+    page_table.map_huge(vpn, ppn, flags).unwrap();
+    // 根节点加一个中间节点，没有末级节点
+    assert_eq!(page_table.node_count(), 2);
+    let va = VirtAddr::from(0x4000_0000 + 0x12_3456);
+    assert_eq!(page_table.translate_va(va), Some(PhysAddr::from(0x8020_0000 + 0x12_3456)));
+    let pte = page_table.translate(va.floor()).unwrap();
+    assert_eq!(pte.ppn(), PhysPageNum(ppn.0 + 0x123));
+    assert!(pte.readable() && pte.writable() && !pte.executable());
+    assert_eq!(page_table.walk(), [(vpn, ppn, flags | PTEFlags::V)]);
+    let inside = VirtPageNum(vpn.0 + 1);
+    assert_eq!(page_table.map(inside, ppn, PTEFlags::R), Err(PageTableError::AlreadyMapped(inside)));
+    assert_eq!(page_table.map_huge(vpn, ppn, flags), Err(PageTableError::AlreadyMapped(vpn)));
+    assert!(!page_table.set_flags(inside, PTEFlags::R));
+    let next = VirtPageNum(vpn.0 + HUGE_FRAME_PAGES);
+    page_table.map(next, ppn, PTEFlags::R).unwrap();
+    assert_eq!(page_table.map_huge(next, ppn, flags), Err(PageTableError::AlreadyMapped(next)));
+    page_table.unmap_huge(vpn);
+    assert!(page_table.translate_va(va).is_none());
+    assert_eq!(page_table.walk().len(), 1);
+    info!("megapage_test passed!");
 }
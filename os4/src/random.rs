@@ -0,0 +1,43 @@
+use crate::sync::UPSafeCell;
+use crate::timer::get_time;
+use lazy_static::*;
+
+// xorshift64 伪随机数发生器。每个地址空间（也就是每个任务）持有一个，互不影响
+#[derive(Clone, Copy, Debug)]
+pub struct Prng {
+    state: u64,
+}
+
+impl Prng {
+    // 用给定的种子新建，相同的种子产生相同的序列；xorshift的状态不能为0，种子为0时换一个固定值
+    pub fn new(seed: u64) -> Self {
+        Self {
+            state: if seed == 0 { 0x2545_f491_4f6c_dd1d } else { seed },
+        }
+    }
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+    // [0, bound) 中的一个随机数，bound不能为0
+    pub fn below(&mut self, bound: usize) -> usize {
+        self.next_u64() as usize % bound
+    }
+}
+
+lazy_static! {
+    // 已经发出去的种子个数，保证同一时刻新建的两个发生器种子也不同
+    static ref SEED_COUNT: UPSafeCell<u64> = unsafe { UPSafeCell::new(0) };
+}
+
+// 给新建的发生器取一个种子：当前时钟加上递增的计数，再用splitmix64打散
+pub fn next_seed() -> u64 {
+    let mut count = SEED_COUNT.exclusive_access();
+    *count += 1;
+    let mut z = (get_time() as u64).wrapping_add(count.wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
@@ -4,6 +4,11 @@ const SBI_SET_TIMER: usize = 0;
 const SBI_CONSOLE_PUTCHAR: usize = 1;
 const SBI_CONSOLE_GETCHAR: usize = 2;
 const SBI_SHUTDOWN: usize = 8;
+// SBI v0.2 之后的扩展，extension id 放在 a7，function id 放在 a6
+const SBI_EXT_BASE: usize = 0x10;
+const SBI_BASE_PROBE_EXTENSION: usize = 3;
+const SBI_EXT_DBCN: usize = 0x4442_434E;
+const SBI_DBCN_CONSOLE_WRITE: usize = 0;
 
 #[inline(always)]
 fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
@@ -20,6 +25,23 @@ fn sbi_call(which: usize, arg0: usize, arg1: usize, arg2: usize) -> usize {
     ret
 }
 
+// 调用新式的SBI扩展，返回 (错误码, 返回值)
+#[inline(always)]
+fn sbi_call_ext(eid: usize, fid: usize, arg0: usize, arg1: usize, arg2: usize) -> (isize, usize) {
+    let (error, value);
+    unsafe {
+        core::arch::asm!(
+            "ecall",
+            inlateout("x10") arg0 => error,
+            inlateout("x11") arg1 => value,
+            in("x12") arg2,
+            in("x16") fid,
+            in("x17") eid,
+        );
+    }
+    (error, value)
+}
+
 pub fn set_timer(timer: usize) {
     sbi_call(SBI_SET_TIMER, timer, 0, 0);
 }
@@ -28,6 +50,29 @@ pub fn console_putchar(c: usize) {
     sbi_call(SBI_CONSOLE_PUTCHAR, c, 0, 0);
 }
 
+// 探测SBI实现是否支持调试控制台扩展（DBCN），支持时可以一次调用写出一整段字节
+pub fn console_write_supported() -> bool {
+    let (error, value) = sbi_call_ext(SBI_EXT_BASE, SBI_BASE_PROBE_EXTENSION, SBI_EXT_DBCN, 0, 0);
+    error == 0 && value != 0
+}
+
+// 一次SBI调用写出一段字节，返回实际写出的字节数，失败时返回None
+// SBI要求的是物理地址，bytes必须位于内核中恒等映射的区域（例如静态变量）
+pub fn console_write(bytes: &[u8]) -> Option<usize> {
+    let (error, written) = sbi_call_ext(
+        SBI_EXT_DBCN,
+        SBI_DBCN_CONSOLE_WRITE,
+        bytes.len(),
+        bytes.as_ptr() as usize,
+        0,
+    );
+    if error == 0 {
+        Some(written)
+    } else {
+        None
+    }
+}
+
 pub fn console_getchar() -> usize {
     sbi_call(SBI_CONSOLE_GETCHAR, 0, 0, 0)
 }
@@ -1,5 +1,9 @@
 //! Synchronization and interior mutability primitives
 
+mod rwlock;
 mod up;
 
+pub use rwlock::UPRwLock;
+#[cfg(feature = "boot_test")]
+pub use rwlock::rwlock_test;
 pub use up::UPSafeCell;
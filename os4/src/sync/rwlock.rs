@@ -0,0 +1,60 @@
+//! Uniprocessor read-write lock
+
+use core::cell::{Ref, RefCell, RefMut};
+
+/// A read-write lock for read-heavy kernel state.
+///
+/// Any number of readers may hold the lock at the same time, while a writer
+/// gets exclusive access. Like [`super::UPSafeCell`], we should only use it in
+/// uniprocessor, and a conflicting borrow panics instead of spinning.
+pub struct UPRwLock<T> {
+    /// inner data
+    inner: RefCell<T>,
+}
+
+unsafe impl<T> Sync for UPRwLock<T> {}
+
+impl<T> UPRwLock<T> {
+    /// User is responsible to guarantee that inner struct is only used in
+    /// uniprocessor.
+    pub unsafe fn new(value: T) -> Self {
+        Self {
+            inner: RefCell::new(value),
+        }
+    }
+    /// Get shared access. Panic if the data is being written.
+    pub fn read(&self) -> Ref<'_, T> {
+        self.inner.borrow()
+    }
+    /// Get exclusive access. Panic if the data is being read or written.
+    pub fn write(&self) -> RefMut<'_, T> {
+        self.inner.borrow_mut()
+    }
+    /// Get shared access, or `None` if the data is being written.
+    pub fn try_read(&self) -> Option<Ref<'_, T>> {
+        self.inner.try_borrow().ok()
+    }
+    /// Get exclusive access, or `None` if the data is being read or written.
+    pub fn try_write(&self) -> Option<RefMut<'_, T>> {
+        self.inner.try_borrow_mut().ok()
+    }
+}
+
+#[allow(unused)]
+/// Check that readers share the lock while a writer excludes everyone.
+pub fn rwlock_test() {
+    let lock = unsafe { UPRwLock::new(5usize) };
+    let r1 = lock.read();
+    let r2 = lock.read();
+    assert_eq!(*r1 + *r2, 10);
+    assert!(lock.try_write().is_none());
+    drop(r1);
+    drop(r2);
+    let mut w = lock.write();
+    *w = 6;
+    assert!(lock.try_read().is_none());
+    assert!(lock.try_write().is_none());
+    drop(w);
+    assert_eq!(*lock.read(), 6);
+    info!("rwlock_test passed!");
+}
@@ -0,0 +1,10 @@
+//! Error numbers returned (negated) by syscalls
+
+/// Bad file descriptor
+pub const EBADF: isize = 9;
+/// Out of memory
+pub const ENOMEM: isize = 12;
+/// File exists (the range is already mapped)
+pub const EEXIST: isize = 17;
+/// Invalid argument
+pub const EINVAL: isize = 22;
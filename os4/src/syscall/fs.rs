@@ -1,21 +1,38 @@
 //! File and filesystem-related syscalls
 
+use super::errno::EBADF;
 use crate::mm::translated_byte_buffer;
-use crate::task::current_user_token;
-
-const FD_STDOUT: usize = 1;
+use crate::task::{current_fd, current_user_token, FileDescriptor};
 
+// 写文件，目前只有标准输出和标准错误可写，未打开或不可写的描述符返回-EBADF
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
-    match fd {
-        FD_STDOUT => {
+    match current_fd(fd) {
+        Some(FileDescriptor::Stdout) | Some(FileDescriptor::Stderr) => {
             let buffers = translated_byte_buffer(current_user_token(), buf, len);
             for buffer in buffers {
                 print!("{}", core::str::from_utf8(buffer).unwrap());
             }
             len as isize
         }
-        _ => {
-            panic!("Unsupported fd in sys_write!");
-        }
+        _ => -EBADF,
     }
 }
+
+#[allow(unused)]
+// 测试写标准输出返回写入的字节数，写未打开的描述符和标准输入返回-EBADF
+pub fn sys_write_test() {
+    use crate::task::{mmap_in_current_memory_set, munmap_in_current_memory_set};
+    let start: usize = 0x6000_0000;
+    let message = b"sys_write_test: hello\n";
+    assert_eq!(mmap_in_current_memory_set(start, 4096, 0b011), 0);
+    let mut buffers = translated_byte_buffer(current_user_token(), start as *const u8, message.len());
+    buffers[0].copy_from_slice(message);
+    let buf = start as *const u8;
+    assert_eq!(sys_write(1, buf, message.len()), message.len() as isize);
+    assert_eq!(sys_write(2, buf, message.len()), message.len() as isize);
+    assert_eq!(sys_write(0, buf, message.len()), -EBADF);
+    assert_eq!(sys_write(5, buf, message.len()), -EBADF);
+    assert_eq!(sys_write(usize::MAX, buf, message.len()), -EBADF);
+    assert_eq!(munmap_in_current_memory_set(start, 4096), 1);
+    info!("sys_write_test passed!");
+}
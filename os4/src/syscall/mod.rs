@@ -12,34 +12,168 @@
 
 const SYSCALL_WRITE: usize = 64;
 const SYSCALL_EXIT: usize = 93;
+const SYSCALL_SLEEP: usize = 101;
+const SYSCALL_SCHED_SETAFFINITY: usize = 122;
+const SYSCALL_KILL: usize = 129;
+const SYSCALL_SCHED_GETAFFINITY: usize = 123;
 const SYSCALL_YIELD: usize = 124;
 const SYSCALL_GET_TIME: usize = 169;
+const SYSCALL_GETPID: usize = 172;
+const SYSCALL_GETPPID: usize = 173;
+const SYSCALL_SETUID: usize = 146;
+const SYSCALL_GETUID: usize = 174;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+const SYSCALL_SBRK: usize = 214;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_MMAP: usize = 222;
+const SYSCALL_MPROTECT: usize = 226;
+const SYSCALL_MADVISE: usize = 233;
 const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_SPAWN: usize = 400;
 const SYSCALL_TASK_INFO: usize = 410;
+const SYSCALL_VMSTAT: usize = 411;
+const SYSCALL_PS: usize = 412;
+const SYSCALL_MMAP_WITH_DATA: usize = 413;
+const SYSCALL_SET_DEADLINE: usize = 414;
+const SYSCALL_PROFILE_DUMP: usize = 415;
+const SYSCALL_MMAP_DMA: usize = 416;
+const SYSCALL_MMAP_PID: usize = 417;
+const SYSCALL_MUNMAP_PID: usize = 418;
+const SYSCALL_SINGLE_STEP: usize = 419;
 
+pub mod errno;
 mod fs;
 pub mod process;
 
+#[cfg(feature = "boot_test")]
+pub use fs::sys_write_test;
+
 use fs::*;
 use process::*;
 
 use crate::task::update_syscall_times;
 
 /// handle syscall exception with `syscall_id` and other arguments
-pub fn syscall(syscall_id: usize, args: [usize; 3]) -> isize {
+pub fn syscall(syscall_id: usize, args: [usize; 6]) -> isize {
     // LAB1: You may need to update syscall info here.
     update_syscall_times(syscall_id);
+    dispatch(syscall_id, args)
+}
+
+/// Route a syscall to its handler, extracting each argument with the type
+/// the handler expects. `args` holds `a0`-`a5` of the trap context.
+pub fn dispatch(syscall_id: usize, args: [usize; 6]) -> isize {
     match syscall_id {
-        SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
-        SYSCALL_EXIT => sys_exit(args[0] as i32),
+        SYSCALL_WRITE => sys_write(as_usize(args[0]), as_ptr(args[1]), as_usize(args[2])),
+        SYSCALL_EXIT => sys_exit(as_i32(args[0])),
         SYSCALL_YIELD => sys_yield(),
-        SYSCALL_GET_TIME => sys_get_time(args[0] as *mut TimeVal, args[1]),
-        SYSCALL_MMAP => sys_mmap(args[0], args[1], args[2]),
-        SYSCALL_MUNMAP => sys_munmap(args[0], args[1]),
-        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
-        SYSCALL_TASK_INFO => sys_task_info(args[0] as *mut TaskInfo),
+        SYSCALL_SLEEP => sys_sleep(as_usize(args[0])),
+        SYSCALL_KILL => sys_kill(as_usize(args[0]), as_usize(args[1])),
+        SYSCALL_SCHED_SETAFFINITY => {
+            sys_sched_setaffinity(as_usize(args[0]), as_usize(args[1]), as_ptr(args[2]))
+        }
+        SYSCALL_SCHED_GETAFFINITY => {
+            sys_sched_getaffinity(as_usize(args[0]), as_usize(args[1]), as_mut_ptr(args[2]))
+        }
+        SYSCALL_GET_TIME => sys_get_time(as_mut_ptr(args[0]), as_usize(args[1])),
+        SYSCALL_GETPID => sys_getpid(),
+        SYSCALL_GETPPID => sys_getppid(),
+        SYSCALL_SPAWN => sys_spawn(as_ptr(args[0])),
+        SYSCALL_GETUID => sys_getuid(),
+        SYSCALL_SETUID => sys_setuid(as_usize(args[0])),
+        SYSCALL_GETRLIMIT => sys_getrlimit(as_usize(args[0]), as_mut_ptr(args[1])),
+        SYSCALL_SETRLIMIT => sys_setrlimit(as_usize(args[0]), as_ptr(args[1])),
+        SYSCALL_MMAP => sys_mmap(as_usize(args[0]), as_usize(args[1]), as_usize(args[2])),
+        SYSCALL_MMAP_WITH_DATA => sys_mmap_with_data(
+            as_usize(args[0]),
+            as_usize(args[1]),
+            as_usize(args[2]),
+            as_ptr(args[3]),
+            as_usize(args[4]),
+        ),
+        SYSCALL_MMAP_DMA => sys_mmap_dma(as_usize(args[0]), as_usize(args[1])),
+        SYSCALL_SBRK => sys_sbrk(as_i32(args[0])),
+        SYSCALL_MUNMAP => sys_munmap(as_usize(args[0]), as_usize(args[1])),
+        SYSCALL_MMAP_PID => sys_mmap_pid(
+            as_usize(args[0]),
+            as_usize(args[1]),
+            as_usize(args[2]),
+            as_usize(args[3]),
+        ),
+        SYSCALL_MUNMAP_PID => {
+            sys_munmap_pid(as_usize(args[0]), as_usize(args[1]), as_usize(args[2]))
+        }
+        SYSCALL_MPROTECT => sys_mprotect(as_usize(args[0]), as_usize(args[1]), as_usize(args[2])),
+        SYSCALL_MADVISE => sys_madvise(as_usize(args[0]), as_usize(args[1]), as_usize(args[2])),
+        SYSCALL_SET_DEADLINE => sys_set_deadline(as_usize(args[0])),
+        SYSCALL_SINGLE_STEP => sys_single_step(as_usize(args[0])),
+        SYSCALL_SET_PRIORITY => sys_set_priority(as_isize(args[0])),
+        SYSCALL_TASK_INFO => sys_task_info(as_mut_ptr(args[0])),
+        SYSCALL_VMSTAT => sys_vmstat(as_mut_ptr(args[0])),
+        SYSCALL_PS => sys_ps(as_mut_ptr(args[0]), as_usize(args[1])),
+        SYSCALL_PROFILE_DUMP => sys_profile_dump(as_mut_ptr(args[0]), as_usize(args[1])),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
+
+// 把系统调用参数解释为各种类型，指针都是用户地址空间中的地址，由具体的系统调用负责翻译
+
+fn as_usize(arg: usize) -> usize {
+    arg
+}
+
+fn as_isize(arg: usize) -> isize {
+    arg as isize
+}
+
+fn as_i32(arg: usize) -> i32 {
+    arg as i32
+}
+
+fn as_ptr<T>(arg: usize) -> *const T {
+    arg as *const T
+}
+
+fn as_mut_ptr<T>(arg: usize) -> *mut T {
+    arg as *mut T
+}
+
+#[allow(unused)]
+/// Feed synthetic argument arrays through `dispatch` and check that each
+/// lands in the right handler with its arguments in the right positions.
+///
+/// Only uses paths that return before touching user memory.
+pub fn dispatch_test() {
+    use crate::task::current_task_id;
+    use errno::EINVAL;
+    assert_eq!(as_i32(-3isize as usize), -3);
+    assert_eq!(as_isize(usize::MAX), -1);
+    assert_eq!(as_ptr::<u8>(0x1000) as usize, 0x1000);
+    assert_eq!(dispatch(SYSCALL_GETPID, [0; 6]), current_task_id() as isize);
+    // 长度参数在第二个位置，过短时返回 -EINVAL
+    assert_eq!(dispatch(SYSCALL_SCHED_SETAFFINITY, [0, 1, 0x1000, 0, 0, 0]), -EINVAL);
+    assert_eq!(dispatch(SYSCALL_SCHED_GETAFFINITY, [0, 1, 0x1000, 0, 0, 0]), -EINVAL);
+    // 资源编号在第一个位置
+    assert_eq!(dispatch(SYSCALL_GETRLIMIT, [RLIMIT_AS + 1, 0x1000, 0, 0, 0, 0]), -EINVAL);
+    // 建议在第三个位置
+    assert_eq!(dispatch(SYSCALL_MADVISE, [0x1000_0000, 0x1000, MADV_DONTNEED + 1, 0, 0, 0]), -EINVAL);
+    assert_eq!(dispatch(SYSCALL_SET_PRIORITY, [usize::MAX, 0, 0, 0, 0, 0]), -1);
+    info!("dispatch_test passed!");
+}
+
+#[allow(unused)]
+// 测试系统调用计数：经过 syscall 入口的调用逐次计数，超出统计范围的ID被忽略
+pub fn syscall_times_test() {
+    use crate::config::MAX_SYSCALL_NUM;
+    use crate::task::get_task_info;
+    let before = get_task_info().syscall_times[SYSCALL_GETPID];
+    for _ in 0..5 {
+        syscall(SYSCALL_GETPID, [0; 6]);
+    }
+    assert_eq!(get_task_info().syscall_times[SYSCALL_GETPID], before + 5);
+    update_syscall_times(MAX_SYSCALL_NUM);
+    update_syscall_times(usize::MAX);
+    assert_eq!(get_task_info().syscall_times[SYSCALL_GETPID], before + 5);
+    info!("syscall_times_test passed!");
+}
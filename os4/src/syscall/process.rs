@@ -1,9 +1,14 @@
 //! Process management syscalls
 
 use crate::config::MAX_SYSCALL_NUM;
-use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, current_user_token, mmap_in_current_memory_set, munmap_in_current_memory_set, get_task_info, TaskStatus};
+use crate::task::{exit_current_and_run_next, suspend_current_and_run_next, sleep_current_and_run_next, current_user_token, mmap_in_current_memory_set, mmap_with_data_in_current_memory_set, munmap_in_current_memory_set, mprotect_in_current_memory_set, get_task_info, get_vmstat, list_tasks, set_current_deadline, current_uid, set_current_uid, current_profile, current_rlimit_as, set_current_rlimit_as, populate_in_current_memory_set, discard_in_current_memory_set, grow_brk_in_current_memory_set, mmap_identical_in_current_memory_set, current_task_id, current_pid, current_parent_id, spawn, set_affinity, get_affinity, set_current_priority, mmap_in_memory_set_of, munmap_in_memory_set_of, with_task, TaskStatus};
 use crate::timer::get_time_us;
-use crate::mm::translated_assign_ptr;
+use crate::trap::set_single_step;
+use crate::loader::get_app_by_name;
+use crate::mm::{translated_assign_ptr, translated_byte_buffer, translated_read_ptr, translated_str};
+use crate::config::RLIM_INFINITY;
+use crate::syscall::errno::EINVAL;
+use alloc::vec::Vec;
 
 #[repr(C)]
 #[derive(Debug)]
@@ -12,16 +17,68 @@ pub struct TimeVal {
     pub usec: usize,
 }
 
+#[repr(C)]
 #[derive(Clone, Debug, Copy)]
 pub struct TaskInfo {
     pub status: TaskStatus,
     pub syscall_times: [u32; MAX_SYSCALL_NUM],
     pub time: usize,
+    pub total_sched_latency_us: usize, // 累计的调度延迟（us）
+    pub total_blocked_us: usize, // 累计的阻塞时间（us）
+}
+
+// 地址空间中各权限组合的用户页面数
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct VmStat {
+    pub r: usize,
+    pub rw: usize,
+    pub rx: usize,
+    pub rwx: usize,
+    pub other: usize,
+}
+
+// 任务列表中的一项，名字以0填充，过长时截断
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct TaskListEntry {
+    pub pid: usize,
+    pub status: TaskStatus,
+    pub name: [u8; 32],
+}
+
+// 采样直方图中的一个桶
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct ProfileBucket {
+    pub start: usize,
+    pub count: usize,
 }
 
+// 资源限制，cur是软限制，max是硬限制（目前总是不限制）
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct RLimit {
+    pub cur: usize,
+    pub max: usize,
+}
+
+// 地址空间大小的资源编号，与Linux一致
+pub const RLIMIT_AS: usize = 9;
+
+// madvise 的建议：即将访问，提前分配页帧，与Linux一致
+pub const MADV_WILLNEED: usize = 3;
+pub const MADV_DONTNEED: usize = 4;
+
+// 信号编号的个数，待处理信号用一个u64位掩码记录
+pub const SIGNAL_NUM: usize = 64;
+// 结束任务的信号，编号与Linux一致
+pub const SIGKILL: usize = 9;
+pub const SIGTERM: usize = 15;
+
 pub fn sys_exit(exit_code: i32) -> ! {
     info!("[kernel] Application exited with code {}", exit_code);
-    exit_current_and_run_next();
+    exit_current_and_run_next(exit_code);
     panic!("Unreachable in sys_exit!");
 }
 
@@ -31,9 +88,16 @@ pub fn sys_yield() -> isize {
     0
 }
 
+// 当前任务睡眠ms毫秒，期间不参与调度，到时后由调度器唤醒
+pub fn sys_sleep(ms: usize) -> isize {
+    sleep_current_and_run_next(get_time_us().saturating_add(ms.saturating_mul(1000)));
+    0
+}
+
 // YOUR JOB: 引入虚地址后重写 sys_get_time
 pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
     let us = get_time_us();
+    // 用户给的指针可能让结构体跨页，用按页拷贝的 translated_assign_ptr 写入
     translated_assign_ptr(
         current_user_token(),
         ts,
@@ -45,9 +109,55 @@ pub fn sys_get_time(ts: *mut TimeVal, _tz: usize) -> isize {
     0
 }
 
+// 获取当前任务的进程号
+pub fn sys_getpid() -> isize {
+    current_pid() as isize
+}
+
+// 获取父任务的编号，没有父任务时返回0
+pub fn sys_getppid() -> isize {
+    current_parent_id() as isize
+}
+
+// 按名字找到应用，新建一个运行它的子任务，返回子任务的进程号。应用不存在或任务数已满时返回-1
+pub fn sys_spawn(path: *const u8) -> isize {
+    let name = translated_str(current_user_token(), path);
+    match get_app_by_name(&name).and_then(spawn) {
+        Some(pid) => pid as isize,
+        None => -1,
+    }
+}
+
+// 获取当前任务的用户号
+pub fn sys_getuid() -> isize {
+    current_uid() as isize
+}
+
+// 设置当前任务的用户号，只有root任务可以调用成功，否则返回-1
+pub fn sys_setuid(uid: usize) -> isize {
+    set_current_uid(uid)
+}
+
+// 设置当前任务的截止时间为ms毫秒之后，供EDF调度使用
+pub fn sys_set_deadline(ms: usize) -> isize {
+    set_current_deadline(Some(get_time_us().saturating_add(ms.saturating_mul(1000))));
+    0
+}
+
+// 打开（enable非0）或关闭当前任务的单步调试，打开后每执行一条指令就陷入内核报告一次sepc
+pub fn sys_single_step(enable: usize) -> isize {
+    set_single_step(enable != 0);
+    0
+}
+
 // CLUE: 从 ch4 开始不再对调度算法进行测试~
-pub fn sys_set_priority(_prio: isize) -> isize {
-    -1
+// 设置当前任务的优先级，供stride调度使用，优先级小于2时返回-1，成功时返回设置的优先级
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < 2 {
+        return -1;
+    }
+    set_current_priority(prio as usize);
+    prio
 }
 
 
@@ -56,16 +166,306 @@ pub fn sys_mmap(start: usize, len: usize, port: usize) -> isize {
     mmap_in_current_memory_set(start, len, port)
 }
 
+// 映射一段虚拟地址与物理地址相同的DMA缓冲区，返回其地址，只有root任务可以调用
+pub fn sys_mmap_dma(len: usize, port: usize) -> isize {
+    if current_uid() != 0 {
+        return -1;
+    }
+    mmap_identical_in_current_memory_set(len, port)
+}
+
+// 分配内存并用用户缓冲区中的数据初始化
+pub fn sys_mmap_with_data(
+    start: usize,
+    len: usize,
+    port: usize,
+    data: *const u8,
+    data_len: usize,
+) -> isize {
+    // 先把数据拷贝出来，避免映射过程中与用户缓冲区的页面相互影响
+    let mut buffer = Vec::with_capacity(data_len);
+    for bytes in translated_byte_buffer(current_user_token(), data, data_len) {
+        buffer.extend_from_slice(bytes);
+    }
+    mmap_with_data_in_current_memory_set(start, len, port, &buffer)
+}
+
+// 解除映射，返回实际解除映射的页数，范围内没有映射时返回0，没有页对齐或伸到trap上下文和跳板时返回-1
 pub fn sys_munmap(start: usize, len: usize) -> isize {
     munmap_in_current_memory_set(start, len)
 }
 
+// 在另一个任务的地址空间中分配内存，供管理其它任务内存的监控程序使用，只有root任务可以调用
+pub fn sys_mmap_pid(pid: usize, start: usize, len: usize, port: usize) -> isize {
+    if current_uid() != 0 {
+        return -1;
+    }
+    mmap_in_memory_set_of(pid, start, len, port)
+}
+
+// 解除另一个任务地址空间中的映射，只有root任务可以调用
+pub fn sys_munmap_pid(pid: usize, start: usize, len: usize) -> isize {
+    if current_uid() != 0 {
+        return -1;
+    }
+    munmap_in_memory_set_of(pid, start, len)
+}
+
+// 向任务pid发送信号signum，只置上它的待处理位，还没有信号处理函数
+pub fn sys_kill(pid: usize, signum: usize) -> isize {
+    if signum >= SIGNAL_NUM {
+        return -EINVAL;
+    }
+    match with_task(pid, |task| task.pending_signals |= 1 << signum) {
+        Some(()) => 0,
+        None => -EINVAL,
+    }
+}
+
+// port为0时将页面设为保护页，访问即杀死任务
+pub fn sys_mprotect(start: usize, len: usize, port: usize) -> isize {
+    mprotect_in_current_memory_set(start, len, port)
+}
+
 // YOUR JOB: 引入虚地址后重写 sys_task_info
 pub fn sys_task_info(ti: *mut TaskInfo) -> isize {
+    translated_assign_ptr(current_user_token(), ti, get_task_info());
+    0
+}
+
+// 统计当前任务地址空间中各权限组合的页面数
+pub fn sys_vmstat(buf: *mut VmStat) -> isize {
     translated_assign_ptr(
         current_user_token(),
-        ti,
-        get_task_info()
+        buf,
+        get_vmstat()
     );
     0
 }
+
+// 把所有任务的列表写入用户缓冲区，最多写len项，返回写入的项数
+pub fn sys_ps(buf: *mut TaskListEntry, len: usize) -> isize {
+    let token = current_user_token();
+    let tasks = list_tasks();
+    let count = tasks.len().min(len);
+    for (i, (pid, status, name)) in tasks.into_iter().take(count).enumerate() {
+        let mut entry = TaskListEntry {
+            pid,
+            status,
+            name: [0; 32],
+        };
+        let name_len = name.len().min(entry.name.len());
+        entry.name[..name_len].copy_from_slice(&name.as_bytes()[..name_len]);
+        translated_assign_ptr(token, buf.wrapping_add(i), entry);
+    }
+    count as isize
+}
+
+// 把当前任务采样最多的至多len个桶写入用户缓冲区（从多到少），返回写入的项数
+pub fn sys_profile_dump(buf: *mut ProfileBucket, len: usize) -> isize {
+    let token = current_user_token();
+    let buckets = current_profile(len);
+    for (i, (start, count)) in buckets.iter().enumerate() {
+        let bucket = ProfileBucket {
+            start: *start,
+            count: *count,
+        };
+        translated_assign_ptr(token, buf.wrapping_add(i), bucket);
+    }
+    buckets.len() as isize
+}
+
+// 读取资源限制，目前只支持 RLIMIT_AS
+pub fn sys_getrlimit(resource: usize, rlim: *mut RLimit) -> isize {
+    if resource != RLIMIT_AS {
+        return -EINVAL;
+    }
+    let limit = RLimit {
+        cur: current_rlimit_as(),
+        max: RLIM_INFINITY,
+    };
+    translated_assign_ptr(current_user_token(), rlim, limit);
+    0
+}
+
+// 设置资源限制，目前只支持 RLIMIT_AS；低于当前用量也可以设置，此后mmap不能再增长
+pub fn sys_setrlimit(resource: usize, rlim: *const RLimit) -> isize {
+    if resource != RLIMIT_AS {
+        return -EINVAL;
+    }
+    let limit = translated_read_ptr(current_user_token(), rlim);
+    if limit.cur > limit.max {
+        return -EINVAL;
+    }
+    set_current_rlimit_as(limit.cur);
+    0
+}
+
+// 按size字节移动program break，返回原来的break，失败时返回-1
+pub fn sys_sbrk(size: i32) -> isize {
+    match grow_brk_in_current_memory_set(size as isize) {
+        Some(old_brk) => old_brk as isize,
+        None => -1,
+    }
+}
+
+// 内存使用建议：
+// MADV_WILLNEED 立即为延迟分配的页面分配页帧，返回新分配的页数；
+// MADV_DONTNEED 归还页面的页帧但保留映射，之后访问得到全零的页面，返回归还的页帧数
+pub fn sys_madvise(start: usize, len: usize, advice: usize) -> isize {
+    match advice {
+        MADV_WILLNEED => populate_in_current_memory_set(start, len),
+        MADV_DONTNEED => discard_in_current_memory_set(start, len),
+        _ => -EINVAL,
+    }
+}
+
+// 设置任务的CPU亲和性掩码，pid为0表示当前任务；掩码为空时返回 -EINVAL
+pub fn sys_sched_setaffinity(pid: usize, len: usize, mask: *const usize) -> isize {
+    if len < core::mem::size_of::<usize>() {
+        return -EINVAL;
+    }
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    set_affinity(pid, translated_read_ptr(current_user_token(), mask))
+}
+
+// 读取任务的CPU亲和性掩码，pid为0表示当前任务，成功时返回写入的字节数
+pub fn sys_sched_getaffinity(pid: usize, len: usize, mask: *mut usize) -> isize {
+    if len < core::mem::size_of::<usize>() {
+        return -EINVAL;
+    }
+    let pid = if pid == 0 { current_task_id() } else { pid };
+    match get_affinity(pid) {
+        Some(affinity) => {
+            translated_assign_ptr(current_user_token(), mask, affinity);
+            core::mem::size_of::<usize>() as isize
+        }
+        None => -EINVAL,
+    }
+}
+
+#[allow(unused)]
+// 测试root任务可以在另一个任务的地址空间中映射内存，目标任务能读到；非root任务调用失败
+// 必须在第一个任务运行之前调用
+pub fn mmap_pid_test() {
+    use crate::config::{PAGE_SIZE, TRAP_CONTEXT};
+    use crate::mm::VirtAddr;
+    let target = 1;
+    let start: usize = 0x5000_0000;
+    assert_eq!(current_uid(), 0);
+    assert_eq!(sys_mmap_pid(target, start, 4096, 0b011), 0);
+    let token = with_task(target, |task| task.get_user_token()).unwrap();
+    let buffers = translated_byte_buffer(token, start as *const u8, 4096);
+    assert!(buffers.iter().all(|bytes| bytes.iter().all(|byte| *byte == 0)));
+    assert_eq!(sys_mmap_pid(usize::MAX, start, 4096, 0b011), -EINVAL);
+    // 非root任务不能操作别的任务的地址空间
+    assert_eq!(set_current_uid(1), 0);
+    assert_eq!(sys_mmap_pid(target, start + 4096, 4096, 0b011), -1);
+    assert_eq!(sys_munmap_pid(target, start, 4096), -1);
+    with_task(current_task_id(), |task| task.uid = 0);
+    assert_eq!(sys_munmap_pid(target, start, 4096), 1);
+    assert!(with_task(target, |task| task.memory_set.translate(VirtAddr::from(start).floor()))
+        .unwrap()
+        .map_or(true, |pte| !pte.is_valid()));
+    // 目标任务的trap上下文不能被解除映射，否则它缓存的trap上下文页帧号会指向已释放的页帧
+    assert_eq!(sys_munmap_pid(target, TRAP_CONTEXT, PAGE_SIZE), -1);
+    assert!(with_task(target, |task| task.memory_set.translate(VirtAddr::from(TRAP_CONTEXT).floor()))
+        .unwrap()
+        .map_or(false, |pte| pte.is_valid()));
+    info!("mmap_pid_test passed!");
+}
+
+#[allow(unused)]
+// 测试发送信号：目标任务的待处理位被置上，重复发送不叠加；非法的信号编号和不存在的任务被拒绝
+pub fn kill_test() {
+    let target = 1;
+    let saved = with_task(target, |task| task.pending_signals).unwrap();
+    with_task(target, |task| task.pending_signals = 0);
+    assert_eq!(sys_kill(target, 10), 0);
+    assert_eq!(sys_kill(target, 10), 0);
+    assert_eq!(sys_kill(target, 63), 0);
+    assert_eq!(with_task(target, |task| task.pending_signals), Some(1 << 10 | 1 << 63));
+    assert_eq!(sys_kill(target, SIGNAL_NUM), -EINVAL);
+    assert_eq!(sys_kill(usize::MAX, 10), -EINVAL);
+    assert_eq!(with_task(target, |task| task.pending_signals), Some(1 << 10 | 1 << 63));
+    // 发给别的任务不影响自己
+    assert_eq!(with_task(current_task_id(), |task| task.pending_signals), Some(0));
+    with_task(target, |task| task.pending_signals = saved);
+    info!("kill_test passed!");
+}
+
+#[allow(unused)]
+// 测试root任务可以把自己降为普通用户，降级之后再想通过setuid回到root会失败
+pub fn setuid_test() {
+    assert_eq!(sys_getuid(), 0);
+    assert_eq!(sys_setuid(0), 0);
+    assert_eq!(sys_setuid(1000), 0);
+    assert_eq!(sys_getuid(), 1000);
+    assert_eq!(sys_setuid(0), -1);
+    assert_eq!(sys_setuid(1001), -1);
+    assert_eq!(sys_getuid(), 1000);
+    // 普通用户也不能做只有root才能做的事
+    assert_eq!(sys_mmap_dma(4096, 0b011), -1);
+    with_task(current_task_id(), |task| task.uid = 0);
+    info!("setuid_test passed!");
+}
+
+#[allow(unused)]
+// 测试任务列表：处于不同状态的任务都如实列出，名字与应用名一致；缓冲区不够时只写len项
+pub fn sys_ps_test() {
+    use crate::loader::get_app_name;
+    // spawn 出的任务也会列出
+    let num_task = list_tasks().len();
+    assert!(num_task >= 4);
+    let states = [TaskStatus::Running, TaskStatus::Ready, TaskStatus::Blocked, TaskStatus::Exited];
+    let saved: Vec<TaskStatus> = (0..states.len())
+        .map(|pid| with_task(pid, |task| task.task_status).unwrap())
+        .collect();
+    for (pid, status) in states.iter().enumerate() {
+        with_task(pid, |task| task.task_status = *status);
+    }
+    let start: usize = 0x6000_0000;
+    assert_eq!(mmap_in_current_memory_set(start, 4096, 0b011), 0);
+    let buf = start as *mut TaskListEntry;
+    let capacity = 4096 / core::mem::size_of::<TaskListEntry>();
+    assert_eq!(sys_ps(buf, capacity), num_task.min(capacity) as isize);
+    let token = current_user_token();
+    for (pid, status) in states.iter().enumerate() {
+        let entry = translated_read_ptr(token, buf.wrapping_add(pid) as *const TaskListEntry);
+        assert_eq!(entry.pid, pid);
+        assert_eq!(entry.status, *status);
+        let name = get_app_name(pid).as_bytes();
+        let name_len = name.len().min(entry.name.len());
+        assert_eq!(&entry.name[..name_len], &name[..name_len]);
+        assert!(entry.name[name_len..].iter().all(|byte| *byte == 0));
+    }
+    // 只要两项时不会写到第三项
+    let third = translated_read_ptr(token, buf.wrapping_add(2) as *const TaskListEntry);
+    with_task(2, |task| task.task_status = TaskStatus::Ready);
+    assert_eq!(sys_ps(buf, 2), 2);
+    assert_eq!(translated_read_ptr(token, buf.wrapping_add(2) as *const TaskListEntry).status, third.status);
+    assert_eq!(munmap_in_current_memory_set(start, 4096), 1);
+    for (pid, status) in saved.into_iter().enumerate() {
+        with_task(pid, |task| task.task_status = status);
+    }
+    info!("sys_ps_test passed!");
+}
+
+#[allow(unused)]
+// 测试系统调用可以把结果写进被 MADV_DONTNEED 丢弃过的页面：内核先替任务重新提交页面，而不是写到无效页表项里的页帧号上
+pub fn dontneed_buffer_test() {
+    let start: usize = 0x6000_0000;
+    assert_eq!(mmap_in_current_memory_set(start, 2 * 4096, 0b011), 0);
+    translated_assign_ptr(current_user_token(), (start + 4096) as *mut [u8; 3], *b"ok\0");
+    assert_eq!(sys_madvise(start, 2 * 4096, MADV_DONTNEED), 2);
+    let ts = (start + 8) as *mut TimeVal;
+    let before = get_time_us();
+    assert_eq!(sys_get_time(ts, 0), 0);
+    let [sec, usec] = translated_read_ptr(current_user_token(), ts as *const [usize; 2]);
+    let us = sec * 1_000_000 + usec;
+    assert!(us >= before && us <= get_time_us());
+    // 读一个丢弃过的页面得到全零
+    assert_eq!(translated_str(current_user_token(), (start + 4096) as *const u8), "");
+    assert_eq!(munmap_in_current_memory_set(start, 2 * 4096), 2);
+    info!("dontneed_buffer_test passed!");
+}
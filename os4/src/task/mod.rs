@@ -10,23 +10,40 @@
 //! might not be what you expect.
 
 mod context;
+mod profile;
 mod switch;
 #[allow(clippy::module_inception)]
 mod task;
 
-use crate::config::MAX_SYSCALL_NUM;
-use crate::syscall::process::TaskInfo;
-use crate::timer::get_time_us;
-use crate::loader::{get_app_data, get_num_app};
+use crate::config::{
+    SchedPolicy, DEFAULT_PRIORITY, EXIT_SUMMARY, MAX_SYSCALL_NUM, MAX_TASK_NUM, SCHED_POLICY,
+    STARVATION_THRESHOLD_US,
+};
+use crate::mm::{MapPermission, MapType, VirtAddr};
+use crate::syscall::errno::EINVAL;
+use crate::syscall::process::{TaskInfo, VmStat};
+use crate::timer::{get_time_us, idle_until_us};
+use crate::loader::{alloc_pid, get_app_args, get_app_data, get_app_name, get_num_app};
 use crate::sync::UPSafeCell;
 use crate::trap::TrapContext;
 use alloc::vec::Vec;
 use lazy_static::*;
 pub use switch::__switch;
-pub use task::{TaskControlBlock, TaskStatus};
+pub use task::{ExitHook, FileDescriptor, TaskControlBlock, TaskStatus};
+#[cfg(feature = "boot_test")]
+pub use task::{argv_test, exit_hooks_test, exit_summary_test, lazy_kernel_stack_test, trap_cx_mapping_test};
 
 pub use context::TaskContext;
 
+/// Pid of the init task, which adopts the children of exiting tasks.
+///
+/// There is no `waitpid` in this chapter, so the kernel reaps on init's
+/// behalf every time a task exits, whether or not init is still running.
+pub const INIT_PID: usize = 0;
+pub use profile::{profile_tick, ProfileHistogram};
+#[cfg(feature = "boot_test")]
+pub use profile::profile_test;
+
 /// The task manager, where all the tasks are managed.
 ///
 /// Functions implemented on `TaskManager` deals with all task state transitions
@@ -37,8 +54,6 @@ pub use context::TaskContext;
 /// borrowing checks to runtime. You can see examples on how to use `inner` in
 /// existing functions on `TaskManager`.
 pub struct TaskManager {
-    /// total number of tasks
-    num_app: usize,
     /// use inner value to get mutable access
     inner: UPSafeCell<TaskManagerInner>,
 }
@@ -58,16 +73,20 @@ lazy_static! {
         // 获取应用数，从link_app.S里导出的符号
         let num_app = get_num_app();
         info!("num_app = {}", num_app);
+        assert!(num_app <= MAX_TASK_NUM, "too many apps: {}", num_app);
         // 新建一个任务控制块的空向量
         let mut tasks: Vec<TaskControlBlock> = Vec::new();
         // 获取各个app的ELF数据，也是用从link_app.S里导出的符号直接截取
         for i in 0..num_app {
-            // 分别给各个app新建任务，得到各个任务的任务控制块
-            tasks.push(TaskControlBlock::new(get_app_data(i), i));
+            // 分别给各个app新建任务，得到各个任务的任务控制块，命令行参数由loader提供
+            let mut task = TaskControlBlock::new(get_app_data(i), i, Some(&get_app_args(i)));
+            // 进程号只在这里和 spawn 中分配，测试中临时创建的任务控制块不占用，保证进程号与任务编号一致
+            task.pid = alloc_pid();
+            task.app_id = i;
+            tasks.push(task);
         }
         // 构建好任务管理器，返回
         TaskManager {
-            num_app,
             inner: unsafe {
                 UPSafeCell::new(TaskManagerInner {
                     tasks,
@@ -78,6 +97,22 @@ lazy_static! {
     };
 }
 
+/// Pick the task with the smallest stride among `ready`; ties go to the one
+/// that comes first, like the other policies.
+fn min_stride(tasks: &[TaskControlBlock], ready: impl Iterator<Item = usize>) -> Option<usize> {
+    ready.min_by_key(|id| tasks[*id].stride)
+}
+
+/// Pick the task with the nearest deadline among `ready`. Tasks without a
+/// deadline come after all others; ties go to the one that comes first, so
+/// they are still taken in round robin order.
+fn earliest_deadline(tasks: &[TaskControlBlock], ready: impl Iterator<Item = usize>) -> Option<usize> {
+    ready.min_by_key(|id| match tasks[*id].deadline {
+        Some(deadline) => (false, deadline),
+        None => (true, 0),
+    })
+}
+
 impl TaskManager {
     /// Run the first task in task list.
     ///
@@ -87,8 +122,8 @@ impl TaskManager {
         let mut inner = self.inner.exclusive_access();
         let next_task = &mut inner.tasks[0];
         next_task.task_status = TaskStatus::Running;
-        //对初次调度时间则进行设置
-        next_task.task_first_running_time = Some(get_time_us() / 1000);
+        //对初次调度时间则进行设置，和之后的调度一样交给 on_dispatch
+        next_task.on_dispatch(get_time_us());
         let next_task_cx_ptr = &next_task.task_cx as *const TaskContext;
         drop(inner);
         let mut _unused = TaskContext::zero_init();
@@ -99,59 +134,246 @@ impl TaskManager {
         panic!("unreachable in run_first_task!");
     }
 
-    /// Change the status of current `Running` task into `Ready`.
-    fn mark_current_suspended(&self) {
+    /// Run `f` on the control block of the current `Running` task.
+    ///
+    /// This is the single place that knows which task is running. `f` runs
+    /// while `inner` is borrowed, so it must not call back into the task
+    /// manager.
+    fn with_current<R>(&self, f: impl FnOnce(&mut TaskControlBlock) -> R) -> R {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Ready;
+        f(&mut inner.tasks[current])
+    }
+
+    /// Run `f` on the control block of task `pid`, or return `None` if
+    /// there is no such task. Same borrowing rule as `with_current`.
+    fn with_task<R>(&self, pid: usize, f: impl FnOnce(&mut TaskControlBlock) -> R) -> Option<R> {
+        let mut inner = self.inner.exclusive_access();
+        inner.tasks.get_mut(pid).map(f)
+    }
+
+    /// Get the id of the current `Running` task.
+    fn get_current_task_id(&self) -> usize {
+        self.inner.exclusive_access().current_task
+    }
+
+    /// Record `child` as a child of `parent`. Called by `spawn`.
+    fn link_child(&self, parent: usize, child: usize) {
+        let mut inner = self.inner.exclusive_access();
+        inner.tasks[child].parent = Some(parent);
+        inner.tasks[parent].children.push(child);
+    }
+
+    /// Create a `Ready` task running app `app_id` as a child of the current
+    /// task and return its pid, or `None` if there are already
+    /// `MAX_TASK_NUM` tasks.
+    fn spawn(&self, app_id: usize) -> Option<usize> {
+        if self.inner.exclusive_access().tasks.len() >= MAX_TASK_NUM {
+            return None;
+        }
+        let pid = alloc_pid();
+        // 不借用 inner 时建好任务控制块，内核栈按进程号放置
+        let mut task = TaskControlBlock::new(get_app_data(app_id), pid, Some(&get_app_args(app_id)));
+        task.pid = pid;
+        task.app_id = app_id;
+        let mut inner = self.inner.exclusive_access();
+        // 进程号与任务编号一致
+        assert_eq!(inner.tasks.len(), pid);
+        inner.tasks.push(task);
+        drop(inner);
+        self.link_child(self.get_current_task_id(), pid);
+        Some(pid)
     }
 
-    /// Change the status of current `Running` task into `Exited`.
-    fn mark_current_exited(&self) {
+    /// Hand the children of the current task over to the init task, so that
+    /// they are reaped by `exit_current` once they have exited. The init task
+    /// itself has nobody to hand its children to and keeps them.
+    fn reparent_current_children(&self) {
         let mut inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        inner.tasks[current].task_status = TaskStatus::Exited;
+        if current == INIT_PID {
+            return;
+        }
+        let children = core::mem::take(&mut inner.tasks[current].children);
+        for child in children.iter() {
+            inner.tasks[*child].parent = Some(INIT_PID);
+        }
+        inner.tasks[INIT_PID].children.extend(children);
+    }
+
+    /// Remove the exited children of task `pid` from its children list and
+    /// return their pids.
+    fn reap_children(&self, pid: usize) -> Vec<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let children = core::mem::take(&mut inner.tasks[pid].children);
+        let (reaped, alive): (Vec<usize>, Vec<usize>) = children
+            .into_iter()
+            .partition(|child| inner.tasks[*child].task_status == TaskStatus::Exited);
+        inner.tasks[pid].children = alive;
+        for child in reaped.iter() {
+            inner.tasks[*child].parent = None;
+        }
+        reaped
+    }
+
+    /// Set the CPU affinity mask of task `pid`. An empty mask or an unknown
+    /// pid is rejected with `-EINVAL`.
+    fn set_affinity(&self, pid: usize, mask: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        if mask == 0 || pid >= inner.tasks.len() {
+            return -EINVAL;
+        }
+        inner.tasks[pid].cpu_affinity = mask;
+        0
+    }
+
+    /// Get the CPU affinity mask of task `pid`.
+    fn get_affinity(&self, pid: usize) -> Option<usize> {
+        let inner = self.inner.exclusive_access();
+        inner.tasks.get(pid).map(|task| task.cpu_affinity)
+    }
+
+    /// Raise the effective priority of task `pid` to at least `to`, leaving
+    /// its base priority alone. Unknown pids are rejected with `-EINVAL`.
+    fn boost_priority(&self, pid: usize, to: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) => {
+                task.effective_priority = task.effective_priority.max(to);
+                0
+            }
+            None => -EINVAL,
+        }
+    }
+
+    /// Drop the effective priority of task `pid` back to its base priority.
+    fn restore_priority(&self, pid: usize) -> isize {
+        let mut inner = self.inner.exclusive_access();
+        match inner.tasks.get_mut(pid) {
+            Some(task) => {
+                task.effective_priority = task.priority;
+                0
+            }
+            None => -EINVAL,
+        }
+    }
+
+    // 设置当前任务的基础优先级
+    fn set_current_priority(&self, priority: usize) {
+        self.with_current(|task| task.set_priority(priority));
+    }
+
+    // 记录当前任务的退出码，按配置记录资源汇总。要在退出清理步骤回收地址空间之前调用
+    fn record_current_exit(&self, exit_code: i32) {
+        let pid = self.get_current_task_id();
+        self.with_current(|task| {
+            task.exit_code = Some(exit_code);
+            if EXIT_SUMMARY {
+                info!("{}", task.exit_summary(pid, get_app_name(task.app_id)));
+            }
+        });
+    }
+
+    // 执行当前任务注册的退出清理步骤
+    fn run_current_exit_hooks(&self) {
+        self.with_current(|task| task.run_exit_hooks());
+    }
+
+    /// Change the status of current `Running` task into `status`.
+    fn mark_current(&self, status: TaskStatus) {
+        self.with_current(|task| match status {
+            TaskStatus::Blocked => task.block(get_time_us()),
+            _ => task.task_status = status,
+        });
     }
 
     /// Find next task to run and return task id.
     ///
-    /// In this case, we only return the first `Ready` task in task list.
+    /// With round robin, we return the first `Ready` task in task list. With
+    /// EDF, we return the `Ready` task with the nearest deadline, and tasks
+    /// without a deadline are taken in round robin order after all others.
     fn find_next_task(&self) -> Option<usize> {
         let inner = self.inner.exclusive_access();
         let current = inner.current_task;
-        (current + 1..current + self.num_app + 1)
-            .map(|id| id % self.num_app)
-            .find(|id| inner.tasks[*id].task_status == TaskStatus::Ready)
+        let num_task = inner.tasks.len();
+        let mut ready = (current + 1..current + num_task + 1)
+            .map(|id| id % num_task)
+            .filter(|id| inner.tasks[*id].task_status == TaskStatus::Ready);
+        match SCHED_POLICY {
+            SchedPolicy::RoundRobin => ready.next(),
+            SchedPolicy::Stride => min_stride(&inner.tasks, ready),
+            SchedPolicy::Edf => earliest_deadline(&inner.tasks, ready),
+        }
     }
 
     /// Get the current 'Running' task's token.
     fn get_current_token(&self) -> usize {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_user_token()
+        self.with_current(|task| task.get_user_token())
     }
 
     #[allow(clippy::mut_from_ref)]
     /// Get the current 'Running' task's trap contexts.
     fn get_current_trap_cx(&self) -> &mut TrapContext {
-        let inner = self.inner.exclusive_access();
-        inner.tasks[inner.current_task].get_trap_cx()
+        self.with_current(|task| task.get_trap_cx())
+    }
+
+    /// Wake every sleeping task whose wakeup time has passed, and return the
+    /// nearest wakeup time among the tasks that are still asleep.
+    fn wake_sleepers(&self, now_us: usize) -> Option<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let mut nearest: Option<usize> = None;
+        for task in inner.tasks.iter_mut() {
+            if task.task_status != TaskStatus::Blocked {
+                continue;
+            }
+            match task.wakeup_us {
+                Some(wakeup) if wakeup <= now_us => {
+                    task.wake(now_us);
+                }
+                Some(wakeup) => nearest = Some(nearest.map_or(wakeup, |n| n.min(wakeup))),
+                None => {}
+            }
+        }
+        nearest
+    }
+
+    /// Make task `next` the current `Running` task and return the context
+    /// pointers to pass to `__switch`.
+    ///
+    /// This is all of a switch except the `__switch` itself, so tests can
+    /// drive the scheduler's bookkeeping before the first task starts.
+    fn dispatch(&self, next: usize) -> (*mut TaskContext, *const TaskContext) {
+        let mut inner = self.inner.exclusive_access();
+        let current = inner.current_task;
+        // 让出CPU之前的这段内核时间记到当前任务上
+        inner.tasks[current].account_kernel_time(get_time_us());
+        inner.tasks[next].task_status = TaskStatus::Running;
+        //如果没有被调度过，则对初次调度时间则进行设置，同时累加调度延迟
+        inner.tasks[next].on_dispatch(get_time_us());
+        inner.current_task = next;
+        let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
+        let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
+        (current_task_cx_ptr, next_task_cx_ptr)
     }
 
     /// Switch current `Running` task to the task we have found,
     /// or there is no `Ready` task and we can exit with all applications completed
+    ///
+    /// Sleeping tasks whose wakeup time has passed are woken first. If every
+    /// remaining task is asleep, the CPU idles until the nearest wakeup time.
     fn run_next_task(&self) {
-        if let Some(next) = self.find_next_task() {
-            let mut inner = self.inner.exclusive_access();
-            let current = inner.current_task;
-            inner.tasks[next].task_status = TaskStatus::Running;
-            //如果没有被调度过，则对初次调度时间则进行设置
-            if inner.tasks[next].task_first_running_time == None {
-                inner.tasks[next].task_first_running_time = Some(get_time_us() / 1000);
+        let mut next_wakeup = self.wake_sleepers(get_time_us());
+        let mut found = self.find_next_task();
+        while found.is_none() {
+            match next_wakeup {
+                Some(wakeup) => idle_until_us(wakeup),
+                None => break,
             }
-            inner.current_task = next;
-            let current_task_cx_ptr = &mut inner.tasks[current].task_cx as *mut TaskContext;
-            let next_task_cx_ptr = &inner.tasks[next].task_cx as *const TaskContext;
-            drop(inner);
+            next_wakeup = self.wake_sleepers(get_time_us());
+            found = self.find_next_task();
+        }
+        if let Some(next) = found {
+            let (current_task_cx_ptr, next_task_cx_ptr) = self.dispatch(next);
             // before this, we should drop local variables that must be dropped manually
             unsafe {
                 __switch(current_task_cx_ptr, next_task_cx_ptr);
@@ -164,35 +386,224 @@ impl TaskManager {
     
     // LAB1: Try to implement your function to update or get task info!
 
-    // 增加对应ID的系统调用计数
+    // 增加对应ID的系统调用计数，超出统计范围的ID不计数（交给分发函数报错）
     fn update_syscall_times(&self, syscall_id: usize) {
-        let mut inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        inner.tasks[current].task_syscall_times[syscall_id] += 1;
+        if syscall_id >= MAX_SYSCALL_NUM {
+            return;
+        }
+        self.with_current(|task| task.task_syscall_times[syscall_id] += 1);
     }
 
     // 获取当前应用任务信息
     fn get_task_info(&self) -> TaskInfo {
-        let inner = self.inner.exclusive_access();
-        let current = inner.current_task;
-        let time = get_time_us() / 1000 - inner.tasks[current].task_first_running_time.unwrap();
-        TaskInfo {
-            status: inner.tasks[current].task_status,
-            syscall_times: inner.tasks[current].task_syscall_times,
-            time,
+        self.with_current(|task| TaskInfo {
+            status: task.task_status,
+            syscall_times: task.task_syscall_times,
+            time: get_time_us() / 1000 - task.task_first_running_time.unwrap(),
+            total_sched_latency_us: task.total_sched_latency_us,
+            total_blocked_us: task.total_blocked_us,
+        })
+    }
+
+    // 记录当前任务主动让出CPU的时刻，再次被调度时据此计算调度延迟
+    fn mark_current_yielded(&self) {
+        self.with_current(|task| {
+            let now = get_time_us();
+            task.yield_time_us = Some(now);
+            task.ready_since_us = now;
+        });
+    }
+
+    /// Warn about every `Ready` task that has waited longer than
+    /// `STARVATION_THRESHOLD_US` since it became ready, once per wait.
+    /// Returns the pids warned about this time.
+    fn check_starvation(&self, now_us: usize) -> Vec<usize> {
+        let mut inner = self.inner.exclusive_access();
+        let mut starved = Vec::new();
+        for (pid, task) in inner.tasks.iter_mut().enumerate() {
+            if task.task_status != TaskStatus::Ready || task.starvation_warned {
+                continue;
+            }
+            let waited = now_us.saturating_sub(task.ready_since_us);
+            if waited > STARVATION_THRESHOLD_US {
+                warn!(
+                    "[kernel] possible starvation: pid {} ready for {}us without being scheduled",
+                    pid, waited
+                );
+                task.starvation_warned = true;
+                starved.push(pid);
+            }
         }
+        starved
+    }
+
+    // 列出所有任务的编号、状态和名字，只短暂持有 inner，系统调用中调用也不会重复借用
+    fn list_tasks(&self) -> Vec<(usize, TaskStatus, &'static str)> {
+        let inner = self.inner.exclusive_access();
+        inner
+            .tasks
+            .iter()
+            .enumerate()
+            .map(|(id, task)| (id, task.task_status, get_app_name(task.app_id)))
+            .collect()
+    }
+
+    // 统计当前任务各权限组合的用户页面数，只统计用户可访问的 Framed 逻辑段
+    fn get_vmstat(&self) -> VmStat {
+        let mut vmstat = VmStat::default();
+        self.with_current(|task| {
+            for area in task.memory_set.iter_areas() {
+                let perm = area.map_perm();
+                if area.map_type() != MapType::Framed || !perm.contains(MapPermission::U) {
+                    continue;
+                }
+                let perm = perm - MapPermission::U;
+                let count = if perm == MapPermission::R {
+                    &mut vmstat.r
+                } else if perm == MapPermission::R | MapPermission::W {
+                    &mut vmstat.rw
+                } else if perm == MapPermission::R | MapPermission::X {
+                    &mut vmstat.rx
+                } else if perm == MapPermission::R | MapPermission::W | MapPermission::X {
+                    &mut vmstat.rwx
+                } else {
+                    &mut vmstat.other
+                };
+                *count += area.frame_count();
+            }
+        });
+        vmstat
     }
 
     fn mmap_in_current_memory_set(&self, start: usize, len: usize, port: usize) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.mmap(start, len, port)
+        self.with_current(|task| task.memory_set.mmap(start, len, port))
+    }
+
+    fn mmap_with_data_in_current_memory_set(
+        &self,
+        start: usize,
+        len: usize,
+        port: usize,
+        data: &[u8],
+    ) -> isize {
+        self.with_current(|task| task.memory_set.mmap_with_data(start, len, port, data))
     }
 
     fn munmap_in_current_memory_set(&self, start: usize, len: usize) -> isize {
-        let mut inner = self.inner.exclusive_access();
-        let current_task = inner.current_task;
-        inner.tasks[current_task].memory_set.munmap(start, len)
+        self.with_current(|task| task.memory_set.munmap(start, len))
+    }
+
+    // 在当前任务的文件描述符表中查找描述符
+    fn get_current_fd(&self, fd: usize) -> Option<FileDescriptor> {
+        self.with_current(|task| task.fd_table.get(fd).copied().flatten())
+    }
+
+    // 取出当前任务要结束它的待处理信号
+    fn take_current_fatal_signal(&self) -> Option<usize> {
+        self.with_current(|task| task.take_fatal_signal())
+    }
+
+    // 当前任务是否处于单步调试模式
+    fn get_current_single_step(&self) -> bool {
+        self.with_current(|task| task.single_step)
+    }
+
+    fn set_current_single_step(&self, enable: bool) {
+        self.with_current(|task| task.single_step = enable);
+    }
+
+    // 记录当前任务单步时插入的断点
+    fn set_current_step_breakpoints(&self, breakpoints: Vec<(usize, [u8; 2])>) {
+        self.with_current(|task| task.step_breakpoints = breakpoints);
+    }
+
+    // 取出当前任务单步时插入的断点
+    fn take_current_step_breakpoints(&self) -> Vec<(usize, [u8; 2])> {
+        self.with_current(|task| core::mem::take(&mut task.step_breakpoints))
+    }
+
+    // 给当前任务的采样直方图记录一次pc
+    fn record_profile_sample(&self, pc: usize) {
+        self.with_current(|task| task.profile.record(pc));
+    }
+
+    // 当前任务采样最多的至多n个桶
+    fn get_current_profile(&self, n: usize) -> Vec<(usize, usize)> {
+        self.with_current(|task| task.profile.hottest(n))
+    }
+
+    // 获取当前任务的地址空间大小限制
+    fn get_current_rlimit_as(&self) -> usize {
+        self.with_current(|task| task.memory_set.rlimit_as())
+    }
+
+    // 设置当前任务的地址空间大小限制
+    fn set_current_rlimit_as(&self, limit: usize) {
+        self.with_current(|task| task.memory_set.set_rlimit_as(limit));
+    }
+
+    // 设置当前任务的截止时间
+    fn set_current_deadline(&self, deadline: Option<usize>) {
+        self.with_current(|task| task.deadline = deadline);
+    }
+
+    // 获取当前任务的用户号
+    fn get_current_uid(&self) -> usize {
+        self.with_current(|task| task.uid)
+    }
+
+    // 设置当前任务的用户号，只有root可以设置
+    fn set_current_uid(&self, uid: usize) -> isize {
+        self.with_current(|task| {
+            if task.uid != 0 {
+                return -1;
+            }
+            task.uid = uid;
+            0
+        })
+    }
+
+    fn mprotect_in_current_memory_set(&self, start: usize, len: usize, port: usize) -> isize {
+        self.with_current(|task| task.memory_set.set_permissions(start, len, port))
+    }
+
+    fn is_guard_page_in_current_memory_set(&self, va: usize) -> bool {
+        self.with_current(|task| task.memory_set.is_guard_page(va.into()))
+    }
+
+    fn is_guard_fault_in_current_memory_set(&self, va: usize) -> bool {
+        self.with_current(|task| task.memory_set.is_guard_fault(va.into()))
+    }
+
+    fn current_memory_set_contains(&self, va: usize) -> bool {
+        self.with_current(|task| task.memory_set.contains_addr(va.into()))
+    }
+
+    fn is_executable_page_in_current_memory_set(&self, va: usize) -> bool {
+        self.with_current(|task| task.memory_set.is_executable_page(va.into()))
+    }
+
+    fn mmap_identical_in_current_memory_set(&self, len: usize, port: usize) -> isize {
+        self.with_current(|task| task.memory_set.mmap_identical(len, port))
+    }
+
+    fn commit_page_in_current_memory_set(&self, va: usize, write: bool) -> bool {
+        self.with_current(|task| {
+            task.memory_set
+                .commit_page_on_fault(VirtAddr::from(va).floor(), write)
+        })
+    }
+
+    fn populate_in_current_memory_set(&self, start: usize, len: usize) -> isize {
+        self.with_current(|task| task.memory_set.populate(start, len))
+    }
+
+    fn grow_brk_in_current_memory_set(&self, delta: isize) -> Option<usize> {
+        self.with_current(|task| task.memory_set.grow_brk(delta))
+    }
+
+    fn discard_in_current_memory_set(&self, start: usize, len: usize) -> isize {
+        self.with_current(|task| task.memory_set.discard(start, len))
     }
 }
 
@@ -207,28 +618,139 @@ fn run_next_task() {
     TASK_MANAGER.run_next_task();
 }
 
-/// Change the status of current `Running` task into `Ready`.
-fn mark_current_suspended() {
-    TASK_MANAGER.mark_current_suspended();
+/// Change the status of current `Running` task into `status`.
+fn mark_current(status: TaskStatus) {
+    TASK_MANAGER.mark_current(status);
 }
 
-/// Change the status of current `Running` task into `Exited`.
-fn mark_current_exited() {
-    TASK_MANAGER.mark_current_exited();
+/// Change the status of current `Running` task into `next_status`, then
+/// switch to the next `Ready` task.
+///
+/// Yield, block and sleep funnel through here. Exit marks the task itself in
+/// `exit_current`, because orphans can only be reaped after that.
+pub fn schedule(next_status: TaskStatus) {
+    mark_current(next_status);
+    run_next_task();
 }
 
 /// Suspend the current 'Running' task and run the next task in task list.
 pub fn suspend_current_and_run_next() {
-    mark_current_suspended();
-    run_next_task();
+    TASK_MANAGER.mark_current_yielded();
+    schedule(TaskStatus::Ready);
+}
+
+/// Block the current 'Running' task and run the next task in task list.
+///
+/// The task stays off the CPU until someone calls [`wake_task`] on it.
+pub fn block_current_and_run_next() {
+    schedule(TaskStatus::Blocked);
+}
+
+/// Put the current 'Running' task to sleep until `wakeup_us` and run the
+/// next task. A sleeping task is `Blocked`; the scheduler wakes it once the
+/// wakeup time has passed.
+pub fn sleep_current_and_run_next(wakeup_us: usize) {
+    TASK_MANAGER.with_current(|task| task.wakeup_us = Some(wakeup_us));
+    schedule(TaskStatus::Blocked);
+}
+
+/// Move task `pid` from `Blocked` back to `Ready`, adding the time it spent
+/// blocked to its `total_blocked_us`. Returns false if it was not blocked.
+pub fn wake_task(pid: usize) -> bool {
+    with_task(pid, |task| task.wake(get_time_us())).unwrap_or(false)
 }
 
 /// Exit the current 'Running' task and run the next task in task list.
-pub fn exit_current_and_run_next() {
-    mark_current_exited();
+pub fn exit_current_and_run_next(exit_code: i32) {
+    exit_current(exit_code);
     run_next_task();
 }
 
+/// Everything `exit_current_and_run_next` does before switching away.
+///
+/// `exit_code` is recorded in the control block and, if `EXIT_SUMMARY` is
+/// set, logged with the task's resource summary. The task's exit hooks run
+/// next, in registration order, and its children are handed over to the
+/// init task. Once the task is `Exited`, the exited children of init,
+/// possibly including this task, are reaped.
+fn exit_current(exit_code: i32) {
+    TASK_MANAGER.record_current_exit(exit_code);
+    TASK_MANAGER.run_current_exit_hooks();
+    TASK_MANAGER.reparent_current_children();
+    mark_current(TaskStatus::Exited);
+    TASK_MANAGER.reap_children(INIT_PID);
+}
+
+/// Run `f` on the control block of the current `Running` task.
+///
+/// `f` must not call other functions of this module.
+pub fn with_current_task<R>(f: impl FnOnce(&mut TaskControlBlock) -> R) -> R {
+    TASK_MANAGER.with_current(f)
+}
+
+/// Run `f` on the control block of task `pid`, or return `None` if there is
+/// no such task.
+///
+/// `f` must not call other functions of this module.
+pub fn with_task<R>(pid: usize, f: impl FnOnce(&mut TaskControlBlock) -> R) -> Option<R> {
+    TASK_MANAGER.with_task(pid, f)
+}
+
+/// Get the id of the current `Running` task.
+pub fn current_task_id() -> usize {
+    TASK_MANAGER.get_current_task_id()
+}
+
+/// Get the pid of the current `Running` task.
+pub fn current_pid() -> usize {
+    TASK_MANAGER.with_current(|task| task.pid)
+}
+
+/// Create a task running app `app_id` as a child of the current task and
+/// return its pid, or `None` if the task table is full.
+pub fn spawn(app_id: usize) -> Option<usize> {
+    TASK_MANAGER.spawn(app_id)
+}
+
+/// Set the CPU affinity mask of task `pid`.
+pub fn set_affinity(pid: usize, mask: usize) -> isize {
+    TASK_MANAGER.set_affinity(pid, mask)
+}
+
+/// Get the CPU affinity mask of task `pid`, or `None` if there is no such task.
+pub fn get_affinity(pid: usize) -> Option<usize> {
+    TASK_MANAGER.get_affinity(pid)
+}
+
+/// Temporarily raise the effective priority of task `pid` to at least `to`,
+/// e.g. while it holds a lock that a higher-priority task is waiting for.
+pub fn boost_priority(pid: usize, to: usize) -> isize {
+    TASK_MANAGER.boost_priority(pid, to)
+}
+
+/// Undo `boost_priority`: the effective priority of task `pid` goes back to
+/// its base priority.
+pub fn restore_priority(pid: usize) -> isize {
+    TASK_MANAGER.restore_priority(pid)
+}
+
+/// Scheduler watchdog, called on every timer tick: log a warning for each
+/// ready task that has been waiting too long. Diagnostic only; it does not
+/// change which task runs next.
+pub fn check_starvation() -> Vec<usize> {
+    TASK_MANAGER.check_starvation(get_time_us())
+}
+
+/// Set the base priority of the current task.
+pub fn set_current_priority(priority: usize) {
+    TASK_MANAGER.set_current_priority(priority);
+}
+
+/// Get the pid of the current task's parent, or 0 if it has none.
+pub fn current_parent_id() -> usize {
+    TASK_MANAGER.with_current(|task| task.parent.unwrap_or(0))
+}
+
 /// Get the current 'Running' task's token.
 pub fn current_user_token() -> usize {
     TASK_MANAGER.get_current_token()
@@ -244,6 +766,94 @@ pub fn get_task_info() -> TaskInfo {
     TASK_MANAGER.get_task_info()
 }
 
+// 查找当前任务打开的文件描述符，未打开时返回None
+pub fn current_fd(fd: usize) -> Option<FileDescriptor> {
+    TASK_MANAGER.get_current_fd(fd)
+}
+
+// 从用户态进入trap时调用，累计当前任务的用户时间
+pub fn account_current_user_time() {
+    TASK_MANAGER.with_current(|task| task.account_user_time(get_time_us()));
+}
+
+// 返回用户态之前调用，累计当前任务的内核时间
+pub fn account_current_kernel_time() {
+    TASK_MANAGER.with_current(|task| task.account_kernel_time(get_time_us()));
+}
+
+// 当前任务的缺页次数加一
+pub fn count_current_page_fault() {
+    TASK_MANAGER.with_current(|task| task.page_faults += 1);
+}
+
+// 取出当前任务待处理的SIGKILL或没被屏蔽的SIGTERM，trap返回用户态之前据此结束任务
+pub fn take_current_fatal_signal() -> Option<usize> {
+    TASK_MANAGER.take_current_fatal_signal()
+}
+
+// 当前任务是否处于单步调试模式
+pub fn current_single_step() -> bool {
+    TASK_MANAGER.get_current_single_step()
+}
+
+// 设置当前任务的单步调试标志，断点的插入与恢复由trap模块负责
+pub fn set_current_single_step(enable: bool) {
+    TASK_MANAGER.set_current_single_step(enable);
+}
+
+pub fn set_current_step_breakpoints(breakpoints: Vec<(usize, [u8; 2])>) {
+    TASK_MANAGER.set_current_step_breakpoints(breakpoints);
+}
+
+pub fn take_current_step_breakpoints() -> Vec<(usize, [u8; 2])> {
+    TASK_MANAGER.take_current_step_breakpoints()
+}
+
+// 记录当前任务被时钟中断打断时的pc
+pub fn record_profile_sample(pc: usize) {
+    TASK_MANAGER.record_profile_sample(pc);
+}
+
+// 获取当前任务采样最多的至多n个桶，每项为（桶起始地址，采样数）
+pub fn current_profile(n: usize) -> Vec<(usize, usize)> {
+    TASK_MANAGER.get_current_profile(n)
+}
+
+// 获取当前任务的地址空间大小限制（字节）
+pub fn current_rlimit_as() -> usize {
+    TASK_MANAGER.get_current_rlimit_as()
+}
+
+// 设置当前任务的地址空间大小限制（字节）
+pub fn set_current_rlimit_as(limit: usize) {
+    TASK_MANAGER.set_current_rlimit_as(limit);
+}
+
+// 设置当前任务在EDF调度下的绝对截止时间（us）
+pub fn set_current_deadline(deadline: Option<usize>) {
+    TASK_MANAGER.set_current_deadline(deadline);
+}
+
+// 获取当前任务的用户号
+pub fn current_uid() -> usize {
+    TASK_MANAGER.get_current_uid()
+}
+
+// 设置当前任务的用户号，非root调用返回-1
+pub fn set_current_uid(uid: usize) -> isize {
+    TASK_MANAGER.set_current_uid(uid)
+}
+
+// 列出所有任务的编号、状态和名字
+pub fn list_tasks() -> Vec<(usize, TaskStatus, &'static str)> {
+    TASK_MANAGER.list_tasks()
+}
+
+// 统计当前任务地址空间的页面权限分布
+pub fn get_vmstat() -> VmStat {
+    TASK_MANAGER.get_vmstat()
+}
+
 /// Get the current 'Running' task's trap contexts.
 pub fn current_trap_cx() -> &'static mut TrapContext {
     TASK_MANAGER.get_current_trap_cx()
@@ -253,6 +863,754 @@ pub fn mmap_in_current_memory_set(start: usize, len: usize, port:usize) -> isize
     TASK_MANAGER.mmap_in_current_memory_set(start, len, port)
 }
 
+pub fn mmap_with_data_in_current_memory_set(
+    start: usize,
+    len: usize,
+    port: usize,
+    data: &[u8],
+) -> isize {
+    TASK_MANAGER.mmap_with_data_in_current_memory_set(start, len, port, data)
+}
+
+// 为当前任务映射一段恒等映射的DMA缓冲区，返回其地址
+pub fn mmap_identical_in_current_memory_set(len: usize, port: usize) -> isize {
+    TASK_MANAGER.mmap_identical_in_current_memory_set(len, port)
+}
+
 pub fn munmap_in_current_memory_set(start: usize, len: usize) -> isize {
     TASK_MANAGER.munmap_in_current_memory_set(start, len)
-}
\ No newline at end of file
+}
+
+// 在任务pid的地址空间中分配内存，没有这个任务时返回 -EINVAL
+pub fn mmap_in_memory_set_of(pid: usize, start: usize, len: usize, port: usize) -> isize {
+    let ret = with_task(pid, |task| task.memory_set.mmap(start, len, port)).unwrap_or(-EINVAL);
+    // 无效的页表项也可能被缓存在TLB里，新建映射之后同样要刷新
+    flush_tlb_of_other_space();
+    ret
+}
+
+// 在任务pid的地址空间中解除映射，没有这个任务时返回 -EINVAL
+pub fn munmap_in_memory_set_of(pid: usize, start: usize, len: usize) -> isize {
+    let ret = with_task(pid, |task| task.memory_set.munmap(start, len)).unwrap_or(-EINVAL);
+    flush_tlb_of_other_space();
+    ret
+}
+
+// 改动了别的任务的页表之后刷新TLB
+// 所有地址空间共用ASID 0，刷新全部TLB项；目标任务当前不在运行时，切换回它时写satp也会刷新
+fn flush_tlb_of_other_space() {
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+}
+
+pub fn mprotect_in_current_memory_set(start: usize, len: usize, port: usize) -> isize {
+    TASK_MANAGER.mprotect_in_current_memory_set(start, len, port)
+}
+
+// 判断出错地址是否为当前任务主动设置的保护页
+pub fn is_guard_page_in_current_memory_set(va: usize) -> bool {
+    TASK_MANAGER.is_guard_page_in_current_memory_set(va)
+}
+
+// 判断出错地址是否落在当前任务用户栈下方的保护页上（栈溢出）
+pub fn is_guard_fault_in_current_memory_set(va: usize) -> bool {
+    TASK_MANAGER.is_guard_fault_in_current_memory_set(va)
+}
+
+// 判断出错地址是否落在当前任务的某个逻辑段内
+pub fn current_memory_set_contains(va: usize) -> bool {
+    TASK_MANAGER.current_memory_set_contains(va)
+}
+
+// 判断出错地址是否为当前任务的可执行页面
+pub fn is_executable_page_in_current_memory_set(va: usize) -> bool {
+    TASK_MANAGER.is_executable_page_in_current_memory_set(va)
+}
+
+// 处理当前任务延迟分配页面的缺页，读时映射零页，写时分配页帧；出错地址不是这种页面时返回false
+pub fn commit_page_in_current_memory_set(va: usize, write: bool) -> bool {
+    TASK_MANAGER.commit_page_in_current_memory_set(va, write)
+}
+
+// 立即为当前任务一段范围内延迟分配的页面分配页帧
+pub fn populate_in_current_memory_set(start: usize, len: usize) -> isize {
+    TASK_MANAGER.populate_in_current_memory_set(start, len)
+}
+
+// 移动当前任务的program break，返回原来的break；收缩时页表项被清除，需要刷新TLB
+pub fn grow_brk_in_current_memory_set(delta: isize) -> Option<usize> {
+    let ret = TASK_MANAGER.grow_brk_in_current_memory_set(delta);
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    ret
+}
+
+// 丢弃当前地址空间中一段页面的内容，页表项被清除，需要刷新TLB
+pub fn discard_in_current_memory_set(start: usize, len: usize) -> isize {
+    let ret = TASK_MANAGER.discard_in_current_memory_set(start, len);
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    ret
+}
+#[allow(unused)]
+/// Check that `with_current_task` sees the task that is actually running,
+/// before and after a yield.
+///
+/// Must run before the first task starts. The yield goes through
+/// `TaskManager::dispatch`, the same bookkeeping `run_next_task` does before
+/// its `__switch`.
+pub fn current_task_test() {
+    let before = current_task_id();
+    assert_eq!(with_current_task(|task| task.get_user_token()), current_user_token());
+    let saved_before = with_task(before, |task| (task.task_status, task.kernel_time_us)).unwrap();
+    // 模拟一次 yield
+    mark_current(TaskStatus::Ready);
+    let next = TASK_MANAGER.find_next_task().unwrap();
+    let saved = with_task(next, |task| {
+        (
+            task.task_status,
+            task.task_first_running_time,
+            task.stride,
+            task.starvation_warned,
+        )
+    })
+    .unwrap();
+    TASK_MANAGER.dispatch(next);
+    assert_eq!(current_task_id(), next);
+    let expected = with_task(next, |task| task.get_user_token()).unwrap();
+    assert_eq!(with_current_task(|task| task.get_user_token()), expected);
+    assert_eq!(with_current_task(|task| task.task_status), TaskStatus::Running);
+    assert_eq!(current_user_token(), expected);
+    // 恢复
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    let task = &mut inner.tasks[next];
+    task.task_status = saved.0;
+    task.task_first_running_time = saved.1;
+    task.stride = saved.2;
+    task.starvation_warned = saved.3;
+    inner.tasks[before].task_status = saved_before.0;
+    inner.tasks[before].kernel_time_us = saved_before.1;
+    inner.current_task = before;
+    drop(inner);
+    assert_eq!(current_task_id(), before);
+    info!("current_task_test passed!");
+}
+
+#[allow(unused)]
+/// Check that `schedule` leaves the current task in the status it was asked
+/// for and picks the right task to run next, for a yield, a block and an
+/// exit.
+///
+/// Must run before the first task starts. Like `current_task_test`, the
+/// switch is simulated by doing the bookkeeping of `run_next_task` without
+/// the `__switch`.
+pub fn schedule_test() {
+    let before = current_task_id();
+    let other = (before + 1) % TASK_MANAGER.inner.exclusive_access().tasks.len();
+    let saved: Vec<(TaskStatus, Option<usize>)> = TASK_MANAGER
+        .inner
+        .exclusive_access()
+        .tasks
+        .iter()
+        .map(|task| (task.task_status, task.blocked_since_us))
+        .collect();
+    for status in [TaskStatus::Ready, TaskStatus::Blocked, TaskStatus::Exited] {
+        for other_ready in [false, true] {
+            // 除了当前任务和 other 之外都已退出，调度结果与调度策略无关
+            for task in TASK_MANAGER.inner.exclusive_access().tasks.iter_mut() {
+                task.task_status = TaskStatus::Exited;
+            }
+            if other_ready {
+                with_task(other, |task| task.task_status = TaskStatus::Ready);
+            }
+            with_task(before, |task| {
+                task.task_status = TaskStatus::Running;
+                task.blocked_since_us = None;
+            });
+            // schedule 的前半部分
+            mark_current(status);
+            assert_eq!(with_task(before, |task| task.task_status), Some(status));
+            assert_eq!(
+                with_task(before, |task| task.blocked_since_us.is_some()),
+                Some(status == TaskStatus::Blocked)
+            );
+            let next = TASK_MANAGER.find_next_task();
+            match (status, other_ready) {
+                // 让出CPU时没有别的就绪任务，就接着运行自己
+                (TaskStatus::Ready, false) => assert_eq!(next, Some(before)),
+                // 两个任务都就绪时选哪个取决于调度策略
+                (TaskStatus::Ready, true) => assert!(next == Some(before) || next == Some(other)),
+                // 阻塞或退出的任务不会被选中
+                (_, true) => assert_eq!(next, Some(other)),
+                (_, false) => assert_eq!(next, None),
+            }
+            // run_next_task 的记账，不做 __switch
+            if let Some(next) = next {
+                with_task(next, |task| task.task_status = TaskStatus::Running);
+                TASK_MANAGER.inner.exclusive_access().current_task = next;
+                assert_eq!(current_task_id(), next);
+                assert_eq!(with_current_task(|task| task.task_status), TaskStatus::Running);
+            }
+            TASK_MANAGER.inner.exclusive_access().current_task = before;
+        }
+    }
+    // 恢复
+    for (task, (status, blocked_since_us)) in
+        TASK_MANAGER.inner.exclusive_access().tasks.iter_mut().zip(saved)
+    {
+        task.task_status = status;
+        task.blocked_since_us = blocked_since_us;
+    }
+    info!("schedule_test passed!");
+}
+
+#[allow(unused)]
+/// Check that a task created by `spawn` is linked to the task that spawned
+/// it, and sees that task's pid through `sys_getppid` once it runs.
+///
+/// Must run before the first task starts. Switches go through
+/// `TaskManager::dispatch` like `sched_latency_test`. The spawned task is
+/// retired at the end, so it never runs for real.
+pub fn parent_link_test() {
+    use crate::syscall::process::{sys_getpid, sys_getppid};
+    let before = current_task_id();
+    let parent = 1;
+    let saved = with_task(parent, |task| {
+        (
+            task.task_status,
+            task.task_first_running_time,
+            task.kernel_time_us,
+            task.stride,
+        )
+    })
+    .unwrap();
+    let kernel_time_before = with_task(before, |task| task.kernel_time_us).unwrap();
+    TASK_MANAGER.dispatch(parent);
+    // 启动时加载的任务没有父任务
+    assert_eq!(sys_getppid(), 0);
+    let app_id = with_current_task(|task| task.app_id);
+    let child = spawn(app_id).unwrap();
+    assert_eq!(child + 1, TASK_MANAGER.inner.exclusive_access().tasks.len());
+    with_task(child, |task| {
+        assert_eq!(task.pid, child);
+        assert_eq!(task.app_id, app_id);
+        assert_eq!(task.parent, Some(parent));
+        assert_eq!(task.task_status, TaskStatus::Ready);
+    });
+    assert!(with_task(parent, |task| task.children.contains(&child)).unwrap());
+    // 切到子任务，它看到的父任务就是 parent
+    mark_current(TaskStatus::Ready);
+    TASK_MANAGER.dispatch(child);
+    assert_eq!(sys_getpid(), child as isize);
+    assert_eq!(sys_getppid(), parent as isize);
+    // 恢复：子任务退出并从 parent 的子任务中去掉
+    mark_current(TaskStatus::Exited);
+    with_task(child, |task| {
+        task.run_exit_hooks();
+        task.parent = None;
+    });
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    inner.tasks[before].kernel_time_us = kernel_time_before;
+    let task = &mut inner.tasks[parent];
+    task.children.retain(|pid| *pid != child);
+    task.task_status = saved.0;
+    task.task_first_running_time = saved.1;
+    task.kernel_time_us = saved.2;
+    task.stride = saved.3;
+    inner.current_task = before;
+    drop(inner);
+    info!("parent_link_test passed!");
+}
+
+#[allow(unused)]
+/// Check that every task gets its own pid, that pids match the task ids,
+/// and that `sys_getpid` keeps returning the same pid as two tasks take
+/// turns on the CPU.
+///
+/// Must run before the first task starts. Switches are simulated by
+/// changing `current_task` without the `__switch`.
+pub fn pid_test() {
+    use crate::syscall::process::sys_getpid;
+    let before = current_task_id();
+    let pids: Vec<usize> = TASK_MANAGER
+        .inner
+        .exclusive_access()
+        .tasks
+        .iter()
+        .map(|task| task.pid)
+        .collect();
+    for (id, pid) in pids.iter().enumerate() {
+        assert_eq!(*pid, id);
+    }
+    let (a, b) = (0, 1);
+    let mut seen = [0isize; 2];
+    for round in 0..3 {
+        for (i, id) in [a, b].iter().enumerate() {
+            TASK_MANAGER.inner.exclusive_access().current_task = *id;
+            let pid = sys_getpid();
+            if round == 0 {
+                seen[i] = pid;
+            }
+            assert_eq!(pid, seen[i]);
+        }
+    }
+    assert_ne!(seen[0], seen[1]);
+    TASK_MANAGER.inner.exclusive_access().current_task = before;
+    info!("pid_test passed!");
+}
+
+#[allow(unused)]
+/// Check that the children of an exiting task are adopted by init, and
+/// reaped by the exit path once they exit, without anyone calling
+/// `reap_children`.
+///
+/// Must run before the first task starts. Both tasks are spawned for the
+/// test and exit through `exit_current`; switches go through
+/// `TaskManager::dispatch`.
+pub fn reparent_test() {
+    use crate::syscall::process::sys_getppid;
+    let before = current_task_id();
+    let spawner = 1;
+    let saved = with_task(spawner, |task| {
+        (
+            task.task_status,
+            task.task_first_running_time,
+            task.kernel_time_us,
+            task.stride,
+        )
+    })
+    .unwrap();
+    let kernel_time_before = with_task(before, |task| task.kernel_time_us).unwrap();
+    let app_id = with_task(spawner, |task| task.app_id).unwrap();
+    TASK_MANAGER.dispatch(spawner);
+    let parent = spawn(app_id).unwrap();
+    mark_current(TaskStatus::Ready);
+    TASK_MANAGER.dispatch(parent);
+    let child = spawn(app_id).unwrap();
+    // 父任务先退出，子任务交给 init；还在运行的子任务不会被回收
+    exit_current(0);
+    assert!(with_task(parent, |task| task.children.is_empty()).unwrap());
+    assert_eq!(with_task(child, |task| task.parent), Some(Some(INIT_PID)));
+    assert!(with_task(INIT_PID, |task| task.children.contains(&child)).unwrap());
+    TASK_MANAGER.dispatch(child);
+    assert_eq!(sys_getppid(), INIT_PID as isize);
+    exit_current(0);
+    assert!(!with_task(INIT_PID, |task| task.children.contains(&child)).unwrap());
+    assert_eq!(with_task(child, |task| task.parent), Some(None));
+    // 恢复：父任务不是 init 的孩子，留在 spawner 的子任务中，手动去掉
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    inner.tasks[before].kernel_time_us = kernel_time_before;
+    inner.tasks[parent].parent = None;
+    let task = &mut inner.tasks[spawner];
+    task.children.retain(|pid| *pid != parent);
+    task.task_status = saved.0;
+    task.task_first_running_time = saved.1;
+    task.kernel_time_us = saved.2;
+    task.stride = saved.3;
+    inner.current_task = before;
+    drop(inner);
+    info!("reparent_test passed!");
+}
+
+#[allow(unused)]
+/// Check that an affinity mask can be set and read back, and that an
+/// all-zero mask is rejected without changing the stored one.
+pub fn affinity_test() {
+    let pid = 1;
+    assert_eq!(get_affinity(pid), Some(usize::MAX));
+    assert_eq!(set_affinity(pid, 0b10), 0);
+    assert_eq!(get_affinity(pid), Some(0b10));
+    assert_eq!(set_affinity(pid, 0), -EINVAL);
+    assert_eq!(get_affinity(pid), Some(0b10));
+    assert_eq!(set_affinity(usize::MAX, 0b1), -EINVAL);
+    assert_eq!(get_affinity(usize::MAX), None);
+    // 恢复
+    assert_eq!(set_affinity(pid, usize::MAX), 0);
+    info!("affinity_test passed!");
+}
+
+#[allow(unused)]
+/// Check that a task sleeping for 50ms is not woken early, that the
+/// scheduler idles until its wakeup time when nothing else can run, and that
+/// `get_time_us` shows at least 50ms have passed once it is `Ready` again.
+///
+/// Must run before the first task starts and after the timer interrupt is
+/// enabled, so that `wfi` returns when the timer fires.
+pub fn sleep_test() {
+    const SLEEP_US: usize = 50_000;
+    let other = 1;
+    let saved = with_task(other, |task| (task.task_status, task.ready_since_us)).unwrap();
+    let total_before = with_task(other, |task| task.total_blocked_us).unwrap();
+    let start = get_time_us();
+    with_task(other, |task| {
+        task.block(start);
+        task.wakeup_us = Some(start + SLEEP_US);
+    });
+    assert_eq!(TASK_MANAGER.wake_sleepers(start), Some(start + SLEEP_US));
+    assert_eq!(with_task(other, |task| task.task_status), Some(TaskStatus::Blocked));
+    idle_until_us(start + SLEEP_US);
+    assert_eq!(TASK_MANAGER.wake_sleepers(get_time_us()), None);
+    let elapsed = get_time_us() - start;
+    assert!(elapsed >= SLEEP_US, "slept for {}us", elapsed);
+    with_task(other, |task| {
+        assert_eq!(task.task_status, TaskStatus::Ready);
+        assert!(task.wakeup_us.is_none());
+    });
+    // 恢复
+    with_task(other, |task| {
+        task.task_status = saved.0;
+        task.ready_since_us = saved.1;
+        task.total_blocked_us = total_before;
+    });
+    info!("sleep_test passed!");
+}
+
+#[allow(unused)]
+/// Check that two tasks yielding to each other both accumulate scheduling
+/// latency, and that the amount matches the time spent waiting.
+///
+/// Must run before the first task starts. Switches go through
+/// `TaskManager::dispatch`, the same bookkeeping `run_next_task` does
+/// before its `__switch`.
+pub fn sched_latency_test() {
+    const WAIT_US: usize = 100;
+    const ROUNDS: usize = 4;
+    let before = current_task_id();
+    let (a, b) = (1, 2);
+    let saved: Vec<_> = [a, b]
+        .iter()
+        .map(|id| {
+            let inner = TASK_MANAGER.inner.exclusive_access();
+            let task = &inner.tasks[*id];
+            (
+                task.task_status,
+                task.task_first_running_time,
+                task.total_sched_latency_us,
+                task.kernel_time_us,
+                task.stride,
+            )
+        })
+        .collect();
+    let kernel_time_before = with_task(before, |task| task.kernel_time_us).unwrap();
+    // a 先运行，之后两个任务轮流 yield，每次都走 suspend_current_and_run_next 的记账，只是不做 __switch
+    TASK_MANAGER.dispatch(a);
+    for _ in 0..ROUNDS {
+        for to in [b, a] {
+            TASK_MANAGER.mark_current_yielded();
+            mark_current(TaskStatus::Ready);
+            let start = get_time_us();
+            while get_time_us() < start + WAIT_US {}
+            TASK_MANAGER.dispatch(to);
+            assert_eq!(current_task_id(), to);
+        }
+    }
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    // 每次让出后要等两次 WAIT_US 才被调度回来；b 最后一次让出后没有再被调度
+    let latency_a = inner.tasks[a].total_sched_latency_us - saved[0].2;
+    let latency_b = inner.tasks[b].total_sched_latency_us - saved[1].2;
+    assert!(latency_a >= ROUNDS * 2 * WAIT_US, "latency of a: {}us", latency_a);
+    assert!(latency_b >= (ROUNDS - 1) * 2 * WAIT_US, "latency of b: {}us", latency_b);
+    assert!(latency_a < 1_000_000 && latency_b < 1_000_000);
+    // 恢复
+    inner.tasks[before].kernel_time_us = kernel_time_before;
+    for (id, (status, first_running_time, latency, kernel_time_us, stride)) in
+        [a, b].iter().zip(saved)
+    {
+        let task = &mut inner.tasks[*id];
+        task.task_status = status;
+        task.task_first_running_time = first_running_time;
+        task.total_sched_latency_us = latency;
+        task.kernel_time_us = kernel_time_us;
+        task.stride = stride;
+        task.yield_time_us = None;
+    }
+    inner.current_task = before;
+    drop(inner);
+    info!("sched_latency_test passed!");
+}
+
+#[allow(unused)]
+// 测试初次调度时间只在第一次被调度时记录，之后再被调度不覆盖，task_info 报告的运行时间单调增长
+pub fn first_running_time_test() {
+    let other = 1;
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    let task = &mut inner.tasks[other];
+    let saved = (task.task_first_running_time, task.stride);
+    task.task_first_running_time = None;
+    let now = get_time_us();
+    for later in [now, now + 5_000, now + 10_000] {
+        task.on_dispatch(later);
+        assert_eq!(task.task_first_running_time, Some(now / 1000));
+    }
+    task.task_first_running_time = saved.0;
+    task.stride = saved.1;
+    drop(inner);
+    // 当前任务让出两次（这里用忙等代替），报告的运行时间不倒退且不为零
+    let mut last = get_task_info().time;
+    for _ in 0..2 {
+        let start = get_time_us();
+        while get_time_us() < start + 2_000 {}
+        let time = get_task_info().time;
+        assert!(time > last && time > 0, "time went from {}ms to {}ms", last, time);
+        last = time;
+    }
+    info!("first_running_time_test passed!");
+}
+
+#[allow(unused)]
+// 测试阻塞时间统计：阻塞一段已知的时间后唤醒，累计的阻塞时间在误差范围内，不是阻塞状态时不能唤醒
+pub fn blocked_time_test() {
+    const BLOCK_US: usize = 2_000;
+    let other = 1;
+    let saved = with_task(other, |task| (task.task_status, task.ready_since_us)).unwrap();
+    let total_before = with_task(other, |task| task.total_blocked_us).unwrap();
+    with_task(other, |task| task.block(get_time_us()));
+    let start = get_time_us();
+    while get_time_us() < start + BLOCK_US {}
+    assert!(wake_task(other));
+    assert!(!wake_task(other));
+    let blocked = with_task(other, |task| {
+        assert_eq!(task.task_status, TaskStatus::Ready);
+        assert!(task.blocked_since_us.is_none());
+        task.total_blocked_us - total_before
+    })
+    .unwrap();
+    assert!(blocked >= BLOCK_US && blocked < BLOCK_US + 1_000, "blocked for {}us", blocked);
+    // 恢复
+    with_task(other, |task| {
+        task.task_status = saved.0;
+        task.ready_since_us = saved.1;
+        task.total_blocked_us = total_before;
+    });
+    info!("blocked_time_test passed!");
+}
+
+#[allow(unused)]
+/// Check that a boosted task is scheduled by its effective priority, and
+/// that restoring brings it back to its base priority.
+///
+/// Runs the stride selection over two tasks by hand, so it does not depend
+/// on `SCHED_POLICY`.
+pub fn priority_boost_test() {
+    const PICKS: usize = 50;
+    let (a, b) = (1, 2);
+    let saved: Vec<(usize, usize)> = [a, b]
+        .iter()
+        .map(|id| {
+            let inner = TASK_MANAGER.inner.exclusive_access();
+            (inner.tasks[*id].stride, inner.tasks[*id].effective_priority)
+        })
+        .collect();
+    let count_picks = || {
+        let mut inner = TASK_MANAGER.inner.exclusive_access();
+        inner.tasks[a].stride = 0;
+        inner.tasks[b].stride = 0;
+        let mut picks_a = 0;
+        for _ in 0..PICKS {
+            let next = min_stride(&inner.tasks, [a, b].iter().copied()).unwrap();
+            let task = &mut inner.tasks[next];
+            let first_running_time = task.task_first_running_time;
+            task.on_dispatch(get_time_us());
+            task.task_first_running_time = first_running_time;
+            if next == a {
+                picks_a += 1;
+            }
+        }
+        picks_a
+    };
+    // 同等优先级时轮流被调度
+    assert_eq!(count_picks(), PICKS / 2);
+    // a 提升到4倍优先级后，被调度的次数大约是 b 的4倍
+    assert_eq!(boost_priority(a, DEFAULT_PRIORITY * 4), 0);
+    assert_eq!(TASK_MANAGER.inner.exclusive_access().tasks[a].priority, DEFAULT_PRIORITY);
+    let picks_a = count_picks();
+    assert!(picks_a >= PICKS * 4 / 5 - 1 && picks_a <= PICKS * 4 / 5 + 1, "{} picks", picks_a);
+    // 比当前有效优先级低的提升不起作用
+    assert_eq!(boost_priority(a, DEFAULT_PRIORITY), 0);
+    assert_eq!(TASK_MANAGER.inner.exclusive_access().tasks[a].effective_priority, DEFAULT_PRIORITY * 4);
+    assert_eq!(restore_priority(a), 0);
+    assert_eq!(count_picks(), PICKS / 2);
+    assert_eq!(boost_priority(usize::MAX, DEFAULT_PRIORITY), -EINVAL);
+    // 恢复
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    for (id, (stride, effective_priority)) in [a, b].iter().zip(saved) {
+        inner.tasks[*id].stride = stride;
+        inner.tasks[*id].effective_priority = effective_priority;
+    }
+    drop(inner);
+    info!("priority_boost_test passed!");
+}
+
+#[allow(unused)]
+/// Check that EDF picks the ready task with the earlier deadline, that
+/// tasks without a deadline wait until those with one have run, and that
+/// `sys_set_deadline` saturates instead of overflowing on huge timeouts.
+///
+/// Runs the EDF selection over two tasks by hand, so it does not depend
+/// on `SCHED_POLICY`.
+pub fn edf_test() {
+    use crate::syscall::process::sys_set_deadline;
+    let (a, b) = (1, 2);
+    let saved: Vec<Option<usize>> = [a, b]
+        .iter()
+        .map(|id| TASK_MANAGER.inner.exclusive_access().tasks[*id].deadline)
+        .collect();
+    let saved_current = with_current_task(|task| task.deadline);
+    let now = get_time_us();
+    let pick = |deadline_a: Option<usize>, deadline_b: Option<usize>| {
+        let mut inner = TASK_MANAGER.inner.exclusive_access();
+        inner.tasks[a].deadline = deadline_a;
+        inner.tasks[b].deadline = deadline_b;
+        earliest_deadline(&inner.tasks, [a, b].iter().copied()).unwrap()
+    };
+    // 截止时间早的先运行，与在就绪队列里的先后无关
+    assert_eq!(pick(Some(now + 20_000), Some(now + 10_000)), b);
+    assert_eq!(pick(Some(now + 10_000), Some(now + 20_000)), a);
+    // 没有截止时间的排在后面，都没有时按顺序轮转
+    assert_eq!(pick(None, Some(now + 20_000)), b);
+    assert_eq!(pick(None, None), a);
+    assert_eq!(pick(Some(now), Some(now)), a);
+    // 很长的相对时间饱和到最晚，而不是溢出成很早的截止时间
+    assert_eq!(sys_set_deadline(usize::MAX), 0);
+    assert_eq!(with_current_task(|task| task.deadline), Some(usize::MAX));
+    // 恢复
+    with_current_task(|task| task.deadline = saved_current);
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    for (id, deadline) in [a, b].iter().zip(saved) {
+        inner.tasks[*id].deadline = deadline;
+    }
+    drop(inner);
+    info!("edf_test passed!");
+}
+
+#[allow(unused)]
+/// Check that the watchdog flags a low-priority task that has been ready
+/// past the threshold, exactly once, and leaves a fresh one alone.
+pub fn starvation_watchdog_test() {
+    let (starved, fresh) = (1, 2);
+    let now = get_time_us() + 2 * STARVATION_THRESHOLD_US;
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    let saved: Vec<(TaskStatus, usize, usize, Option<usize>, usize)> = [starved, fresh]
+        .iter()
+        .map(|id| {
+            let task = &inner.tasks[*id];
+            (
+                task.task_status,
+                task.effective_priority,
+                task.ready_since_us,
+                task.task_first_running_time,
+                task.stride,
+            )
+        })
+        .collect();
+    for id in [starved, fresh] {
+        inner.tasks[id].task_status = TaskStatus::Ready;
+        inner.tasks[id].starvation_warned = false;
+    }
+    inner.tasks[starved].effective_priority = 2;
+    inner.tasks[starved].ready_since_us = now - STARVATION_THRESHOLD_US - 1;
+    inner.tasks[fresh].ready_since_us = now - STARVATION_THRESHOLD_US / 2;
+    drop(inner);
+    let warned = TASK_MANAGER.check_starvation(now);
+    assert!(warned.contains(&starved) && !warned.contains(&fresh));
+    // 同一次等待只警告一次
+    assert!(!TASK_MANAGER.check_starvation(now + 1).contains(&starved));
+    // 被调度之后清除标志，下次等待重新计时
+    let mut inner = TASK_MANAGER.inner.exclusive_access();
+    inner.tasks[starved].on_dispatch(now);
+    assert!(!inner.tasks[starved].starvation_warned);
+    // 恢复
+    for (id, (status, effective_priority, ready_since_us, first_running_time, stride)) in
+        [starved, fresh].iter().zip(saved)
+    {
+        let task = &mut inner.tasks[*id];
+        task.task_status = status;
+        task.effective_priority = effective_priority;
+        task.ready_since_us = ready_since_us;
+        task.task_first_running_time = first_running_time;
+        task.stride = stride;
+        task.starvation_warned = false;
+    }
+    drop(inner);
+    info!("starvation_watchdog_test passed!");
+}
+
+#[allow(unused)]
+// 测试trap返回前的信号检查：一个任务向另一个任务发送SIGKILL后，轮到目标任务返回用户态时取出SIGKILL；
+// 屏蔽对SIGKILL无效，被屏蔽的SIGTERM保持待处理，其余信号不会结束任务
+pub fn fatal_signal_test() {
+    use crate::syscall::process::{sys_kill, SIGKILL, SIGTERM};
+    let before = current_task_id();
+    let target = 1;
+    let saved = with_task(target, |task| (task.pending_signals, task.signal_mask)).unwrap();
+    with_task(target, |task| {
+        task.pending_signals = 0;
+        task.signal_mask = u64::MAX;
+    });
+    assert_eq!(sys_kill(target, SIGKILL), 0);
+    assert_eq!(sys_kill(target, SIGTERM), 0);
+    assert_eq!(sys_kill(target, 10), 0);
+    // 发送者自己没有待处理的致命信号
+    assert_eq!(take_current_fatal_signal(), None);
+    // 模拟目标任务的trap返回
+    TASK_MANAGER.inner.exclusive_access().current_task = target;
+    assert_eq!(take_current_fatal_signal(), Some(SIGKILL));
+    assert_eq!(take_current_fatal_signal(), None);
+    with_current_task(|task| task.signal_mask = 0);
+    assert_eq!(take_current_fatal_signal(), Some(SIGTERM));
+    assert_eq!(take_current_fatal_signal(), None);
+    assert_eq!(with_current_task(|task| task.pending_signals), 1 << 10);
+    // 恢复
+    TASK_MANAGER.inner.exclusive_access().current_task = before;
+    with_task(target, |task| {
+        task.pending_signals = saved.0;
+        task.signal_mask = saved.1;
+    });
+    info!("fatal_signal_test passed!");
+}
+
+#[allow(unused)]
+/// Check `get_vmstat` against the current task's ELF: the executable pages
+/// match the code segments and the writable pages match the data segments
+/// plus the user stack.
+///
+/// Must run before the first task starts, while the task's address space
+/// still holds only what `from_elf` mapped.
+pub fn vmstat_test() {
+    use crate::config::{LAZY_BSS, PAGE_SIZE, USER_STACK_SIZE};
+    use xmas_elf::program::Type;
+    let elf = xmas_elf::ElfFile::new(get_app_data(current_task_id())).unwrap();
+    let mut expected = VmStat::default();
+    for i in 0..elf.header.pt2.ph_count() {
+        let ph = elf.program_header(i).unwrap();
+        if ph.get_type().unwrap() != Type::Load {
+            continue;
+        }
+        let start = VirtAddr::from(ph.virtual_addr() as usize);
+        let end = VirtAddr::from(start.0 + ph.mem_size() as usize).ceil();
+        let flags = ph.flags();
+        // 打开LAZY_BSS时，各段数据之后的页面映射到共享零页，不算作私有页帧
+        let data_end = VirtAddr::from(start.0 + ph.file_size() as usize).ceil();
+        let private_end = if LAZY_BSS { data_end.min(end) } else { end };
+        let pages = private_end.0 - start.floor().0;
+        let count = match (flags.is_write(), flags.is_execute()) {
+            (false, false) => &mut expected.r,
+            (true, false) => &mut expected.rw,
+            (false, true) => &mut expected.rx,
+            (true, true) => &mut expected.rwx,
+        };
+        *count += pages;
+    }
+    assert!(expected.rx > 0);
+    expected.rw += USER_STACK_SIZE / PAGE_SIZE;
+    let vmstat = get_vmstat();
+    assert_eq!(vmstat.rx, expected.rx);
+    assert_eq!(vmstat.rw, expected.rw);
+    assert_eq!(vmstat.r, expected.r);
+    assert_eq!(vmstat.rwx, expected.rwx);
+    assert_eq!(vmstat.other, 0);
+    info!("vmstat_test passed!");
+}
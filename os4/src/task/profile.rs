@@ -0,0 +1,86 @@
+//! Sampling profiler driven by the timer interrupt.
+
+use crate::config::{PROFILE_BUCKET_SIZE, PROFILE_SAMPLE_INTERVAL};
+use crate::mm::align_down;
+use crate::sync::UPSafeCell;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+// 按地址区间分桶的pc采样直方图，键是桶的起始地址，值是落在桶里的采样数
+#[derive(Default)]
+pub struct ProfileHistogram {
+    buckets: BTreeMap<usize, usize>,
+}
+
+impl ProfileHistogram {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    // 记录一次采样
+    pub fn record(&mut self, pc: usize) {
+        *self
+            .buckets
+            .entry(align_down(pc, PROFILE_BUCKET_SIZE))
+            .or_insert(0) += 1;
+    }
+    // 采样总数
+    pub fn total(&self) -> usize {
+        self.buckets.values().sum()
+    }
+    // 采样最多的至多n个桶，按采样数从多到少排列，采样数相同时地址小的在前
+    pub fn hottest(&self, n: usize) -> Vec<(usize, usize)> {
+        let mut buckets: Vec<(usize, usize)> =
+            self.buckets.iter().map(|(start, count)| (*start, *count)).collect();
+        buckets.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        buckets.truncate(n);
+        buckets
+    }
+}
+
+lazy_static! {
+    // 全局的时钟中断计数
+    static ref PROFILE_TICKS: UPSafeCell<usize> = unsafe { UPSafeCell::new(0) };
+}
+
+/// Count one timer tick, and return whether this tick should be sampled.
+pub fn profile_tick() -> bool {
+    let mut ticks = PROFILE_TICKS.exclusive_access();
+    *ticks += 1;
+    *ticks % PROFILE_SAMPLE_INTERVAL == 0
+}
+
+// 读出当前指令的地址
+#[inline(always)]
+fn current_pc() -> usize {
+    let pc: usize;
+    unsafe {
+        core::arch::asm!("auipc {}, 0", out(reg) pc);
+    }
+    pc
+}
+
+#[allow(unused)]
+// 测试一段只在很小地址范围内运行的计算循环会在直方图中占主导
+// 内核里没法让时钟中断打断自己，这里在循环里读pc模拟采样，并混入一些别处的采样作为噪声
+pub fn profile_test() {
+    let mut histogram = ProfileHistogram::new();
+    let mut acc: usize = 1;
+    let mut loop_pc = 0;
+    for i in 0..10000usize {
+        acc = acc.wrapping_mul(6364136223846793005).wrapping_add(i);
+        if i % 16 == 0 {
+            loop_pc = current_pc();
+            histogram.record(loop_pc);
+        }
+        if i % 160 == 0 {
+            histogram.record(profile_tick as usize);
+            histogram.record(ProfileHistogram::hottest as usize);
+        }
+    }
+    assert!(acc != 0);
+    let hottest = histogram.hottest(3);
+    assert_eq!(hottest[0].0, align_down(loop_pc, PROFILE_BUCKET_SIZE));
+    assert!(hottest[0].1 * 2 > histogram.total());
+    info!("profile_test passed!");
+}
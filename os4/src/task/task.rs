@@ -1,13 +1,33 @@
 //! Types related to task management
-use super::TaskContext;
-use crate::config::{kernel_stack_position, TRAP_CONTEXT};
-use crate::mm::{MapPermission, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
+use super::{ProfileHistogram, TaskContext};
+use crate::config::{
+    kernel_stack_position, BIG_STRIDE, DEFAULT_PRIORITY, LAZY_KERNEL_STACK, MAX_TASK_NUM, PAGE_SIZE,
+    TRAP_CONTEXT,
+};
+use crate::mm::{
+    align_down, expect_page_aligned, translated_assign_ptr, translated_byte_buffer, MapPermission, MemorySet,
+    PhysPageNum, VirtAddr, KERNEL_SPACE,
+};
+use crate::syscall::process::{SIGKILL, SIGTERM};
+use crate::timer::get_time_us;
 use crate::trap::{trap_handler, TrapContext};
 use super::MAX_SYSCALL_NUM;
+use alloc::boxed::Box;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+// 任务退出时执行的清理步骤，按注册顺序依次执行
+// 清理步骤运行时任务管理器正被借用，不能在里面再调用 task 模块的函数
+// 任务控制块存放在全局的 TASK_MANAGER 里，lazy_static 要求其中的数据都是 Send 的
+pub type ExitHook = Box<dyn FnOnce(&mut TaskControlBlock) + Send>;
 
 // 任务控制块
 pub struct TaskControlBlock {
-    pub task_status: TaskStatus, // 任务状态，未运行、挂起、运行中、结束
+    pub pid: usize, // 进程号，由任务管理器创建任务时分配，任务存在期间不变
+    pub app_id: usize, // 运行的应用的编号，spawn 出的任务与进程号不同
+    pub task_status: TaskStatus, // 任务状态，未运行、挂起、运行中、结束、阻塞
     pub task_cx: TaskContext, // 任务上下文，12个s寄存器、ra寄存器、sp寄存器
     pub memory_set: MemorySet, // 地址空间，页表、逻辑段实体
     pub trap_cx_ppn: PhysPageNum, // trap上下文的物理页帧号，也就是物理地址中间那部分
@@ -15,36 +35,184 @@ pub struct TaskControlBlock {
     // LAB1: Add whatever you need about the Task.
     pub task_syscall_times: [u32; MAX_SYSCALL_NUM], // 各种系统调用的次数
     pub task_first_running_time: Option<usize>, // 任务第一次被调度的时刻
+    pub uid: usize, // 用户号，0为root
+    pub fd_table: Vec<Option<FileDescriptor>>, // 文件描述符表，下标即描述符，None表示未打开
+    pub deadline: Option<usize>, // EDF调度的绝对截止时间（us）
+    pub single_step: bool, // 是否处于单步调试模式
+    pub step_breakpoints: Vec<(usize, [u8; 2])>, // 单步时临时插入的断点地址和被覆盖的原指令字节，分支指令会插两个
+    pub exit_hooks: Vec<ExitHook>, // 退出时的清理步骤
+    pub profile: ProfileHistogram, // 时钟中断采样得到的pc直方图
+    // 本章的任务控制块按值存放在任务管理器中，用任务编号（即pid）代替指针记录父子关系
+    pub parent: Option<usize>, // 父任务的编号，没有父任务时为None
+    pub children: Vec<usize>, // 子任务的编号
+    pub cpu_affinity: usize, // 允许运行的CPU位掩码，目前只有一个核，只做记录，多核时由调度器遵守
+    pub yield_time_us: Option<usize>, // 最近一次主动让出CPU的时刻，再次被调度时清空
+    pub total_sched_latency_us: usize, // 累计的调度延迟，即从让出CPU到再次被调度的时间之和
+    pub priority: usize, // sys_set_priority 设置的基础优先级
+    // 调度器实际使用的优先级，平时等于基础优先级；将来持有锁时可以被等待它的高优先级任务临时提升（优先级继承）
+    pub effective_priority: usize,
+    pub stride: usize, // stride调度的行程
+    pub ready_since_us: usize, // 最近一次进入就绪状态的时刻，看门狗据此判断是否饿死
+    pub starvation_warned: bool, // 这次等待是否已经报过饿死警告，避免每个时钟中断都报
+    pub blocked_since_us: Option<usize>, // 最近一次进入阻塞状态的时刻，被唤醒时清空
+    pub total_blocked_us: usize, // 累计的阻塞时间，与就绪等待、运行的时间分开统计
+    pub wakeup_us: Option<usize>, // 睡眠任务的唤醒时刻，调度时过了这个时刻就唤醒；睡眠也算阻塞
+    pub pending_signals: u64, // 已收到还没处理的信号，第i位对应信号i
+    pub signal_mask: u64, // 被屏蔽的信号，屏蔽期间保持待处理
+    pub exit_code: Option<i32>, // 退出码，任务结束时设置
+    pub user_time_us: usize, // 累计在用户态运行的时间
+    pub kernel_time_us: usize, // 累计在内核态为它运行的时间（trap处理、调度切换）
+    pub time_stamp_us: usize, // 上一次在用户态和内核态之间切换的时刻，用于累计上面两个时间
+    pub page_faults: usize, // 缺页异常的次数，包括按需分配和写时复制
+}
+
+// 在内核地址空间中映射第kernel_stack_id个内核栈，返回栈顶
+// lazy为true时只有栈顶一页立即分配页帧，其余页面按需分配。
+// 不变量：栈顶一页总是已经映射的。第一次切换到任务时 __switch 会在这个 sp 上恢复上下文，
+// 随后 trap_return 也在这一页上运行，而内核态的缺页不会被处理，这一页缺失会直接出错
+fn map_kernel_stack(kernel_stack_id: usize, lazy: bool) -> usize {
+    let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(kernel_stack_id);
+    let mut kernel_space = KERNEL_SPACE.write();
+    if lazy {
+        kernel_space.insert_lazy_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+        assert!(kernel_space.commit_page(VirtAddr::from(kernel_stack_top - PAGE_SIZE).floor()));
+    } else {
+        kernel_space.insert_framed_area(
+            kernel_stack_bottom.into(),
+            kernel_stack_top.into(),
+            MapPermission::R | MapPermission::W,
+        );
+    }
+    kernel_stack_top
 }
 
 impl TaskControlBlock {
     pub fn get_trap_cx(&self) -> &'static mut TrapContext {
+        debug_assert!(
+            self.trap_cx_mapped(),
+            "trap context page is no longer mapped at the cached {:?}",
+            self.trap_cx_ppn
+        );
         self.trap_cx_ppn.get_mut()
     }
+    // 重新查页表，确认 TRAP_CONTEXT 仍然映射到缓存的 trap_cx_ppn，防止映射被改动后写到别处
+    pub fn trap_cx_mapped(&self) -> bool {
+        match self.memory_set.translate(expect_page_aligned(TRAP_CONTEXT, "TRAP_CONTEXT")) {
+            Some(pte) => pte.is_valid() && pte.ppn() == self.trap_cx_ppn,
+            None => false,
+        }
+    }
     pub fn get_user_token(&self) -> usize {
         self.memory_set.token()
     }
-    // 新建一个任务，得到这个任务的任务控制块
-    pub fn new(elf_data: &[u8], app_id: usize) -> Self {
+    // 任务被调度运行时调用：记录第一次被调度的时刻，并累加从上次让出CPU到现在的调度延迟
+    pub fn on_dispatch(&mut self, now_us: usize) {
+        if self.task_first_running_time.is_none() {
+            self.task_first_running_time = Some(now_us / 1000);
+        }
+        if let Some(yield_time) = self.yield_time_us.take() {
+            self.total_sched_latency_us += now_us - yield_time;
+        }
+        self.stride += BIG_STRIDE / self.effective_priority;
+        self.starvation_warned = false;
+        self.time_stamp_us = now_us;
+    }
+    // 从用户态进入内核时调用，把上次返回用户态以来的时间记为用户时间
+    pub fn account_user_time(&mut self, now_us: usize) {
+        self.user_time_us += now_us - self.time_stamp_us;
+        self.time_stamp_us = now_us;
+    }
+    // 返回用户态或让出CPU时调用，把进入内核以来的时间记为内核时间
+    pub fn account_kernel_time(&mut self, now_us: usize) {
+        self.kernel_time_us += now_us - self.time_stamp_us;
+        self.time_stamp_us = now_us;
+    }
+    // 任务退出时记录的一行资源汇总
+    pub fn exit_summary(&self, pid: usize, name: &str) -> String {
+        format!(
+            "[kernel] exit summary: pid={} name={} code={} peak_pages={} syscalls={} user_us={} kernel_us={} page_faults={}",
+            pid,
+            name,
+            self.exit_code.unwrap_or(0),
+            self.memory_set.peak_mapped_bytes() / PAGE_SIZE,
+            self.task_syscall_times.iter().map(|times| *times as usize).sum::<usize>(),
+            self.user_time_us,
+            self.kernel_time_us,
+            self.page_faults
+        )
+    }
+    // 进入阻塞状态，记下时刻
+    pub fn block(&mut self, now_us: usize) {
+        self.task_status = TaskStatus::Blocked;
+        self.blocked_since_us = Some(now_us);
+    }
+    // 从阻塞状态唤醒为就绪状态并累计阻塞时间，不处于阻塞状态时返回false
+    pub fn wake(&mut self, now_us: usize) -> bool {
+        if self.task_status != TaskStatus::Blocked {
+            return false;
+        }
+        self.task_status = TaskStatus::Ready;
+        self.wakeup_us = None;
+        if let Some(since) = self.blocked_since_us.take() {
+            self.total_blocked_us += now_us - since;
+        }
+        self.ready_since_us = now_us;
+        true
+    }
+    // 取出一个要结束任务的待处理信号：SIGKILL不能被屏蔽；还没有信号处理函数，
+    // 没被屏蔽的SIGTERM按默认动作结束任务。其余信号保持待处理
+    pub fn take_fatal_signal(&mut self) -> Option<usize> {
+        let deliverable = self.pending_signals & !(self.signal_mask & !(1 << SIGKILL));
+        let signum = [SIGKILL, SIGTERM]
+            .iter()
+            .copied()
+            .find(|signum| deliverable & (1 << *signum) != 0)?;
+        self.pending_signals &= !(1 << signum);
+        Some(signum)
+    }
+    // 设置基础优先级，没有被提升时有效优先级跟着改变，被提升时不低于新的基础优先级
+    pub fn set_priority(&mut self, priority: usize) {
+        if self.effective_priority == self.priority {
+            self.effective_priority = priority;
+        } else {
+            self.effective_priority = self.effective_priority.max(priority);
+        }
+        self.priority = priority;
+    }
+    // 注册一个退出清理步骤，排在已注册的步骤之后
+    pub fn add_exit_hook(&mut self, hook: ExitHook) {
+        self.exit_hooks.push(hook);
+    }
+    // 按注册顺序执行并清空所有退出清理步骤
+    pub fn run_exit_hooks(&mut self) {
+        let hooks = core::mem::take(&mut self.exit_hooks);
+        for hook in hooks {
+            hook(self);
+        }
+    }
+    // 新建一个任务，得到这个任务的任务控制块，args为传给应用的命令行参数
+    // kernel_stack_id决定内核栈的位置：任务传入自己的进程号，测试传入不小于 MAX_TASK_NUM 的位置
+    pub fn new(elf_data: &[u8], kernel_stack_id: usize, args: Option<&[&str]>) -> Self {
         // memory_set with elf program headers/trampoline/trap context/user stack
         // 先要给任务新建地址空间，使用ELF文件，按ELF期望进行布局，得到地址空间、栈指针初始位置、程序入口点
         let (memory_set, user_sp, entry_point) = MemorySet::from_elf(elf_data);
         // 得到trap上下文的物理页号
         let trap_cx_ppn = memory_set
-            .translate(VirtAddr::from(TRAP_CONTEXT).into())
+            .translate(expect_page_aligned(TRAP_CONTEXT, "TRAP_CONTEXT"))
             .unwrap()
             .ppn();
         // 任务状态设置为未运行
         let task_status = TaskStatus::Ready;
         // 在内核空间给应用分配个内核栈，kernel_stack_position来自config的规定
-        let (kernel_stack_bottom, kernel_stack_top) = kernel_stack_position(app_id);
-        KERNEL_SPACE.lock().insert_framed_area(
-            kernel_stack_bottom.into(),
-            kernel_stack_top.into(),
-            MapPermission::R | MapPermission::W,
-        );
+        let kernel_stack_top = map_kernel_stack(kernel_stack_id, LAZY_KERNEL_STACK);
         // 创建任务控制块
-        let task_control_block = Self {
+        let mut task_control_block = Self {
+            pid: kernel_stack_id, // 任务管理器会重新分配，这里先用内核栈位置占位
+            app_id: 0, // 由任务管理器设置
             task_status,
             task_cx: TaskContext::goto_trap_return(kernel_stack_top), // 在初始启动中，任务挂起上下文设置成ra为trap_return的地址，s是零，sp是内核栈
             // 这样看起来就好像是即将从trap中恢复时被挂起了
@@ -54,26 +222,223 @@ impl TaskControlBlock {
             base_size: user_sp,
             task_syscall_times: [0; MAX_SYSCALL_NUM],
             task_first_running_time: None,
+            uid: 0,
+            fd_table: vec![
+                Some(FileDescriptor::Stdin),
+                Some(FileDescriptor::Stdout),
+                Some(FileDescriptor::Stderr),
+            ],
+            deadline: None,
+            single_step: false,
+            step_breakpoints: Vec::new(),
+            exit_hooks: Vec::new(),
+            profile: ProfileHistogram::new(),
+            parent: None,
+            children: Vec::new(),
+            cpu_affinity: usize::MAX,
+            yield_time_us: None,
+            total_sched_latency_us: 0,
+            priority: DEFAULT_PRIORITY,
+            effective_priority: DEFAULT_PRIORITY,
+            stride: 0,
+            ready_since_us: get_time_us(),
+            starvation_warned: false,
+            blocked_since_us: None,
+            total_blocked_us: 0,
+            wakeup_us: None,
+            pending_signals: 0,
+            signal_mask: 0,
+            exit_code: None,
+            user_time_us: 0,
+            kernel_time_us: 0,
+            time_stamp_us: 0,
+            page_faults: 0,
         };
+        // 默认的退出清理：先关闭文件描述符，再回收用户地址空间的数据页帧
+        // 页表节点只能在数据页帧之后随地址空间一起释放；内核栈退出时还在用，保留给后续复用
+        task_control_block.add_exit_hook(Box::new(|task| task.fd_table.clear()));
+        task_control_block.add_exit_hook(Box::new(|task| task.memory_set.clear()));
         // 设置trap上下文，让挂起的程序恢复时从trap恢复到用户态执行
         let trap_cx = task_control_block.get_trap_cx();
         *trap_cx = TrapContext::app_init_context(
             entry_point, // 程序入口点
             user_sp, // 用户栈初始指针
             // 下面这仨是固定的
-            KERNEL_SPACE.lock().token(), // 内核空间页表token
+            KERNEL_SPACE.read().token(), // 内核空间页表token
             kernel_stack_top, // 内核栈顶
             trap_handler as usize, // trap处理函数
         );
+        // 把命令行参数压到用户栈顶，并按调用约定用a0、a1传给应用
+        if let Some(args) = args {
+            let (user_sp, argv_base) =
+                push_args(task_control_block.get_user_token(), user_sp, args);
+            trap_cx.set_sp(user_sp);
+            trap_cx.x[10] = args.len();
+            trap_cx.x[11] = argv_base;
+        }
         task_control_block
     }
 }
 
+// 在用户栈上布置argv：高处是以0结尾的参数字符串，下面是指向它们的指针数组（以空指针结尾）
+// 返回新的栈指针（16字节对齐）和指针数组的起始地址
+fn push_args(token: usize, mut user_sp: usize, args: &[&str]) -> (usize, usize) {
+    let mut arg_ptrs = Vec::new();
+    for arg in args.iter() {
+        user_sp -= arg.len() + 1;
+        let mut bytes = arg.bytes().chain(core::iter::once(0u8));
+        for buffer in translated_byte_buffer(token, user_sp as *const u8, arg.len() + 1) {
+            for byte in buffer.iter_mut() {
+                *byte = bytes.next().unwrap();
+            }
+        }
+        arg_ptrs.push(user_sp);
+    }
+    user_sp = align_down(user_sp, core::mem::size_of::<usize>());
+    user_sp -= (args.len() + 1) * core::mem::size_of::<usize>();
+    // 按RISC-V调用约定，栈指针需16字节对齐
+    user_sp = align_down(user_sp, 16);
+    let argv_base = user_sp;
+    for (i, arg_ptr) in arg_ptrs.iter().enumerate() {
+        translated_assign_ptr(
+            token,
+            (argv_base + i * core::mem::size_of::<usize>()) as *mut usize,
+            *arg_ptr,
+        );
+    }
+    translated_assign_ptr(
+        token,
+        (argv_base + args.len() * core::mem::size_of::<usize>()) as *mut usize,
+        0,
+    );
+    (user_sp, argv_base)
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+/// kinds of file descriptor a task may hold
+pub enum FileDescriptor {
+    Stdin,
+    Stdout,
+    Stderr,
+}
+
 #[derive(Copy, Clone, PartialEq, Debug)]
 /// task status: UnInit, Ready, Running, Exited
+// 经 sys_task_info 和 sys_ps 写给用户，布局要与用户库中的定义一致
+#[repr(usize)]
 pub enum TaskStatus {
     UnInit,
     Ready,
     Running,
     Exited,
+    Blocked,
+}
+
+#[allow(unused)]
+// 测试trap上下文映射的校验能发现缓存的页帧号与页表不一致
+pub fn trap_cx_mapping_test() {
+    use crate::loader::get_app_data;
+    // 用 MAX_TASK_NUM 之后的内核栈位置，不会与任务以及运行时注册的应用冲突
+    let mut tcb = TaskControlBlock::new(get_app_data(0), MAX_TASK_NUM, None);
+    assert!(tcb.trap_cx_mapped());
+    tcb.trap_cx_ppn = PhysPageNum(tcb.trap_cx_ppn.0 + 1);
+    assert!(!tcb.trap_cx_mapped());
+    info!("trap_cx_mapping_test passed!");
+}
+
+#[allow(unused)]
+// 测试退出清理步骤按注册顺序执行，且默认步骤回收了用户页帧
+pub fn exit_hooks_test() {
+    use crate::loader::get_app_data;
+    use crate::mm::frame_remain_num;
+    // 用 MAX_TASK_NUM 之后的内核栈位置，避免与任务以及 trap_cx_mapping_test 冲突
+    let mut tcb = TaskControlBlock::new(get_app_data(0), MAX_TASK_NUM + 1, None);
+    tcb.add_exit_hook(Box::new(|task| {
+        assert_eq!(task.uid, 0);
+        task.uid = 1;
+    }));
+    tcb.add_exit_hook(Box::new(|task| {
+        assert_eq!(task.uid, 1);
+        task.uid = 2;
+    }));
+    let remain_before = frame_remain_num();
+    tcb.run_exit_hooks();
+    assert_eq!(tcb.uid, 2);
+    assert!(tcb.exit_hooks.is_empty());
+    assert!(tcb.fd_table.is_empty());
+    assert!(frame_remain_num() > remain_before);
+    info!("exit_hooks_test passed!");
+}
+
+#[allow(unused)]
+// 测试延迟分配的内核栈在任何切换之前栈顶一页已经映射，其余页面还没有
+pub fn lazy_kernel_stack_test() {
+    use crate::config::KERNEL_STACK_SIZE;
+    // 用 MAX_TASK_NUM 之后的内核栈位置，避免与任务以及 exit_hooks_test 冲突
+    let top = map_kernel_stack(MAX_TASK_NUM + 2, true);
+    let kernel_space = KERNEL_SPACE.read();
+    let top_page = kernel_space
+        .translate(VirtAddr::from(top - PAGE_SIZE).floor())
+        .unwrap();
+    assert!(top_page.is_valid() && top_page.writable());
+    if KERNEL_STACK_SIZE > PAGE_SIZE {
+        let bottom = kernel_space.translate(VirtAddr::from(top - KERNEL_STACK_SIZE).floor());
+        assert!(bottom.map_or(true, |pte| !pte.is_valid()));
+    }
+    info!("lazy_kernel_stack_test passed!");
+}
+
+#[allow(unused)]
+// 测试退出汇总包含各项统计字段及其取值
+pub fn exit_summary_test() {
+    use crate::loader::{get_app_data, get_app_name};
+    // 用 MAX_TASK_NUM 之后的内核栈位置，避免与任务以及 lazy_kernel_stack_test 冲突
+    let mut tcb = TaskControlBlock::new(get_app_data(0), MAX_TASK_NUM + 3, None);
+    let peak_pages = tcb.memory_set.peak_mapped_bytes() / PAGE_SIZE;
+    assert!(peak_pages > 0);
+    tcb.on_dispatch(1000);
+    tcb.account_kernel_time(1030);
+    tcb.account_user_time(1100);
+    tcb.account_kernel_time(1105);
+    tcb.task_syscall_times[64] = 3;
+    tcb.task_syscall_times[93] = 1;
+    tcb.page_faults = 2;
+    tcb.exit_code = Some(-3);
+    let summary = tcb.exit_summary(0, get_app_name(0));
+    assert!(summary.contains("pid=0 "));
+    assert!(summary.contains(&format!("name={} ", get_app_name(0))));
+    assert!(summary.contains("code=-3 "));
+    assert!(summary.contains(&format!("peak_pages={} ", peak_pages)));
+    assert!(summary.contains("syscalls=4 "));
+    assert!(summary.contains("user_us=70 "));
+    assert!(summary.contains("kernel_us=35 "));
+    assert!(summary.ends_with("page_faults=2"));
+    // 回收地址空间后峰值清零，汇总要在退出清理步骤之前生成
+    tcb.run_exit_hooks();
+    assert_eq!(tcb.memory_set.peak_mapped_bytes(), 0);
+    info!("exit_summary_test passed!");
+}
+
+#[allow(unused)]
+// 测试命令行参数被压到用户栈上：a0、a1是argc和argv，argv中的指针指向以0结尾的参数，最后是空指针，
+// 栈指针16字节对齐且在argv之下
+pub fn argv_test() {
+    use crate::loader::get_app_data;
+    use crate::mm::{translated_ref, translated_str};
+    let args = ["ch4_args", "hello", "rCore"];
+    // 用 MAX_TASK_NUM 之后的内核栈位置，避免与任务以及 exit_summary_test 冲突
+    let tcb = TaskControlBlock::new(get_app_data(0), MAX_TASK_NUM + 4, Some(&args));
+    let token = tcb.get_user_token();
+    let trap_cx = tcb.get_trap_cx();
+    let (sp, argc, argv) = (trap_cx.x[2], trap_cx.x[10], trap_cx.x[11]);
+    assert_eq!(argc, args.len());
+    assert_eq!(sp % 16, 0);
+    assert!(sp <= argv && argv < tcb.base_size);
+    let word = core::mem::size_of::<usize>();
+    for (i, arg) in args.iter().enumerate() {
+        let ptr = *translated_ref(token, (argv + i * word) as *const usize);
+        assert_eq!(translated_str(token, ptr as *const u8), *arg);
+    }
+    assert_eq!(*translated_ref(token, (argv + args.len() * word) as *const usize), 0);
+    info!("argv_test passed!");
 }
@@ -16,3 +16,16 @@ pub fn get_time_us() -> usize {
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
+
+// 所有任务都在睡眠时调用：把时钟中断定在 deadline_us，用 wfi 停下CPU等到那个时刻，再恢复正常的时钟中断间隔
+// S态中断此时是关着的，但 sie 中打开了时钟中断，中断到来（待处理）时 wfi 就会返回，不会进入trap
+pub fn idle_until_us(deadline_us: usize) {
+    // 截止时间可能来自用户给出的很长的睡眠时间，换算成时钟周期时饱和到最大值而不是溢出
+    set_timer(deadline_us.saturating_mul(CLOCK_FREQ / MICRO_PER_SEC));
+    while get_time_us() < deadline_us {
+        unsafe {
+            core::arch::asm!("wfi");
+        }
+    }
+    set_next_trigger();
+}
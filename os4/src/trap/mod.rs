@@ -14,15 +14,26 @@
 mod context;
 
 use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
+use crate::mm::{frame_remain_num, translated_assign_ptr, PageTable, VirtAddr};
 use crate::syscall::syscall;
 use crate::task::{
-    current_trap_cx, current_user_token, exit_current_and_run_next, suspend_current_and_run_next,
+    account_current_kernel_time, account_current_user_time, check_starvation,
+    commit_page_in_current_memory_set, count_current_page_fault, current_memory_set_contains,
+    current_single_step, current_trap_cx, current_user_token, exit_current_and_run_next,
+    is_executable_page_in_current_memory_set, is_guard_fault_in_current_memory_set,
+    is_guard_page_in_current_memory_set, profile_tick,
+    record_profile_sample, set_current_single_step, set_current_step_breakpoints,
+    suspend_current_and_run_next, take_current_fatal_signal, take_current_step_breakpoints,
 };
 use crate::timer::set_next_trigger;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 use riscv::register::{
     mtvec::TrapMode,
     scause::{self, Exception, Interrupt, Trap},
-    sie, stval, stvec,
+    sie, sstatus, stval, stvec,
 };
 
 core::arch::global_asm!(include_str!("trap.S"));
@@ -46,6 +57,48 @@ fn set_user_trap_entry() {
     }
 }
 
+// 打开 sstatus.SUM 执行 f，结束后恢复原来的状态，返回 f 的结果
+// SUM 打开期间内核可以直接按虚拟地址读写带U标志的页面，风险在于：
+// 1. 用户传来的坏指针不再被硬件拦下，调用方必须先确认地址在用户地址空间中已映射且权限合适；
+// 2. 只有当前 satp 指向的页表里的地址才能访问，本章内核运行在内核地址空间，用户地址并不在内核页表里；
+// 3. f 中发生的 trap 仍然会 panic，也不能在 f 中切换任务，否则 SUM 会泄漏给别的任务。
+pub fn with_sum_enabled<R>(f: impl FnOnce() -> R) -> R {
+    let was_enabled = sstatus::read().sum();
+    unsafe {
+        sstatus::set_sum();
+    }
+    let result = f();
+    // 嵌套调用时只由最外层关闭
+    if !was_enabled {
+        unsafe {
+            sstatus::clear_sum();
+        }
+    }
+    result
+}
+
+// 检查 sstatus.SIE 是否关闭。处理trap和返回用户态的路上都不应该打开S态中断，
+// 否则时钟中断会在内核里重入 trap 处理（内核态的trap入口是 trap_from_kernel，直接panic）
+fn check_interrupts_disabled(site: &str) -> Result<(), String> {
+    if sstatus::read().sie() {
+        Err(format!(
+            "sstatus.SIE is set in {}: S-mode interrupts must stay disabled on the trap path",
+            site
+        ))
+    } else {
+        Ok(())
+    }
+}
+
+// 调试构建下检查中断处于关闭状态，违反时panic
+fn assert_interrupts_disabled(site: &str) {
+    if cfg!(debug_assertions) {
+        if let Err(msg) = check_interrupts_disabled(site) {
+            panic!("{}", msg);
+        }
+    }
+}
+
 pub fn enable_timer_interrupt() {
     unsafe {
         sie::set_stimer();
@@ -56,27 +109,72 @@ pub fn enable_timer_interrupt() {
 pub fn trap_handler() -> ! {
     // 从U到S，设置在内核中发生trap时直接panic
     set_kernel_trap_entry();
+    // 进入trap时硬件已经清除了SIE
+    assert_interrupts_disabled("trap_handler");
+    // 从这里开始到返回用户态之前的时间都算作内核时间
+    account_current_user_time();
     // 获取当前应用的 Trap 上下文的可变引用
     let cx = current_trap_cx();
     let scause = scause::read();
+    // 快速路径：ecall 是最常见的 trap，先单独判断，省去 stval 的读取和其余分支的匹配
+    if let Trap::Exception(Exception::UserEnvCall) = scause.cause() {
+        handle_ecall(cx, current_user_token());
+        trap_return();
+    }
     let stval = stval::read();
     match scause.cause() {
-        Trap::Exception(Exception::UserEnvCall) => {
-            cx.sepc += 4;
-            cx.x[10] = syscall(cx.x[17], [cx.x[10], cx.x[11], cx.x[12]]) as usize;
-        }
         Trap::Exception(Exception::StoreFault)
+        | Trap::Exception(Exception::LoadFault)
         | Trap::Exception(Exception::StorePageFault)
-        | Trap::Exception(Exception::LoadPageFault) => {
-            error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
-            exit_current_and_run_next();
+        | Trap::Exception(Exception::LoadPageFault)
+        | Trap::Exception(Exception::InstructionPageFault) => {
+            // 延迟分配的页面第一次被访问时，映射零页或分配页帧后返回重新执行即可
+            let is_store = scause.cause() == Trap::Exception(Exception::StorePageFault);
+            count_current_page_fault();
+            if !commit_page_in_current_memory_set(stval, is_store) {
+                // 先报告出错的位置，再按原因给出结论；只结束这个任务，内核继续运行其它应用
+                info!(
+                    "[kernel] page fault: addr = {:#x}, pc = {:#x}, {}",
+                    stval,
+                    cx.sepc,
+                    fault_area_desc(current_memory_set_contains(stval))
+                );
+                if is_guard_fault_in_current_memory_set(stval) {
+                    // 用户栈向下越过了栈底，落进了from_elf留下的保护页
+                    error!("[kernel] stack overflow in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+                } else if is_guard_page_in_current_memory_set(stval) {
+                    error!("[kernel] guard page hit in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+                } else if frame_remain_num() == 0 {
+                    // 页面本来可以按需分配，只是页帧用完了，只结束这个任务
+                    error!("[kernel] out of memory in application, bad addr = {:#x}, bad instruction = {:#x}, killed.", stval, cx.sepc);
+                } else if is_store && is_executable_page_in_current_memory_set(stval) {
+                    // 写自己的代码段，多半是自修改代码或JIT的问题，与随机的非法访存区分开
+                    error!("[kernel] write to executable page in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+                } else {
+                    error!("[kernel] PageFault in application, bad addr = {:#x}, bad instruction = {:#x}, core dumped.", stval, cx.sepc);
+                }
+                exit_current_and_run_next(-2);
+            }
+        }
+        Trap::Exception(Exception::Breakpoint) => {
+            // 单步调试插入的断点：恢复原指令，不推进sepc，返回后执行下一条指令
+            if !handle_single_step(cx.sepc) {
+                error!("[kernel] Breakpoint in application, bad instruction = {:#x}, core dumped.", cx.sepc);
+                exit_current_and_run_next(-2);
+            }
         }
         Trap::Exception(Exception::IllegalInstruction) => {
             error!("[kernel] IllegalInstruction in application, core dumped.");
-            exit_current_and_run_next();
+            exit_current_and_run_next(-3);
         }
         Trap::Interrupt(Interrupt::SupervisorTimer) => {
             set_next_trigger();
+            // 每隔若干个时钟中断记录一次被打断的用户pc，用于采样分析
+            if profile_tick() {
+                record_profile_sample(cx.sepc);
+            }
+            // 看门狗：检查有没有就绪任务等得太久
+            check_starvation();
             suspend_current_and_run_next();
         }
         _ => {
@@ -90,10 +188,226 @@ pub fn trap_handler() -> ! {
     trap_return();
 }
 
+// 缺页报告中出错地址与逻辑段的关系
+fn fault_area_desc(in_area: bool) -> &'static str {
+    if in_area {
+        "inside a mapped area"
+    } else {
+        "outside every mapped area"
+    }
+}
+
+// 压缩指令 c.ebreak 的编码
+const C_EBREAK: [u8; 2] = [0x02, 0x90];
+
+// 根据指令最低字节判断指令长度：低两位为11的是32位指令，否则是16位压缩指令
+fn instruction_len(low_byte: u8) -> usize {
+    if low_byte & 0b11 == 0b11 {
+        4
+    } else {
+        2
+    }
+}
+
+// 处理系统调用：先让 sepc 跳过 ecall 指令（按指令实际长度前进），再分发系统调用，返回值放进 a0
+fn handle_ecall(cx: &mut TrapContext, token: usize) {
+    skip_trapped_instruction(cx, token);
+    cx.x[10] = syscall(
+        cx.x[17],
+        [cx.x[10], cx.x[11], cx.x[12], cx.x[13], cx.x[14], cx.x[15]],
+    ) as usize;
+}
+
+// 让 sepc 跳过陷入的那条指令。指令长度由最低字节决定，只查一次页表读这一个字节，不分配内存，
+// 每次系统调用都会走到这里；指令刚被取到过，页面一定已经映射，读不到时按32位指令处理
+fn skip_trapped_instruction(cx: &mut TrapContext, token: usize) {
+    let low_byte = PageTable::from_token(token)
+        .translate_va(VirtAddr::from(cx.sepc))
+        .map_or(0b11, |pa| *pa.get_ref::<u8>());
+    cx.sepc += instruction_len(low_byte);
+}
+
+// 读用户地址空间中的两个字节，页面没有映射时返回None，不会像 translated_byte_buffer 那样panic。
+// 单步时要读的地址由用户的寄存器决定，可能是任意值
+fn user_bytes(token: usize, va: usize) -> Option<[u8; 2]> {
+    let page_table = PageTable::from_token(token);
+    let mut bytes = [0u8; 2];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        let pa = page_table.translate_va(VirtAddr::from(va.checked_add(i)?))?;
+        *byte = *pa.get_ref::<u8>();
+    }
+    Some(bytes)
+}
+// 写入时经过写时复制和共享零页的处理，不会改到与别的地址空间共享的页帧。
+// 页面已经不在了（比如断点还没命中就被munmap）时没有原指令可恢复，直接跳过
+fn write_user_bytes(token: usize, va: usize, bytes: [u8; 2]) {
+    if PageTable::from_token(token).translate_va(VirtAddr::from(va)).is_some() {
+        translated_assign_ptr(token, va as *mut [u8; 2], bytes);
+    }
+}
+
+// 读出pc处的一条指令，16位压缩指令放在低16位
+fn user_instruction(token: usize, pc: usize) -> Option<u32> {
+    let low = u16::from_le_bytes(user_bytes(token, pc)?) as u32;
+    if instruction_len(low as u8) == 2 {
+        return Some(low);
+    }
+    let high = u16::from_le_bytes(user_bytes(token, pc.checked_add(2)?)?) as u32;
+    Some(low | high << 16)
+}
+
+// 把最低bits位当作有符号数做符号扩展
+fn sign_extend(value: u32, bits: u32) -> usize {
+    ((((value as usize) << (64 - bits)) as isize) >> (64 - bits)) as usize
+}
+
+// 执行完pc处的指令inst之后下一条可能执行的指令地址，x是用户的通用寄存器。
+// 跳转的目标由立即数和寄存器算出；条件分支不判断条件，顺序的下一条和分支目标都算上
+fn step_targets(pc: usize, inst: u32, x: &[usize; 32]) -> Vec<usize> {
+    let bits = |hi: u32, lo: u32| (inst >> lo) & ((1 << (hi - lo + 1)) - 1);
+    let reg = |r: u32| if r == 0 { 0 } else { x[r as usize] };
+    let next = pc + instruction_len(inst as u8);
+    if instruction_len(inst as u8) == 4 {
+        match bits(6, 0) {
+            // jal
+            0x6f => {
+                let imm = bits(31, 31) << 20 | bits(19, 12) << 12 | bits(20, 20) << 11 | bits(30, 21) << 1;
+                vec![pc.wrapping_add(sign_extend(imm, 21))]
+            }
+            // jalr
+            0x67 => vec![reg(bits(19, 15)).wrapping_add(sign_extend(bits(31, 20), 12)) & !1],
+            // beq、bne、blt、bge、bltu、bgeu
+            0x63 => {
+                let imm = bits(31, 31) << 12 | bits(7, 7) << 11 | bits(30, 25) << 5 | bits(11, 8) << 1;
+                vec![next, pc.wrapping_add(sign_extend(imm, 13))]
+            }
+            _ => vec![next],
+        }
+    } else {
+        match (bits(1, 0), bits(15, 13)) {
+            // c.j
+            (0b01, 0b101) => {
+                let imm = bits(12, 12) << 11
+                    | bits(8, 8) << 10
+                    | bits(10, 9) << 8
+                    | bits(6, 6) << 7
+                    | bits(7, 7) << 6
+                    | bits(2, 2) << 5
+                    | bits(11, 11) << 4
+                    | bits(5, 3) << 1;
+                vec![pc.wrapping_add(sign_extend(imm, 12))]
+            }
+            // c.beqz、c.bnez
+            (0b01, 0b110) | (0b01, 0b111) => {
+                let imm = bits(12, 12) << 8 | bits(6, 5) << 6 | bits(2, 2) << 5 | bits(11, 10) << 3 | bits(4, 3) << 1;
+                vec![next, pc.wrapping_add(sign_extend(imm, 9))]
+            }
+            // c.jr、c.jalr：rs2为0且rs1不为0，rs1也为0的是 c.ebreak
+            (0b10, 0b100) if bits(6, 2) == 0 && bits(11, 7) != 0 => vec![reg(bits(11, 7)) & !1],
+            _ => vec![next],
+        }
+    }
+}
+
+// 开关当前任务的单步调试，关闭时若已插入断点则恢复原指令
+pub fn set_single_step(enable: bool) {
+    set_current_single_step(enable);
+    if !enable {
+        let token = current_user_token();
+        for (addr, saved) in take_current_step_breakpoints() {
+            write_user_bytes(token, addr, saved);
+        }
+    }
+}
+
+// RISC-V 基础指令集没有硬件单步，这里在即将执行的指令之后可能执行的每个地址插入 c.ebreak，
+// 执行完一条指令就会陷入。跳回自己的指令（如 j .）没法这样停下，只能等它跳出来
+// 读不到指令或跳转目标（比如跳到没有映射的地址）时关闭单步，任务照常运行，访存出错时按缺页处理
+fn arm_single_step() {
+    if !current_single_step() {
+        return;
+    }
+    // 上次插入的断点还没命中（比如中途发生了时钟中断或系统调用），保持不动
+    let pending = take_current_step_breakpoints();
+    if !pending.is_empty() {
+        set_current_step_breakpoints(pending);
+        return;
+    }
+    let token = current_user_token();
+    let cx = current_trap_cx();
+    let sepc = cx.sepc;
+    let inst = match user_instruction(token, sepc) {
+        Some(inst) => inst,
+        None => {
+            warn!("[kernel] single step: cannot read instruction at {:#x}, single step off", sepc);
+            set_single_step(false);
+            return;
+        }
+    };
+    let current = sepc..sepc + instruction_len(inst as u8);
+    let mut breakpoints: Vec<(usize, [u8; 2])> = Vec::new();
+    for target in step_targets(sepc, inst, &cx.x) {
+        if current.contains(&target) || breakpoints.iter().any(|(addr, _)| *addr == target) {
+            continue;
+        }
+        match user_bytes(token, target) {
+            Some(saved) => breakpoints.push((target, saved)),
+            None => {
+                warn!("[kernel] single step: cannot reach {:#x}, single step off", target);
+                set_single_step(false);
+                return;
+            }
+        }
+    }
+    for (addr, _) in breakpoints.iter() {
+        write_user_bytes(token, *addr, C_EBREAK);
+    }
+    set_current_step_breakpoints(breakpoints);
+}
+
+// 处理单步断点，返回是否为单步插入的断点。命中其中一个时恢复全部断点处的原指令
+fn handle_single_step(sepc: usize) -> bool {
+    let breakpoints = take_current_step_breakpoints();
+    if !breakpoints.iter().any(|(addr, _)| *addr == sepc) {
+        set_current_step_breakpoints(breakpoints);
+        return false;
+    }
+    let token = current_user_token();
+    for (addr, saved) in breakpoints {
+        write_user_bytes(token, addr, saved);
+    }
+    info!("[kernel] single step, sepc = {:#x}", sepc);
+    true
+}
+
+// 被信号结束的任务的退出码，与shell的约定一致
+fn signal_exit_code(signum: usize) -> i32 {
+    128 + signum as i32
+}
+
+// 回到用户态之前检查信号：有SIGKILL或没被屏蔽的SIGTERM时结束任务，切换到下一个任务，不再返回
+fn check_fatal_signals() {
+    if let Some(signum) = take_current_fatal_signal() {
+        info!(
+            "[kernel] Application killed by signal {}, exit code {}",
+            signum,
+            signal_exit_code(signum)
+        );
+        exit_current_and_run_next(signal_exit_code(signum));
+    }
+}
+
 #[no_mangle]
 pub fn trap_return() -> ! {
+    // 系统调用（包括发给自己的kill）已经处理完，回到用户态之前处理致命信号
+    check_fatal_signals();
+    account_current_kernel_time();
     // 从S到U，设置stvec寄存器中的trap处理地址为跳板地址
     set_user_trap_entry();
+    // 单步调试模式下在下一条指令处插入断点，下面的 fence.i 保证修改后的指令可见
+    arm_single_step();
+    // sret 会用 SPIE 恢复SIE，在此之前中断必须一直关着
+    assert_interrupts_disabled("trap_return");
 
     // 准备好 __restore 需要两个参数：
     // 分别是 Trap 上下文在应用地址空间中的虚拟地址和要继续执行的应用地址空间的 token 。
@@ -128,3 +442,257 @@ pub fn trap_from_kernel() -> ! {
 }
 
 pub use context::TrapContext;
+
+#[allow(unused)]
+// 测试按指令长度推进sepc：在一个地址空间里依次放一条 c.ebreak 和一条 ecall，
+// 用指向它们的trap上下文走一遍 ecall 快速路径里推进sepc的那一步
+pub fn sepc_advance_test() {
+    use crate::config::PAGE_SIZE;
+    use crate::mm::{MapPermission, MemorySet};
+    const CODE_VA: usize = 0x1000;
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        CODE_VA.into(),
+        (CODE_VA + PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::X | MapPermission::U,
+    );
+    let code = memory_set
+        .translate(VirtAddr::from(CODE_VA).floor())
+        .unwrap()
+        .ppn()
+        .get_bytes_array();
+    // c.ebreak 是16位压缩指令，ecall 的编码为 0x00000073，是32位指令
+    code[..2].copy_from_slice(&C_EBREAK);
+    code[2..6].copy_from_slice(&0x73u32.to_le_bytes());
+    let mut cx = TrapContext::app_init_context(CODE_VA, 0, 0, 0, 0);
+    skip_trapped_instruction(&mut cx, memory_set.token());
+    assert_eq!(cx.sepc, CODE_VA + 2);
+    skip_trapped_instruction(&mut cx, memory_set.token());
+    assert_eq!(cx.sepc, CODE_VA + 6);
+    info!("sepc_advance_test passed!");
+}
+
+#[allow(unused)]
+// 测试单步调试：在当前任务里mmap一页放几条指令，依次单步，每一步断点都插在下一条可能执行的指令处，
+// 条件分支的两个去向都插，命中其中一个后全部恢复；跳转目标读不到时关闭单步而不是panic；
+// 断点还没命中时关闭单步，代码与原来一致
+// 使用当前任务的地址空间和trap上下文，必须在第一个任务运行之前调用
+pub fn single_step_test() {
+    use crate::mm::translated_byte_buffer;
+    use crate::task::{mmap_in_current_memory_set, munmap_in_current_memory_set};
+    const CODE: usize = 0x6000_0000;
+    const C_NOP: [u8; 2] = [0x01, 0x00];
+    const C_JR_RA: [u8; 2] = [0x82, 0x80];
+    const BRANCH: usize = CODE + 2;
+    const TAKEN: usize = CODE + 14;
+    const JUMP_TARGET: usize = CODE + 0x20;
+    assert_eq!(mmap_in_current_memory_set(CODE, 4096, 0b011), 0);
+    let token = current_user_token();
+    let cx = current_trap_cx();
+    let (saved_sepc, saved_x) = (cx.sepc, cx.x);
+    let code_bytes = || -> Vec<u8> {
+        translated_byte_buffer(token, CODE as *const u8, 0x40)
+            .iter()
+            .flat_map(|buffer| buffer.iter().copied())
+            .collect()
+    };
+    // c.nop; beq a0, a1, 12; c.nop * 4; c.jr ra; ...; c.jr ra
+    let mut code = [0u8; 0x40];
+    code[0..2].copy_from_slice(&C_NOP);
+    code[2..6].copy_from_slice(&0x00b5_0663u32.to_le_bytes());
+    for nop in (6..14).step_by(2) {
+        code[nop..nop + 2].copy_from_slice(&C_NOP);
+    }
+    code[14..16].copy_from_slice(&C_JR_RA);
+    code[0x20..0x22].copy_from_slice(&C_JR_RA);
+    for (dst, src) in translated_byte_buffer(token, CODE as *const u8, code.len())
+        .into_iter()
+        .flatten()
+        .zip(code.iter())
+    {
+        *dst = *src;
+    }
+    let original = code_bytes();
+    set_single_step(true);
+    // 顺序执行的指令：断点在下一条，即将执行的这一条不变
+    cx.sepc = CODE;
+    arm_single_step();
+    assert_eq!(user_bytes(token, BRANCH), Some(C_EBREAK));
+    assert_eq!(user_bytes(token, CODE), Some(C_NOP));
+    assert!(handle_single_step(BRANCH));
+    assert_eq!(code_bytes(), original);
+    // 条件分支：顺序的下一条和分支目标都插断点，没命中之前再次 arm 不变
+    cx.sepc = BRANCH;
+    arm_single_step();
+    arm_single_step();
+    assert_eq!(user_bytes(token, BRANCH + 4), Some(C_EBREAK));
+    assert_eq!(user_bytes(token, TAKEN), Some(C_EBREAK));
+    assert!(handle_single_step(TAKEN));
+    assert_eq!(code_bytes(), original);
+    // 寄存器跳转：断点插在目标处，而不是顺序的下一条
+    cx.sepc = TAKEN;
+    cx.x[1] = JUMP_TARGET;
+    arm_single_step();
+    assert_eq!(user_bytes(token, JUMP_TARGET), Some(C_EBREAK));
+    assert_eq!(user_bytes(token, TAKEN + 2), Some([0, 0]));
+    assert!(handle_single_step(JUMP_TARGET));
+    // 跳到没有映射的地址：关闭单步，不插断点
+    cx.sepc = JUMP_TARGET;
+    cx.x[1] = 0;
+    arm_single_step();
+    assert!(!current_single_step());
+    assert!(take_current_step_breakpoints().is_empty());
+    assert_eq!(code_bytes(), original);
+    // 断点还没命中时关闭单步，原指令都恢复
+    set_single_step(true);
+    cx.sepc = BRANCH;
+    arm_single_step();
+    set_single_step(false);
+    assert!(!current_single_step());
+    assert!(take_current_step_breakpoints().is_empty());
+    assert_eq!(code_bytes(), original);
+    // 不是单步插入的断点交给普通的断点处理
+    assert!(!handle_single_step(CODE));
+    cx.sepc = saved_sepc;
+    cx.x = saved_x;
+    assert_eq!(munmap_in_current_memory_set(CODE, 4096), 1);
+    info!("single_step_test passed!");
+}
+
+#[allow(unused)]
+// 测试单步时对下一条指令地址的计算：各种跳转和分支的目标，以及普通指令的顺序下一条
+pub fn step_targets_test() {
+    const PC: usize = 0x1_0000;
+    let mut x = [0usize; 32];
+    x[1] = 0x2001;
+    x[10] = 0x3000;
+    let cases: [(u32, Vec<usize>); 11] = [
+        // addi x0, x0, 0 和 c.nop
+        (0x0000_0013, vec![PC + 4]),
+        (0x0001, vec![PC + 2]),
+        // j 8 和 jal ra, -4
+        (0x0080_006f, vec![PC + 8]),
+        (0xffdf_f0ef, vec![PC - 4]),
+        // jalr x0, 8(ra)：最低位清零
+        (0x0080_8067, vec![0x2008]),
+        // beq a0, a1, 16
+        (0x00b5_0863, vec![PC + 4, PC + 16]),
+        // c.j 8 和 c.beqz a0, 8
+        (0xa021, vec![PC + 8]),
+        (0xc501, vec![PC + 2, PC + 8]),
+        // c.jr ra 和 c.jalr a0
+        (0x8082, vec![0x2000]),
+        (0x9502, vec![0x3000]),
+        // c.ebreak 不是跳转
+        (0x9002, vec![PC + 2]),
+    ];
+    for (inst, expected) in cases.iter() {
+        assert_eq!(&step_targets(PC, *inst, &x), expected, "inst {:#x}", inst);
+    }
+    info!("step_targets_test passed!");
+}
+
+#[cfg(feature = "ecall_bench_test")]
+// 系统调用路径的微基准：用 rdcycle 统计 handle_ecall 处理一次 getpid 的平均周期数，
+// 每次都检查返回值和跳过 ecall 之后的 sepc，平均开销超过上限说明快速路径变慢了
+pub fn ecall_bench_test() {
+    use crate::config::PAGE_SIZE;
+    use crate::mm::{MapPermission, MemorySet};
+    use crate::task::current_pid;
+    const CODE_VA: usize = 0x1000;
+    const SYSCALL_GETPID: usize = 172;
+    const ROUNDS: usize = 1000;
+    // 一次 getpid 只是查页表读一个字节再分发，远用不了这么多周期
+    const MAX_AVG_CYCLES: usize = 20_000;
+    fn rdcycle() -> usize {
+        let cycle: usize;
+        unsafe {
+            core::arch::asm!("rdcycle {}", out(reg) cycle);
+        }
+        cycle
+    }
+    let mut memory_set = MemorySet::new_bare();
+    memory_set.insert_framed_area(
+        CODE_VA.into(),
+        (CODE_VA + PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::X | MapPermission::U,
+    );
+    memory_set
+        .translate(VirtAddr::from(CODE_VA).floor())
+        .unwrap()
+        .ppn()
+        .get_bytes_array()[..4]
+        .copy_from_slice(&0x73u32.to_le_bytes());
+    let token = memory_set.token();
+    let mut cx = TrapContext::app_init_context(CODE_VA, 0, 0, 0, 0);
+    let mut total = 0;
+    for _ in 0..ROUNDS {
+        cx.sepc = CODE_VA;
+        cx.x[17] = SYSCALL_GETPID;
+        let start = rdcycle();
+        handle_ecall(&mut cx, token);
+        total += rdcycle() - start;
+        assert_eq!(cx.sepc, CODE_VA + 4);
+        assert_eq!(cx.x[10], current_pid());
+    }
+    let avg = total / ROUNDS;
+    info!("[kernel] ecall path: {} cycles per getpid on average", avg);
+    assert!(avg < MAX_AVG_CYCLES, "ecall path took {} cycles on average", avg);
+    info!("ecall_bench_test passed!");
+}
+
+#[cfg(feature = "sum_test")]
+// 测试在 SUM 保护下按虚拟地址读取用户页面
+// 内核页表里没有用户地址，这里临时在内核空间映射一个带U标志的页面来代替用户缓冲区
+pub fn sum_test() {
+    use crate::config::PAGE_SIZE;
+    use crate::mm::{MapPermission, VirtAddr, KERNEL_SPACE};
+    const TEST_VA: usize = 0x4000_0000;
+    let mut kernel_space = KERNEL_SPACE.write();
+    kernel_space.insert_framed_area(
+        TEST_VA.into(),
+        (TEST_VA + PAGE_SIZE).into(),
+        MapPermission::R | MapPermission::W | MapPermission::U,
+    );
+    let ppn = kernel_space
+        .translate(VirtAddr::from(TEST_VA).floor())
+        .unwrap()
+        .ppn();
+    drop(kernel_space);
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    // 通过页帧写入数据，再按虚拟地址读出
+    ppn.get_bytes_array()[..4].copy_from_slice(b"sum!");
+    let bytes = with_sum_enabled(|| unsafe { *(TEST_VA as *const [u8; 4]) });
+    assert_eq!(&bytes, b"sum!");
+    assert!(!sstatus::read().sum());
+    assert_eq!(KERNEL_SPACE.write().munmap(TEST_VA, PAGE_SIZE), 1);
+    unsafe {
+        core::arch::asm!("sfence.vma");
+    }
+    info!("sum_test passed!");
+}
+
+#[cfg(feature = "sie_check_test")]
+// 测试中断状态检查：在本该关中断的位置故意打开SIE，确认检查能发现并给出位置
+// 打开SIE之前先屏蔽时钟中断，免得真的在内核里触发中断
+pub fn sie_check_test() {
+    assert!(check_interrupts_disabled("sie_check_test").is_ok());
+    let stimer = sie::read().stimer();
+    unsafe {
+        sie::clear_stimer();
+        sstatus::set_sie();
+    }
+    let result = check_interrupts_disabled("sie_check_test");
+    unsafe {
+        sstatus::clear_sie();
+        if stimer {
+            sie::set_stimer();
+        }
+    }
+    let msg = result.unwrap_err();
+    assert!(msg.contains("sstatus.SIE") && msg.contains("sie_check_test"));
+    assert!(check_interrupts_disabled("sie_check_test").is_ok());
+    info!("sie_check_test passed!");
+}
@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/*
+理想结果：内核按 config::APP_ARGS 传入两个参数，argv[0] 是程序名，输出 Test args OK!
+*/
+
+#[no_mangle]
+fn main(argc: usize, argv: &[&str]) -> i32 {
+    assert_eq!(argc, 3);
+    assert_eq!(argv, ["ch4_args", "hello", "rCore"]);
+    // 参数字符串就在用户栈上，栈指针之上
+    let sp: usize;
+    unsafe {
+        core::arch::asm!("mv {}, sp", out(reg) sp);
+    }
+    assert!(argv.iter().all(|arg| arg.as_ptr() as usize > sp));
+    println!("Test args OK!");
+    0
+}
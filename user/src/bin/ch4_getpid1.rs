@@ -0,0 +1,23 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getpid, yield_};
+
+/*
+理想结果：与 ch4_getpid0 交替运行，各自打印的 pid 不同，且让出 CPU 前后不变。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let pid = getpid();
+    assert!(pid >= 0);
+    for _ in 0..3 {
+        yield_();
+        assert_eq!(pid, getpid());
+    }
+    println!("Test getpid1 OK! pid = {}", pid);
+    0
+}
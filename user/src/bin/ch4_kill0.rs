@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getpid, kill, yield_};
+
+/*
+理想结果：给自己发送不致命的信号成功且任务继续运行，非法的信号编号和不存在的任务返回 -EINVAL，输出 Test kill0 OK!
+*/
+
+const EINVAL: isize = 22;
+const SIGUSR1: usize = 10;
+const SIGNAL_NUM: usize = 64;
+
+#[no_mangle]
+fn main() -> i32 {
+    let pid = getpid() as usize;
+    // 待处理的普通信号不会结束任务，重复发送也一样
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    assert_eq!(kill(pid, SIGUSR1), 0);
+    yield_();
+    assert_eq!(kill(pid, SIGNAL_NUM - 1), 0);
+    assert_eq!(kill(pid, SIGNAL_NUM), -EINVAL);
+    assert_eq!(kill(usize::MAX, SIGUSR1), -EINVAL);
+    println!("Test kill0 OK!");
+    0
+}
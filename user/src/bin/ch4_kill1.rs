@@ -0,0 +1,27 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getpid, kill, yield_};
+
+/*
+理想结果：向 ch4_kill1_victim 发送 SIGKILL，它在下一次返回用户态时被结束，内核输出
+"Application killed by signal 9, exit code 137"，ch4_kill1_victim 不输出 fail，本程序输出 Test kill1 OK!
+*/
+
+const SIGKILL: usize = 9;
+
+#[no_mangle]
+fn main() -> i32 {
+    // 应用按名字排序编号，ch4_kill1_victim 紧跟在本程序之后
+    let victim = getpid() as usize + 1;
+    assert_eq!(kill(victim, SIGKILL), 0);
+    // 让出几次，让目标任务有机会被调度（并在返回用户态时被结束）
+    for _ in 0..10 {
+        yield_();
+    }
+    println!("Test kill1 OK!");
+    0
+}
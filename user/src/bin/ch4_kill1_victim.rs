@@ -0,0 +1,21 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::yield_;
+
+/*
+理想结果：被 ch4_kill1 发送的 SIGKILL 结束，不输出任何内容。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    // 反复让出，给 ch4_kill1 发送信号的机会；每次从系统调用返回用户态之前内核都会检查致命信号
+    for _ in 0..100 {
+        yield_();
+    }
+    println!("Still alive after SIGKILL, Test kill1 fail!");
+    0
+}
@@ -0,0 +1,39 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, mprotect};
+
+/*
+理想结果：只读映射的页面经 mprotect 改为可读写后可以写入，输出 Test mprotect OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096;
+    assert_eq!(0, mmap(start, len, 1));
+    let addr: *mut u8 = start as *mut u8;
+    unsafe {
+        assert_eq!(*addr, 0);
+    }
+    assert_eq!(0, mprotect(start, len, 3));
+    for i in start..(start + len) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            *addr = i as u8;
+        }
+    }
+    for i in start..(start + len) {
+        let addr: *mut u8 = i as *mut u8;
+        unsafe {
+            assert_eq!(*addr, i as u8);
+        }
+    }
+    // 范围内有未映射的页面
+    assert_eq!(-1, mprotect(start, len * 2, 1));
+    println!("Test mprotect OK!");
+    0
+}
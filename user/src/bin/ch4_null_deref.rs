@@ -0,0 +1,20 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/*
+理想结果：读空指针触发缺页，内核打印出错地址 0x0 和出错的 pc，说明地址不在任何逻辑段内，
+只结束这个程序，其余程序照常运行。不输出 fail 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    unsafe {
+        #[allow(clippy::zero_ptr)]
+        (0x0 as *const u8).read_volatile();
+    }
+    println!("Should cause error, Test null deref fail!");
+    0
+}
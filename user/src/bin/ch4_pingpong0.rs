@@ -0,0 +1,34 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, task_info, yield_, TaskInfo};
+
+/*
+理想结果：与 ch4_pingpong1 轮流让出CPU，每次让出后要等对方和其他就绪任务都运行过一次才被调度回来，
+task_info 报告的累计调度延迟大于0，且不超过这段时间的总长，输出 Test ping OK!
+*/
+
+const ROUNDS: usize = 20;
+
+#[no_mangle]
+fn main() -> i32 {
+    let info = TaskInfo::new();
+    assert_eq!(0, task_info(&info));
+    let latency_before = info.total_sched_latency_us;
+    let start = get_time();
+    for _ in 0..ROUNDS {
+        yield_();
+    }
+    let elapsed_ms = (get_time() - start) as usize;
+    assert_eq!(0, task_info(&info));
+    let latency = info.total_sched_latency_us - latency_before;
+    println!("ping: sched latency {}us over {} yields", latency, ROUNDS);
+    assert!(latency > 0);
+    // get_time 只精确到毫秒，多留一毫秒的余量
+    assert!(latency <= (elapsed_ms + 1) * 1000);
+    println!("Test ping OK!");
+    0
+}
@@ -0,0 +1,45 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::sbrk;
+
+/*
+理想结果：堆逐页增长后每一页都可以读写，再逐页缩回起点，最终输出 Test sbrk OK!
+*/
+
+const PAGE_SIZE: usize = 4096;
+const PAGES: usize = 4;
+
+#[no_mangle]
+fn main() -> i32 {
+    let base = sbrk(0);
+    assert!(base > 0);
+    let base = base as usize;
+    for i in 0..PAGES {
+        // 每次返回的是增长前的 break
+        assert_eq!(sbrk(PAGE_SIZE as i32), (base + i * PAGE_SIZE) as isize);
+        let page = (base + i * PAGE_SIZE) as *mut u8;
+        unsafe {
+            page.write_volatile(i as u8 + 1);
+            page.add(PAGE_SIZE - 1).write_volatile(i as u8 + 1);
+        }
+    }
+    for i in 0..PAGES {
+        let page = (base + i * PAGE_SIZE) as *const u8;
+        unsafe {
+            assert_eq!(page.read_volatile(), i as u8 + 1);
+            assert_eq!(page.add(PAGE_SIZE - 1).read_volatile(), i as u8 + 1);
+        }
+    }
+    for i in (0..PAGES).rev() {
+        assert_eq!(sbrk(-(PAGE_SIZE as i32)), (base + (i + 1) * PAGE_SIZE) as isize);
+    }
+    // 不能缩到起点以下
+    assert_eq!(sbrk(-1), -1);
+    assert_eq!(sbrk(0), base as isize);
+    println!("Test sbrk OK!");
+    0
+}
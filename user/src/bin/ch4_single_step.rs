@@ -0,0 +1,35 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::single_step;
+
+/*
+理想结果：打开单步调试后执行的每条指令都让内核输出一行 "[kernel] single step, sepc = ..."，
+顺序执行时相邻两行的 sepc 相差指令长度（4 或 2），跳转后是跳转目标，被 j 跳过的指令不出现；
+关闭后正常运行，输出 Test single_step OK!
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let mut acc: usize;
+    assert_eq!(single_step(true), 0);
+    unsafe {
+        core::arch::asm!(
+            "li {0}, 1",
+            "j 2f",
+            "li {0}, 100",
+            "2:",
+            "addi {0}, {0}, 2",
+            "addi {0}, {0}, 3",
+            "addi {0}, {0}, 4",
+            out(reg) acc,
+        );
+    }
+    assert_eq!(single_step(false), 0);
+    assert_eq!(acc, 10);
+    println!("Test single_step OK!");
+    0
+}
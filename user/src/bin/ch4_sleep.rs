@@ -0,0 +1,22 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{get_time, sleep_blocking};
+
+/*
+理想结果：睡眠 50ms 之后醒来，经过的时间不少于 50ms。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start = get_time();
+    assert!(start > 0);
+    sleep_blocking(50);
+    let elapsed = get_time() - start;
+    assert!(elapsed >= 50, "slept for {}ms", elapsed);
+    println!("Test sleep 50ms OK! elapsed = {}ms", elapsed);
+    0
+}
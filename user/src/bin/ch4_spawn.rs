@@ -0,0 +1,31 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{getpid, getppid, spawn, yield_};
+
+/*
+理想结果：启动时加载的 ch4_spawn 没有父任务，它 spawn 出一个运行同一应用的子任务，子任务的
+getppid 是它的进程号，输出 Test spawn child OK! 和 Test spawn OK!；spawn 不存在的应用返回-1
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let pid = getpid();
+    let ppid = getppid();
+    if ppid != 0 {
+        // spawn 出的子任务，进程号在父任务之后分配
+        assert!(ppid < pid);
+        println!("Test spawn child OK! pid = {}, ppid = {}", pid, ppid);
+        return 0;
+    }
+    assert_eq!(spawn("no_such_app\0"), -1);
+    let child = spawn("ch4_spawn\0");
+    assert!(child > pid);
+    // 子任务排在任务表末尾，让出一次CPU时它先于本任务再次运行，看到的父任务还没有退出
+    yield_();
+    println!("Test spawn OK!");
+    0
+}
@@ -0,0 +1,29 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/*
+理想结果：无限递归把用户栈用完，越过栈底写到下方的保护页，内核报告 stack overflow 并结束程序。
+不输出 fail 就算过。
+*/
+
+// 每层在栈上放一个数组并用 volatile 读写，防止编译器把递归优化掉
+fn recurse(depth: usize) -> usize {
+    let mut frame = [0u8; 256];
+    unsafe {
+        core::ptr::write_volatile(&mut frame[0], depth as u8);
+    }
+    if depth == usize::MAX {
+        return 0;
+    }
+    recurse(depth + 1) + unsafe { core::ptr::read_volatile(&frame[0]) } as usize
+}
+
+#[no_mangle]
+fn main() -> i32 {
+    let sum = recurse(0);
+    println!("Should cause error, Test stack overflow fail! {}", sum);
+    0
+}
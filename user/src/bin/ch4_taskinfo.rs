@@ -0,0 +1,42 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{
+    getpid, task_info, yield_, TaskInfo, TaskStatus, SYSCALL_EXIT, SYSCALL_GETPID,
+    SYSCALL_TASK_INFO, SYSCALL_YIELD,
+};
+
+/*
+理想结果：发出若干次已知的系统调用后，task_info 报告的次数与之一致。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let info = TaskInfo::new();
+    for _ in 0..3 {
+        getpid();
+    }
+    for _ in 0..2 {
+        yield_();
+    }
+    // 本次 task info 调用也计入
+    assert_eq!(0, task_info(&info));
+    assert_eq!(3, info.syscall_times[SYSCALL_GETPID]);
+    assert_eq!(2, info.syscall_times[SYSCALL_YIELD]);
+    assert_eq!(1, info.syscall_times[SYSCALL_TASK_INFO]);
+    assert_eq!(0, info.syscall_times[SYSCALL_EXIT]);
+    assert!(info.status == TaskStatus::Running);
+    let first = info.time;
+
+    getpid();
+    assert_eq!(0, task_info(&info));
+    assert_eq!(4, info.syscall_times[SYSCALL_GETPID]);
+    assert_eq!(2, info.syscall_times[SYSCALL_TASK_INFO]);
+    // 运行时间从第一次被调度开始计，不会倒退
+    assert!(info.time >= first);
+    println!("Test task info counts OK!");
+    0
+}
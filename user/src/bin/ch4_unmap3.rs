@@ -0,0 +1,36 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+use user_lib::{mmap, munmap};
+
+/*
+理想结果：解除映射后再访问触发访存异常，程序被杀死。不输出 fail 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let start: usize = 0x10000000;
+    let len: usize = 4096 * 2;
+    let prot: usize = 3;
+    assert_eq!(0, mmap(start, len, prot));
+    let addr: *mut u8 = start as *mut u8;
+    // 两页都写一遍，内核打开延迟分配时也都有页表项
+    for i in 0..2 {
+        let page = (start + i * 4096) as *mut u8;
+        unsafe {
+            page.write_volatile(0x5a);
+            assert_eq!(page.read_volatile(), 0x5a);
+        }
+    }
+    // 只有一部分映射的范围解除其中已映射的2页，再次解除时没有页面可解除
+    assert_eq!(munmap(start, len * 2), 2);
+    assert_eq!(munmap(start, len), 0);
+    unsafe {
+        addr.read_volatile();
+    }
+    println!("Should cause error, Test 04_7 ummap3 fail!");
+    0
+}
@@ -0,0 +1,19 @@
+#![no_std]
+#![no_main]
+
+#[macro_use]
+extern crate user_lib;
+
+/*
+理想结果：程序写自己的代码段触发访存异常，内核报告 write to executable page 并杀死程序。不输出 fail 就算过。
+*/
+
+#[no_mangle]
+fn main() -> i32 {
+    let addr = main as usize as *mut u8;
+    unsafe {
+        addr.write_volatile(0);
+    }
+    println!("Should cause error, Test write_code fail!");
+    0
+}